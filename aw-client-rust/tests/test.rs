@@ -11,7 +11,6 @@ mod test {
     use chrono::{DateTime, Duration, Utc};
     use serde_json::Map;
     use std::path::PathBuf;
-    use std::sync::Mutex;
     use std::thread;
 
     // A random port, but still not guaranteed to not be bound
@@ -41,10 +40,11 @@ mod test {
         // Start testserver and wait 10s for it to start up
         // TODO: Properly shutdown
         use aw_server::endpoints::ServerState;
-        let state = ServerState {
-            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
-            asset_path: PathBuf::from("."), // webui won't be used, so it's invalidly set
-        };
+        // webui won't be used, so the asset path is invalidly set
+        let state = ServerState::new(
+            aw_datastore::Datastore::new_in_memory(false),
+            PathBuf::from("."),
+        );
         let mut aw_config = aw_server::config::AWConfig::default();
         aw_config.port = PORT;
         let server = aw_server::endpoints::build_rocket(state, aw_config);
@@ -670,8 +670,74 @@ impl DatastoreInstance {
         endtime_opt: Option<DateTime<Utc>>,
         limit_opt: Option<u64>,
     ) -> Result<Vec<Event>, DatastoreError> {
+        let (events, _rows_read) =
+            self.get_events_counted(conn, bucket_id, starttime_opt, endtime_opt, limit_opt)?;
+        Ok(events)
+    }
+
+    /// Same as get_events, but also returns the amount of rows which were read from the database
+    pub fn get_events_counted(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<(Vec<Event>, usize), DatastoreError> {
         let bucket = self.get_bucket(&bucket_id)?;
+        DatastoreInstance::get_events_by_bucketrow(
+            conn,
+            bucket.bid.unwrap(),
+            bucket_id,
+            starttime_opt,
+            endtime_opt,
+            limit_opt,
+        )
+    }
 
+    /// Gets events using only the connection, without the bucket cache of a DatastoreInstance
+    ///
+    /// Allows reading events from connections which are not owned by the datastore worker. Also
+    /// returns the amount of rows which were read from the database.
+    pub fn get_events_uncached(
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<(Vec<Event>, usize), DatastoreError> {
+        let bucketrow: i64 = match conn.query_row(
+            "SELECT id FROM buckets WHERE name = ?1",
+            &[bucket_id],
+            |row| row.get(0),
+        ) {
+            Ok(bucketrow) => bucketrow,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(DatastoreError::NoSuchBucket),
+            Err(err) => {
+                return Err(DatastoreError::InternalError(format!(
+                    "Failed to get bucket row: {}",
+                    err
+                )))
+            }
+        };
+        DatastoreInstance::get_events_by_bucketrow(
+            conn,
+            bucketrow,
+            bucket_id,
+            starttime_opt,
+            endtime_opt,
+            limit_opt,
+        )
+    }
+
+    fn get_events_by_bucketrow(
+        conn: &Connection,
+        bucketrow: i64,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<(Vec<Event>, usize), DatastoreError> {
         let mut list = Vec::new();
 
         let starttime_filter_ns: i64 = match starttime_opt {
@@ -684,7 +750,7 @@ impl DatastoreInstance {
         };
         if starttime_filter_ns > endtime_filter_ns {
             warn!("Starttime in event query was lower than endtime!");
-            return Ok(list);
+            return Ok((list, 0));
         }
         let limit = match limit_opt {
             Some(l) => l as i64,
@@ -712,12 +778,7 @@ impl DatastoreInstance {
         };
 
         let rows = match stmt.query_map(
-            &[
-                &bucket.bid.unwrap(),
-                &starttime_filter_ns,
-                &endtime_filter_ns,
-                &limit,
-            ],
+            &[&bucketrow, &starttime_filter_ns, &endtime_filter_ns, &limit],
             |row| {
                 let id = row.get(0)?;
                 let mut starttime_ns: i64 = row.get(1)?;
@@ -756,14 +817,16 @@ impl DatastoreInstance {
                 )))
             }
         };
+        let mut rows_read = 0;
         for row in rows {
+            rows_read += 1;
             match row {
                 Ok(event) => list.push(event),
                 Err(err) => warn!("Corrupt event in bucket {}: {}", bucket_id, err),
             };
         }
 
-        Ok(list)
+        Ok((list, rows_read))
     }
 
     pub fn get_event_count(
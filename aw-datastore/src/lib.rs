@@ -18,9 +18,11 @@ macro_rules! json_map {
 mod datastore;
 mod legacy_import;
 mod worker;
+mod write_versions;
 
 pub use self::datastore::DatastoreInstance;
 pub use self::worker::Datastore;
+pub use self::write_versions::IntervalVersions;
 
 pub enum DatastoreMethod {
     Memory(),
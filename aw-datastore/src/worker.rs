@@ -1,13 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration as StdDuration;
 
 use chrono::DateTime;
 use chrono::Duration;
 use chrono::Utc;
+use chrono_tz::Tz;
 
 use rusqlite::Connection;
 use rusqlite::DropBehavior;
+use rusqlite::OpenFlags;
 use rusqlite::Transaction;
 use rusqlite::TransactionBehavior;
 
@@ -15,6 +20,9 @@ use aw_models::Bucket;
 use aw_models::Event;
 use aw_models::KeyValue;
 
+use aw_transform::timeslots::split_events_by_day;
+
+use crate::write_versions::{IntervalVersions, WriteVersions};
 use crate::DatastoreError;
 use crate::DatastoreInstance;
 use crate::DatastoreMethod;
@@ -24,9 +32,25 @@ use mpsc_requests::ResponseReceiver;
 type RequestSender = mpsc_requests::RequestSender<Command, Result<Response, DatastoreError>>;
 type RequestReceiver = mpsc_requests::RequestReceiver<Command, Result<Response, DatastoreError>>;
 
+/// How long connections wait for locks held by other connections before failing
+const BUSY_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
+/// How many times committing is attempted before the transaction is rolled back
+const COMMIT_ATTEMPTS: u32 = 3;
+
 #[derive(Clone)]
 pub struct Datastore {
     requester: RequestSender,
+    read_pool: Option<Arc<ReadPool>>,
+    // Whether reads should go through read_pool instead of the worker thread
+    read_directly: bool,
+    // Rows read from the database by get_events through this handle, see rows_read
+    rows_read: Arc<AtomicUsize>,
+    // Buckets whose events were read through this handle, see buckets_read
+    buckets_read: Arc<Mutex<HashSet<String>>>,
+    // Max events the owner of this handle may still read, see event_budget
+    event_budget: Arc<Mutex<Option<u64>>>,
+    write_versions: Arc<Mutex<WriteVersions>>,
 }
 
 impl fmt::Debug for Datastore {
@@ -35,11 +59,58 @@ impl fmt::Debug for Datastore {
     }
 }
 
-/*
- * TODO:
- * - Allow read requests to go straight through a read-only db connection instead of requesting the
- * worker thread for better performance?
- */
+/// Read-only connections to a datastore file which can be used from any thread
+///
+/// Reads made through these connections do not have to wait for the worker thread, but they only
+/// see changes which the worker thread has committed.
+struct ReadPool {
+    path: String,
+    connections: Mutex<Vec<Connection>>,
+}
+
+impl ReadPool {
+    fn new(path: String) -> Self {
+        ReadPool {
+            path,
+            connections: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn take_connection(&self) -> Result<Connection, DatastoreError> {
+        if let Some(conn) = self.connections.lock().unwrap().pop() {
+            return Ok(conn);
+        }
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(&self.path, flags)
+            .and_then(|conn| conn.busy_timeout(BUSY_TIMEOUT).map(|_| conn));
+        match conn {
+            Ok(conn) => Ok(conn),
+            Err(err) => Err(DatastoreError::InternalError(format!(
+                "Failed to open read-only connection to datastore: {}",
+                err
+            ))),
+        }
+    }
+
+    fn get_events(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<(Vec<Event>, usize), DatastoreError> {
+        let conn = self.take_connection()?;
+        let result = DatastoreInstance::get_events_uncached(
+            &conn,
+            bucket_id,
+            starttime_opt,
+            endtime_opt,
+            limit_opt,
+        );
+        self.connections.lock().unwrap().push(conn);
+        result
+    }
+}
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
@@ -49,6 +120,8 @@ pub enum Response {
     BucketMap(HashMap<String, Bucket>),
     Event(Event),
     EventList(Vec<Event>),
+    // Events and the amount of rows which were read to get them
+    EventsRead(Vec<Event>, usize),
     Count(i64),
     KeyValue(KeyValue),
     StringVec(Vec<String>),
@@ -78,6 +151,29 @@ pub enum Command {
     DeleteKeyValue(String),
 }
 
+impl Command {
+    /// Whether the command changes the datastore, the response to such commands is only sent
+    /// once the change has been committed
+    fn is_write(&self) -> bool {
+        match self {
+            Command::CreateBucket(_)
+            | Command::DeleteBucket(_)
+            | Command::InsertEvents(_, _)
+            | Command::Heartbeat(_, _, _)
+            | Command::DeleteEventsById(_, _)
+            | Command::ForceCommit()
+            | Command::InsertKeyValue(_, _)
+            | Command::DeleteKeyValue(_) => true,
+            Command::GetBucket(_)
+            | Command::GetBuckets()
+            | Command::GetEvents(_, _, _, _)
+            | Command::GetEventCount(_, _, _)
+            | Command::GetKeyValue(_)
+            | Command::GetKeysStarting(_) => false,
+        }
+    }
+}
+
 fn _unwrap_response(
     receiver: ResponseReceiver<Result<Response, DatastoreError>>,
 ) -> Result<(), DatastoreError> {
@@ -94,23 +190,22 @@ struct DatastoreWorker {
     responder: RequestReceiver,
     legacy_import: bool,
     quit: bool,
-    uncommited_events: usize,
-    commit: bool,
     last_heartbeat: HashMap<String, Option<Event>>,
+    write_versions: Arc<Mutex<WriteVersions>>,
 }
 
 impl DatastoreWorker {
     pub fn new(
         responder: mpsc_requests::RequestReceiver<Command, Result<Response, DatastoreError>>,
         legacy_import: bool,
+        write_versions: Arc<Mutex<WriteVersions>>,
     ) -> Self {
         DatastoreWorker {
             responder,
             legacy_import,
             quit: false,
-            uncommited_events: 0,
-            commit: false,
             last_heartbeat: HashMap::new(),
+            write_versions,
         }
     }
 
@@ -121,9 +216,30 @@ impl DatastoreWorker {
                 Connection::open_in_memory().expect("Failed to create in-memory datastore")
             }
             DatastoreMethod::File(path) => {
-                Connection::open(path).expect("Failed to create datastore")
+                let conn = Connection::open(path).expect("Failed to create datastore");
+                // In WAL mode readers on other connections do not block commits. The mode is
+                // stored in the database file and SQLite keeps the -wal and -shm files next to
+                // it while the database is open.
+                let journal_mode: String = conn
+                    .pragma_update_and_check(None, "journal_mode", &"WAL", |row| row.get(0))
+                    .expect("Failed to set journal mode of datastore");
+                if journal_mode != "wal" {
+                    warn!(
+                        "Datastore does not support WAL, uses {} instead",
+                        journal_mode
+                    );
+                } else {
+                    // Every write is committed before it is responded to, with NORMAL a commit
+                    // does not wait for the disk and the database can not get corrupted. Only
+                    // the last commits can be lost on power loss.
+                    conn.pragma_update(None, "synchronous", &"NORMAL")
+                        .expect("Failed to set synchronous mode of datastore");
+                }
+                conn
             }
         };
+        conn.busy_timeout(BUSY_TIMEOUT)
+            .expect("Failed to set busy timeout of datastore");
         let mut ds = DatastoreInstance::new(&conn, true).unwrap();
 
         // Ensure legacy import
@@ -139,9 +255,8 @@ impl DatastoreWorker {
                 Ok(_) => (),
                 Err(err) => error!("Failed to do legacy import: {:?}", err),
             }
-            match transaction.commit() {
-                Ok(_) => (),
-                Err(err) => panic!("Failed to commit datastore transaction! {}", err),
+            if let Err(err) = commit(transaction) {
+                error!("Failed to commit legacy import: {}", err);
             }
         }
 
@@ -151,9 +266,8 @@ impl DatastoreWorker {
             let mut transaction = conn
                 .transaction_with_behavior(TransactionBehavior::Immediate)
                 .unwrap();
-            self.uncommited_events = 0;
-            self.commit = false;
             transaction.set_drop_behavior(DropBehavior::Commit);
+            let mut write_response = None;
             loop {
                 let (request, response_sender) = match self.responder.poll() {
                     Ok((req, res_sender)) => (req, res_sender),
@@ -164,21 +278,37 @@ impl DatastoreWorker {
                         break;
                     }
                 };
+                let is_write = request.is_write();
                 let response = self.handle_request(request, &mut ds, &transaction);
+                if is_write {
+                    // Respond after the commit so the changes are visible to other connections
+                    // once the request has returned and so a failed commit is reported
+                    write_response = Some((response_sender, response));
+                    break;
+                }
                 response_sender.respond(response);
                 let now: DateTime<Utc> = Utc::now();
                 let commit_interval_passed: bool = (now - last_commit_time) > Duration::seconds(15);
-                if self.commit || commit_interval_passed || self.uncommited_events > 100 {
+                if commit_interval_passed {
                     break;
                 };
             }
-            debug!(
-                "Commiting DB! Force commit {}, {} uncommited events",
-                self.commit, self.uncommited_events
-            );
-            match transaction.commit() {
-                Ok(_) => (),
-                Err(err) => panic!("Failed to commit datastore transaction! {}", err),
+            debug!("Commiting DB! Write {}", write_response.is_some());
+            let commit_result = commit(transaction);
+            if let Err(err) = &commit_result {
+                error!("Failed to commit datastore transaction: {}", err);
+                // The cached heartbeats can be events which were rolled back
+                self.last_heartbeat.clear();
+            }
+            if let Some((response_sender, response)) = write_response {
+                let response = match commit_result {
+                    Ok(_) => response,
+                    Err(err) => Err(DatastoreError::InternalError(format!(
+                        "Failed to commit datastore transaction: {}",
+                        err
+                    ))),
+                };
+                response_sender.respond(response);
             }
             if self.quit {
                 break;
@@ -187,6 +317,10 @@ impl DatastoreWorker {
         info!("DB Worker thread finished");
     }
 
+    fn write_versions(&self) -> std::sync::MutexGuard<'_, WriteVersions> {
+        self.write_versions.lock().unwrap()
+    }
+
     fn handle_request(
         &mut self,
         request: Command,
@@ -194,17 +328,21 @@ impl DatastoreWorker {
         transaction: &Transaction,
     ) -> Result<Response, DatastoreError> {
         match request {
-            Command::CreateBucket(bucket) => match ds.create_bucket(&transaction, bucket) {
-                Ok(_) => {
-                    self.commit = true;
-                    Ok(Response::Empty())
+            Command::CreateBucket(bucket) => {
+                let bucket_id = bucket.id.clone();
+                match ds.create_bucket(&transaction, bucket) {
+                    Ok(_) => {
+                        // Buckets can be created with events
+                        self.write_versions().create_bucket(&bucket_id);
+                        Ok(Response::Empty())
+                    }
+                    Err(e) => Err(e),
                 }
-                Err(e) => Err(e),
-            },
+            }
             Command::DeleteBucket(bucketname) => {
                 match ds.delete_bucket(&transaction, &bucketname) {
                     Ok(_) => {
-                        self.commit = true;
+                        self.write_versions().delete_bucket(&bucketname);
                         Ok(Response::Empty())
                     }
                     Err(e) => Err(e),
@@ -216,9 +354,21 @@ impl DatastoreWorker {
             },
             Command::GetBuckets() => Ok(Response::BucketMap(ds.get_buckets())),
             Command::InsertEvents(bucketname, events) => {
+                // Events with an id replace existing events whose time is unknown
+                if events.iter().any(|event| event.id.is_some()) {
+                    self.write_versions().touch_bucket(&bucketname);
+                }
                 match ds.insert_events(&transaction, &bucketname, events) {
                     Ok(events) => {
-                        self.uncommited_events += events.len();
+                        let mut write_versions = self.write_versions();
+                        for event in &events {
+                            write_versions.touch(
+                                &bucketname,
+                                event.timestamp,
+                                event.calculate_endtime(),
+                            );
+                        }
+                        drop(write_versions);
                         self.last_heartbeat.insert(bucketname.to_string(), None); // invalidate last_heartbeat cache
                         Ok(Response::EventList(events))
                     }
@@ -234,21 +384,26 @@ impl DatastoreWorker {
                     &mut self.last_heartbeat,
                 ) {
                     Ok(e) => {
-                        self.uncommited_events += 1;
+                        // A merged heartbeat covers the event it was merged into
+                        self.write_versions().touch(
+                            &bucketname,
+                            e.timestamp,
+                            e.calculate_endtime(),
+                        );
                         Ok(Response::Event(e))
                     }
                     Err(e) => Err(e),
                 }
             }
             Command::GetEvents(bucketname, starttime_opt, endtime_opt, limit_opt) => {
-                match ds.get_events(
+                match ds.get_events_counted(
                     &transaction,
                     &bucketname,
                     starttime_opt,
                     endtime_opt,
                     limit_opt,
                 ) {
-                    Ok(el) => Ok(Response::EventList(el)),
+                    Ok((el, rows_read)) => Ok(Response::EventsRead(el, rows_read)),
                     Err(e) => Err(e),
                 }
             }
@@ -260,14 +415,15 @@ impl DatastoreWorker {
             }
             Command::DeleteEventsById(bucketname, event_ids) => {
                 match ds.delete_events_by_id(&transaction, &bucketname, event_ids) {
-                    Ok(()) => Ok(Response::Empty()),
+                    Ok(()) => {
+                        // The time of the deleted events is unknown
+                        self.write_versions().touch_bucket(&bucketname);
+                        Ok(Response::Empty())
+                    }
                     Err(e) => Err(e),
                 }
             }
-            Command::ForceCommit() => {
-                self.commit = true;
-                Ok(Response::Empty())
-            }
+            Command::ForceCommit() => Ok(Response::Empty()),
             Command::InsertKeyValue(key, data) => {
                 match ds.insert_key_value(&transaction, &key, &data) {
                     Ok(()) => Ok(Response::Empty()),
@@ -292,25 +448,143 @@ impl DatastoreWorker {
     }
 }
 
+/// Commits a transaction, retrying if the database is locked by another connection
+///
+/// The transaction is rolled back if it can not be committed.
+fn commit(transaction: Transaction) -> Result<(), rusqlite::Error> {
+    let mut attempt = 1;
+    loop {
+        match transaction.execute_batch("COMMIT") {
+            Ok(_) => return Ok(()),
+            Err(rusqlite::Error::SqliteFailure(err, msg))
+                if err.code == rusqlite::ErrorCode::DatabaseBusy && attempt < COMMIT_ATTEMPTS =>
+            {
+                warn!(
+                    "Datastore is locked, retrying commit (attempt {}): {:?}",
+                    attempt, msg
+                );
+                attempt += 1;
+            }
+            Err(err) => {
+                // Dropping the transaction with the default behavior would try to commit again
+                transaction.rollback()?;
+                return Err(err);
+            }
+        }
+    }
+}
+
 impl Datastore {
     pub fn new(dbpath: String, legacy_import: bool) -> Self {
+        let read_pool = Some(Arc::new(ReadPool::new(dbpath.clone())));
         let method = DatastoreMethod::File(dbpath);
-        Datastore::_new_internal(method, legacy_import)
+        Datastore::_new_internal(method, legacy_import, read_pool)
     }
 
     pub fn new_in_memory(legacy_import: bool) -> Self {
         let method = DatastoreMethod::Memory();
-        Datastore::_new_internal(method, legacy_import)
+        Datastore::_new_internal(method, legacy_import, None)
     }
 
-    fn _new_internal(method: DatastoreMethod, legacy_import: bool) -> Self {
+    fn _new_internal(
+        method: DatastoreMethod,
+        legacy_import: bool,
+        read_pool: Option<Arc<ReadPool>>,
+    ) -> Self {
         let (requester, responder) =
             mpsc_requests::channel::<Command, Result<Response, DatastoreError>>();
+        let write_versions = Arc::new(Mutex::new(WriteVersions::new()));
+        let worker_write_versions = write_versions.clone();
         let _thread = thread::spawn(move || {
-            let mut di = DatastoreWorker::new(responder, legacy_import);
+            let mut di = DatastoreWorker::new(responder, legacy_import, worker_write_versions);
             di.work_loop(method);
         });
-        Datastore { requester }
+        Datastore {
+            requester,
+            read_pool,
+            read_directly: false,
+            rows_read: Arc::new(AtomicUsize::new(0)),
+            buckets_read: Arc::new(Mutex::new(HashSet::new())),
+            event_budget: Arc::new(Mutex::new(None)),
+            write_versions,
+        }
+    }
+
+    /// Returns a handle which reads events through its own read-only connections
+    ///
+    /// Pending writes are committed first so they are visible to the returned handle. Reads from
+    /// multiple threads through the handle can then run in parallel instead of being queued in
+    /// the worker thread. Writes still go through the worker thread. In-memory datastores cannot
+    /// be opened by other connections, so for them the handle behaves like a normal clone.
+    pub fn parallel_reader(&self) -> Result<Datastore, DatastoreError> {
+        let mut reader = self.clone();
+        if self.read_pool.is_some() {
+            self.force_commit()?;
+            reader.read_directly = true;
+        }
+        Ok(reader)
+    }
+
+    /// Returns a clone of the handle which counts the rows it reads separately from this one
+    pub fn with_row_counter(&self) -> Datastore {
+        let mut handle = self.clone();
+        handle.rows_read = Arc::new(AtomicUsize::new(0));
+        handle
+    }
+
+    /// Amount of rows read from the database by get_events through this handle and its clones
+    pub fn rows_read(&self) -> usize {
+        self.rows_read.load(Ordering::Relaxed)
+    }
+
+    /// Returns a clone of the handle whose event budget is separate from this one and unset
+    pub fn with_event_budget(&self) -> Datastore {
+        let mut handle = self.clone();
+        handle.event_budget = Arc::new(Mutex::new(None));
+        handle
+    }
+
+    /// Sets how many more events the owner of this handle and its clones may read, None means
+    /// unlimited
+    ///
+    /// The budget is not enforced by the datastore, readers pass it on as the limit of their
+    /// reads.
+    pub fn set_event_budget(&self, budget: Option<u64>) {
+        *self.event_budget.lock().unwrap() = budget;
+    }
+
+    /// The budget set with set_event_budget
+    pub fn event_budget(&self) -> Option<u64> {
+        *self.event_budget.lock().unwrap()
+    }
+
+    /// Returns a clone of the handle which records the buckets it reads separately from this one
+    pub fn with_read_tracking(&self) -> Datastore {
+        let mut handle = self.clone();
+        handle.buckets_read = Arc::new(Mutex::new(HashSet::new()));
+        handle
+    }
+
+    /// Buckets whose events were read through this handle and its clones
+    pub fn buckets_read(&self) -> HashSet<String> {
+        self.buckets_read.lock().unwrap().clone()
+    }
+
+    fn record_read(&self, bucket_id: &str) {
+        self.buckets_read
+            .lock()
+            .unwrap()
+            .insert(bucket_id.to_string());
+    }
+
+    /// Versions of the events of each bucket between start and end, which change on every write
+    /// to the bucket within them, and the version of the set of buckets
+    ///
+    /// Writes are counted once the worker thread handles them, so results computed from the
+    /// datastore are up to date as long as the versions of the buckets they read have not
+    /// changed since before they were computed.
+    pub fn write_versions(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> IntervalVersions {
+        self.write_versions.lock().unwrap().versions(start, end)
     }
 
     pub fn create_bucket(&self, bucket: &Bucket) -> Result<(), DatastoreError> {
@@ -401,15 +675,49 @@ impl Datastore {
         endtime_opt: Option<DateTime<Utc>>,
         limit_opt: Option<u64>,
     ) -> Result<Vec<Event>, DatastoreError> {
-        let cmd = Command::GetEvents(bucket_id.to_string(), starttime_opt, endtime_opt, limit_opt);
-        let receiver = self.requester.request(cmd).unwrap();
-        match receiver.collect().unwrap() {
-            Ok(r) => match r {
-                Response::EventList(el) => Ok(el),
-                _ => panic!("Invalid response"),
-            },
-            Err(e) => Err(e),
-        }
+        let (events, rows_read) = match &self.read_pool {
+            Some(read_pool) if self.read_directly => {
+                read_pool.get_events(bucket_id, starttime_opt, endtime_opt, limit_opt)?
+            }
+            _ => {
+                let cmd = Command::GetEvents(
+                    bucket_id.to_string(),
+                    starttime_opt,
+                    endtime_opt,
+                    limit_opt,
+                );
+                let receiver = self.requester.request(cmd).unwrap();
+                match receiver.collect().unwrap()? {
+                    Response::EventsRead(el, rows_read) => (el, rows_read),
+                    _ => panic!("Invalid response"),
+                }
+            }
+        };
+        self.rows_read.fetch_add(rows_read, Ordering::Relaxed);
+        self.record_read(bucket_id);
+        Ok(events)
+    }
+
+    /// Same as get_events, but events are also split at the boundaries of local days
+    ///
+    /// Days start at midnight plus offset in the timezone. The events are trimmed to the start
+    /// and end before they are split and the limit applies to the events before they are split.
+    pub fn get_events_split_by_day(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        tz: &Tz,
+        offset: Duration,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let mut events = self.get_events(bucket_id, starttime_opt, endtime_opt, limit_opt)?;
+        // Events are newest first, split them in chronological order to keep the parts in order
+        events.reverse();
+        let mut events =
+            split_events_by_day(events, tz, offset).map_err(DatastoreError::InternalError)?;
+        events.reverse();
+        Ok(events)
     }
 
     pub fn get_event_count(
@@ -418,6 +726,7 @@ impl Datastore {
         starttime_opt: Option<DateTime<Utc>>,
         endtime_opt: Option<DateTime<Utc>>,
     ) -> Result<i64, DatastoreError> {
+        self.record_read(bucket_id);
         let cmd = Command::GetEventCount(bucket_id.to_string(), starttime_opt, endtime_opt);
         let receiver = self.requester.request(cmd).unwrap();
         match receiver.collect().unwrap() {
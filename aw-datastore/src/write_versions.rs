@@ -0,0 +1,156 @@
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// Max amount of days which are tracked per bucket, when exceeded the least recently written
+/// half is dropped
+const MAX_DAYS: usize = 1000;
+
+/// Tracks which UTC days of which buckets writes to the datastore have touched
+///
+/// Every write gets the next value of a counter and the days it touched in a bucket remember the
+/// value of the last write to them. The version of a bucket within a time interval is the value
+/// of the last write which touched any of its days, so it only changes when events of that
+/// bucket within the interval change. Writes where the affected time is unknown, such as
+/// deleting events by id, raise the version of every interval of the bucket. Creating and
+/// deleting buckets changes the version of the set of buckets instead.
+#[derive(Debug, Default)]
+pub struct WriteVersions {
+    counter: u64,
+    buckets_version: u64,
+    buckets: HashMap<String, BucketVersions>,
+}
+
+#[derive(Debug, Default)]
+struct BucketVersions {
+    // Version of all days which are not in days
+    floor: u64,
+    days: BTreeMap<NaiveDate, u64>,
+}
+
+/// Versions of the data within a time interval, see Datastore::write_versions
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalVersions {
+    /// Version of the set of buckets
+    pub buckets: u64,
+    /// Versions of the events of each bucket
+    pub events: HashMap<String, u64>,
+}
+
+impl IntervalVersions {
+    /// Version of the events of a bucket, buckets which have not been written to since the
+    /// datastore was opened have version 0
+    pub fn events_version(&self, bucket_id: &str) -> u64 {
+        self.events.get(bucket_id).cloned().unwrap_or(0)
+    }
+}
+
+impl WriteVersions {
+    pub fn new() -> WriteVersions {
+        WriteVersions::default()
+    }
+
+    /// Versions of the data between start and end
+    pub fn versions(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> IntervalVersions {
+        let start = start.naive_utc().date();
+        let end = end.naive_utc().date();
+        let events = self
+            .buckets
+            .iter()
+            .map(|(bucket_id, bucket)| (bucket_id.to_string(), bucket.version(start, end)))
+            .collect();
+        IntervalVersions {
+            buckets: self.buckets_version,
+            events,
+        }
+    }
+
+    /// Records a write of events of a bucket between start and end
+    pub fn touch(&mut self, bucket_id: &str, start: DateTime<Utc>, end: DateTime<Utc>) {
+        self.counter += 1;
+        let counter = self.counter;
+        self.bucket(bucket_id).touch(counter, start, end);
+    }
+
+    /// Records a write to the events of a bucket where the affected time is unknown
+    pub fn touch_bucket(&mut self, bucket_id: &str) {
+        self.counter += 1;
+        let counter = self.counter;
+        self.bucket(bucket_id).touch_all(counter);
+    }
+
+    /// Records that a bucket was created, which can already have events
+    pub fn create_bucket(&mut self, bucket_id: &str) {
+        self.touch_bucket(bucket_id);
+        self.buckets_version = self.counter;
+    }
+
+    /// Records that a bucket was deleted
+    pub fn delete_bucket(&mut self, bucket_id: &str) {
+        self.counter += 1;
+        self.buckets_version = self.counter;
+        self.buckets.remove(bucket_id);
+    }
+
+    fn bucket(&mut self, bucket_id: &str) -> &mut BucketVersions {
+        self.buckets
+            .entry(bucket_id.to_string())
+            .or_insert_with(BucketVersions::default)
+    }
+}
+
+impl BucketVersions {
+    fn version(&self, start: NaiveDate, end: NaiveDate) -> u64 {
+        if start > end {
+            return self.floor;
+        }
+        self.days
+            .range(start..=end)
+            .map(|(_day, version)| *version)
+            .fold(self.floor, max)
+    }
+
+    fn touch(&mut self, version: u64, start: DateTime<Utc>, end: DateTime<Utc>) {
+        let start_day = start.naive_utc().date();
+        let end_day = end.naive_utc().date();
+        if end_day.signed_duration_since(start_day).num_days() >= MAX_DAYS as i64 {
+            self.touch_all(version);
+            return;
+        }
+        let mut day = start_day;
+        while day <= end_day {
+            self.days.insert(day, version);
+            day = match day.succ_opt() {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        if self.days.len() > MAX_DAYS {
+            self.prune();
+        }
+    }
+
+    fn touch_all(&mut self, version: u64) {
+        self.floor = version;
+        self.days.clear();
+    }
+
+    /// Drops the least recently written half of the days, the floor is raised to the version of
+    /// the dropped days so their intervals still get a new version
+    fn prune(&mut self) {
+        let mut versions: Vec<u64> = self.days.values().cloned().collect();
+        versions.sort_unstable();
+        let cut = versions[versions.len() / 2];
+        let pruned: Vec<NaiveDate> = self
+            .days
+            .iter()
+            .filter(|(_day, version)| **version <= cut)
+            .map(|(day, _version)| *day)
+            .collect();
+        for day in pruned {
+            self.days.remove(&day);
+        }
+        self.floor = max(self.floor, cut);
+    }
+}
@@ -10,10 +10,12 @@ extern crate appdirs;
 #[cfg(test)]
 mod datastore_tests {
     use chrono::Duration;
+    use chrono::TimeZone;
     use chrono::Utc;
     use serde_json::json;
 
     use aw_datastore::Datastore;
+    use aw_datastore::DatastoreError;
 
     use aw_models::Bucket;
     use aw_models::BucketMetadata;
@@ -431,4 +433,287 @@ mod datastore_tests {
             );
         }
     }
+
+    #[test]
+    fn test_wal_reload() {
+        let mut db_path = get_cache_dir().unwrap();
+        db_path.push("datastore-wal-unittest.db");
+        let db_path_str = db_path.to_str().unwrap().to_string();
+
+        if db_path.exists() {
+            std::fs::remove_file(&db_path)
+                .expect("Failed to remove datastore-wal-unittest.db file");
+        }
+
+        let bucket = test_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data: json_map! {"key": json!("value")},
+        };
+        {
+            let ds = Datastore::new(db_path_str.clone(), false);
+            ds.create_bucket(&bucket).unwrap();
+            ds.insert_events(&bucket.id, &[e1.clone()]).unwrap();
+            // Writes are committed before they return
+            let reader = ds.parallel_reader().unwrap();
+            assert_eq!(
+                reader
+                    .get_events(&bucket.id, None, None, None)
+                    .unwrap()
+                    .len(),
+                1
+            );
+        }
+        {
+            // The database is in WAL mode and still opens after a restart
+            let conn = rusqlite::Connection::open(&db_path).unwrap();
+            let journal_mode: String = conn
+                .query_row("PRAGMA journal_mode", rusqlite::NO_PARAMS, |row| row.get(0))
+                .unwrap();
+            assert_eq!(journal_mode, "wal");
+
+            let ds = Datastore::new(db_path_str, false);
+            let events = ds.get_events(&bucket.id, None, None, None).unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].data, e1.data);
+            ds.insert_events(&bucket.id, &[e1]).unwrap();
+            assert_eq!(
+                ds.get_events(&bucket.id, None, None, None).unwrap().len(),
+                2
+            );
+        }
+    }
+
+    #[test]
+    fn test_parallel_reader() {
+        let mut db_path = get_cache_dir().unwrap();
+        db_path.push("datastore-parallel-unittest.db");
+        let db_path_str = db_path.to_str().unwrap().to_string();
+
+        if db_path.exists() {
+            std::fs::remove_file(&db_path)
+                .expect("Failed to remove datastore-parallel-unittest.db file");
+        }
+
+        let ds = Datastore::new(db_path_str, false);
+        let bucket = create_test_bucket(&ds);
+        let e1 = Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data: json_map! {"key": json!("value")},
+        };
+        ds.insert_events(&bucket.id, &[e1.clone()]).unwrap();
+
+        // Uncommitted events should be visible through the reader
+        let reader = ds.parallel_reader().unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = reader.clone();
+                let bucket_id = bucket.id.clone();
+                std::thread::spawn(move || reader.get_events(&bucket_id, None, None, None))
+            })
+            .collect();
+        for thread in threads {
+            let events = thread.join().unwrap().unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].data, e1.data);
+        }
+
+        // Writes through the reader still go through the worker thread
+        reader.insert_events(&bucket.id, &[e1]).unwrap();
+        assert_eq!(
+            ds.get_events(&bucket.id, None, None, None).unwrap().len(),
+            2
+        );
+
+        match reader.get_events("nonexistent", None, None, None) {
+            Err(DatastoreError::NoSuchBucket) => (),
+            res => panic!("Expected NoSuchBucket, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_rows_read() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = create_test_bucket(&ds);
+        let e1 = Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data: json_map! {"key": json!("value")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = e1.timestamp + Duration::seconds(2);
+        ds.insert_events(&bucket.id, &[e1, e2]).unwrap();
+
+        let counted = ds.with_row_counter();
+        counted.get_events(&bucket.id, None, None, None).unwrap();
+        assert_eq!(counted.rows_read(), 2);
+        // The limit stops reading rows
+        counted.get_events(&bucket.id, None, None, Some(1)).unwrap();
+        assert_eq!(counted.rows_read(), 3);
+        // Clones share the counter, with_row_counter starts a new one
+        let clone = counted.clone();
+        clone.get_events(&bucket.id, None, None, None).unwrap();
+        assert_eq!(counted.rows_read(), 5);
+        assert_eq!(ds.rows_read(), 0);
+    }
+
+    #[test]
+    fn test_event_budget() {
+        let ds = Datastore::new_in_memory(false);
+        assert_eq!(ds.event_budget(), None);
+        let budgeted = ds.with_event_budget();
+        budgeted.set_event_budget(Some(10));
+        // Clones share the budget, with_event_budget starts a new one
+        let clone = budgeted.clone();
+        clone.set_event_budget(Some(5));
+        assert_eq!(budgeted.event_budget(), Some(5));
+        assert_eq!(budgeted.with_event_budget().event_budget(), None);
+        assert_eq!(ds.event_budget(), None);
+    }
+
+    #[test]
+    fn test_write_versions() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = create_test_bucket(&ds);
+        let day1 = Utc.ymd(2000, 1, 1).and_hms(0, 0, 0);
+        let day2 = day1 + Duration::days(1);
+        let e1 = Event {
+            id: None,
+            timestamp: day1,
+            duration: Duration::seconds(1),
+            data: json_map! {"key": json!("value")},
+        };
+        let version = |day| ds.write_versions(day, day).events_version(&bucket.id);
+        let version1 = version(day1);
+        let version2 = version(day2);
+
+        // Writes only change the version of the days they touch
+        let mut e2 = e1.clone();
+        e2.timestamp = day2;
+        let e2 = ds.insert_events(&bucket.id, &[e2]).unwrap().remove(0);
+        assert_eq!(version(day1), version1);
+        assert!(version(day2) > version2);
+        let version2 = version(day2);
+        ds.heartbeat(&bucket.id, e1.clone(), 0.0).unwrap();
+        assert!(version(day1) > version1);
+        assert_eq!(version(day2), version2);
+
+        // Deleting events changes all versions of the bucket as their time is unknown
+        let version1 = version(day1);
+        ds.delete_events_by_id(&bucket.id, vec![e2.id.unwrap()])
+            .unwrap();
+        assert!(version(day1) > version1);
+        assert!(version(day2) > version2);
+
+        // Versions never decrease when old days are dropped
+        let version1 = version(day1);
+        let version2 = version(day2);
+        let events: Vec<Event> = (1..1500)
+            .map(|i| {
+                let mut e = e1.clone();
+                e.timestamp = day1 + Duration::days(i);
+                e
+            })
+            .collect();
+        ds.insert_events(&bucket.id, &events).unwrap();
+        assert!(version(day1) >= version1);
+        assert!(version(day2) > version2);
+
+        // Writes to other buckets do not change the versions of the bucket, but creating and
+        // deleting buckets changes the version of the set of buckets
+        let versions = ds.write_versions(day1, day2);
+        let mut other = bucket.clone();
+        other.id = "other".to_string();
+        ds.create_bucket(&other).unwrap();
+        ds.insert_events(&other.id, &[e1]).unwrap();
+        let new_versions = ds.write_versions(day1, day2);
+        assert_eq!(
+            new_versions.events_version(&bucket.id),
+            versions.events_version(&bucket.id)
+        );
+        assert!(new_versions.events_version(&other.id) > 0);
+        assert!(new_versions.buckets > versions.buckets);
+        ds.delete_bucket(&other.id).unwrap();
+        assert!(ds.write_versions(day1, day2).buckets > new_versions.buckets);
+    }
+
+    #[test]
+    fn test_buckets_read() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = create_test_bucket(&ds);
+        let tracked = ds.with_read_tracking();
+        assert!(tracked.buckets_read().is_empty());
+        tracked.get_events(&bucket.id, None, None, None).unwrap();
+        // Clones share the buckets read, other handles do not
+        let clone = tracked.clone();
+        clone.get_event_count(&bucket.id, None, None).unwrap();
+        assert_eq!(tracked.buckets_read().len(), 1);
+        assert!(tracked.buckets_read().contains(&bucket.id));
+        assert!(tracked.with_read_tracking().buckets_read().is_empty());
+    }
+
+    #[test]
+    fn test_write_during_read() {
+        let mut db_path = get_cache_dir().unwrap();
+        db_path.push("datastore-write-during-read-unittest.db");
+        let db_path_str = db_path.to_str().unwrap().to_string();
+
+        if db_path.exists() {
+            std::fs::remove_file(&db_path)
+                .expect("Failed to remove datastore-write-during-read-unittest.db file");
+        }
+
+        let ds = Datastore::new(db_path_str, false);
+        let bucket = create_test_bucket(&ds);
+        let e1 = Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data: json_map! {"key": json!("value")},
+        };
+        ds.insert_events(&bucket.id, &[e1.clone()]).unwrap();
+        ds.force_commit().unwrap();
+
+        // Keep a read transaction open on another connection while the worker commits
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        conn.execute_batch("BEGIN").unwrap();
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM events", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+
+        ds.insert_events(&bucket.id, &[e1.clone()]).unwrap();
+        ds.force_commit().unwrap();
+        let reader = ds.parallel_reader().unwrap();
+        assert_eq!(
+            reader
+                .get_events(&bucket.id, None, None, None)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // The open read still sees the data from when it started
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM events", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+        conn.execute_batch("COMMIT").unwrap();
+
+        // The worker thread is still alive
+        ds.insert_events(&bucket.id, &[e1]).unwrap();
+        assert_eq!(
+            ds.get_events(&bucket.id, None, None, None).unwrap().len(),
+            3
+        );
+    }
 }
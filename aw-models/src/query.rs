@@ -1,3 +1,5 @@
+use serde_json::{Map, Value};
+
 use crate::TimeInterval;
 
 #[derive(Deserialize, Clone, Debug)]
@@ -5,4 +7,12 @@ pub struct Query {
     //#[serde(with = "DurationSerialization")]
     pub timeperiods: Vec<TimeInterval>,
     pub query: Vec<String>,
+    /// If set, a trace of all function calls is returned together with the results, which
+    /// changes the response of /api/0/query from a list to `{"result": [...], "profile": [...]}`
+    #[serde(default)]
+    pub profile: bool,
+    /// Variables which are defined before the query is run, so values from users do not have to
+    /// be inserted into the query code
+    #[serde(default)]
+    pub params: Map<String, Value>,
 }
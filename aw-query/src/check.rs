@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::functions;
+use crate::functions::Type;
+use crate::lexer;
+use crate::parser;
+use crate::DataType;
+use crate::QueryError;
+
+/// A problem found while statically checking a query
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(line: usize, err: QueryError) -> Diagnostic {
+        Diagnostic {
+            line,
+            message: format!("{}", err),
+        }
+    }
+}
+
+/// Checks a query for errors without executing it
+///
+/// Reports undefined variables, calls to unknown functions, wrong amount of arguments to builtin
+/// functions and arguments which obviously have the wrong type. Since the datastore is never
+/// touched errors such as missing buckets are not detected.
+pub fn check(code: &str) -> Vec<Diagnostic> {
+    check_with_params(code, &HashMap::new())
+}
+
+/// Checks a query which will be run with the given params defined
+pub fn check_with_params(code: &str, params: &HashMap<String, DataType>) -> Vec<Diagnostic> {
+    let lexer = lexer::Lexer::new(code);
+    let program = match parser::parse(lexer) {
+        Ok(p) => p,
+        Err((token, msg)) => {
+            let line = match token {
+                Some((_, span)) => span.line,
+                None => code.lines().count(),
+            };
+            let err = QueryError::ParsingError(format!("{:?}", (token, msg)));
+            return vec![Diagnostic::new(line, err)];
+        }
+    };
+    let mut checker = Checker::new(params);
+    for expr in &program.stmts {
+        checker.check_expr(expr);
+    }
+    if program.stmts.is_empty() {
+        checker.report(0, QueryError::EmptyQuery());
+    }
+    checker.diagnostics
+}
+
+struct Checker {
+    vars: HashMap<String, Type>,
+    diagnostics: Vec<Diagnostic>,
+    // Number of if blocks we are currently inside of
+    conditional_depth: usize,
+}
+
+impl Checker {
+    fn new(params: &HashMap<String, DataType>) -> Checker {
+        let mut env = HashMap::new();
+        functions::fill_env(&mut env);
+        let mut vars = HashMap::new();
+        for (name, val) in env.iter() {
+            vars.insert(name.to_string(), type_of(val));
+        }
+        vars.insert("TIMEINTERVAL".to_string(), Type::String);
+        for (name, val) in params {
+            vars.insert(name.to_string(), type_of(val));
+        }
+        Checker {
+            vars,
+            diagnostics: Vec::new(),
+            conditional_depth: 0,
+        }
+    }
+
+    fn report(&mut self, line: usize, err: QueryError) {
+        self.diagnostics.push(Diagnostic::new(line, err));
+    }
+
+    fn assign(&mut self, var: &str, t: Type) {
+        // Variables assigned inside of an if block might not be assigned at all, so if the types
+        // differ between branches we can no longer know what type it has
+        let t = match self.vars.get(var) {
+            Some(prev) if self.conditional_depth > 0 && *prev != t => Type::Any,
+            _ => t,
+        };
+        self.vars.insert(var.to_string(), t);
+    }
+
+    fn check_number_op(&mut self, expr: &Expr, a: &Expr, b: &Expr, op: &str) -> Type {
+        for operand in &[a, b] {
+            let t = self.check_expr(operand);
+            if !compatible(Type::Number, t) {
+                self.report(
+                    expr.span.line,
+                    QueryError::InvalidType(format!(
+                        "Cannot {} something that is not a number!",
+                        op
+                    )),
+                );
+            }
+        }
+        Type::Number
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Type {
+        use crate::ast::Expr_::*;
+        match expr.node {
+            Add(ref a, ref b) => {
+                let a_type = self.check_expr(a);
+                let b_type = self.check_expr(b);
+                match (a_type, b_type) {
+                    (Type::Any, t) | (t, Type::Any) => t,
+                    (Type::Number, Type::Number) => Type::Number,
+                    (Type::List, Type::List) => Type::List,
+                    (Type::String, Type::String) => Type::String,
+                    (Type::DateTime, Type::Number) => Type::DateTime,
+                    (a_type, b_type) => {
+                        self.report(
+                            expr.span.line,
+                            QueryError::InvalidType(format!(
+                                "Cannot use + on {:?} and {:?}!",
+                                a_type, b_type
+                            )),
+                        );
+                        Type::Any
+                    }
+                }
+            }
+            Sub(ref a, ref b) => {
+                let a_type = self.check_expr(a);
+                let b_type = self.check_expr(b);
+                match (a_type, b_type) {
+                    (Type::DateTime, Type::Number) => Type::DateTime,
+                    (Type::DateTime, Type::DateTime) => Type::Number,
+                    (Type::DateTime, Type::Any) | (Type::Any, Type::DateTime) => Type::Any,
+                    (a_type, b_type) => {
+                        for t in &[a_type, b_type] {
+                            if !compatible(Type::Number, *t) {
+                                self.report(
+                                    expr.span.line,
+                                    QueryError::InvalidType(
+                                        "Cannot sub something that is not a number!".to_string(),
+                                    ),
+                                );
+                            }
+                        }
+                        Type::Number
+                    }
+                }
+            }
+            Mul(ref a, ref b) => self.check_number_op(expr, a, b, "multiply"),
+            Div(ref a, ref b) => {
+                if let Number(n) = b.node {
+                    if n == 0.0 {
+                        self.report(
+                            expr.span.line,
+                            QueryError::MathError("Tried to divide by zero!".to_string()),
+                        );
+                    }
+                }
+                self.check_number_op(expr, a, b, "divide")
+            }
+            Mod(ref a, ref b) => self.check_number_op(expr, a, b, "mod"),
+            Equal(ref lhs, ref rhs) => {
+                let lhs_type = self.check_expr(lhs);
+                let rhs_type = self.check_expr(rhs);
+                if !compatible(lhs_type, rhs_type) {
+                    self.report(
+                        expr.span.line,
+                        QueryError::InvalidType(format!(
+                            "Cannot compare values of different types {:?} and {:?}",
+                            lhs_type, rhs_type
+                        )),
+                    );
+                }
+                Type::Bool
+            }
+            Assign(ref var, ref b) => {
+                let t = self.check_expr(b);
+                self.assign(var, t);
+                t
+            }
+            Var(ref var) => match self.vars.get(var) {
+                Some(t) => *t,
+                None => {
+                    self.report(
+                        expr.span.line,
+                        QueryError::VariableNotDefined(var.to_string()),
+                    );
+                    Type::Any
+                }
+            },
+            Bool(_) => Type::Bool,
+            Number(_) => Type::Number,
+            String(_) => Type::String,
+            Return(ref e) => self.check_expr(e),
+            If(ref ifs) => {
+                self.conditional_depth += 1;
+                for (cond, block) in ifs {
+                    let t = self.check_expr(cond);
+                    if !compatible(Type::Bool, t) {
+                        self.report(
+                            cond.span.line,
+                            QueryError::InvalidType(format!(
+                                "Condition of if statement is of type {:?}, expected Bool",
+                                t
+                            )),
+                        );
+                    }
+                    for e in block {
+                        self.check_expr(e);
+                    }
+                }
+                self.conditional_depth -= 1;
+                Type::None
+            }
+            Function(ref fname, ref e) => {
+                let args = match e.node {
+                    List(ref l) => l,
+                    _ => unreachable!(),
+                };
+                let arg_types: Vec<Type> = args.iter().map(|arg| self.check_expr(arg)).collect();
+                self.check_call(expr, fname, &arg_types)
+            }
+            List(ref list) => {
+                for entry in list {
+                    self.check_expr(entry);
+                }
+                Type::List
+            }
+            Dict(ref d) => {
+                for val in d.values() {
+                    self.check_expr(val);
+                }
+                Type::Dict
+            }
+        }
+    }
+
+    fn check_call(&mut self, expr: &Expr, fname: &str, arg_types: &[Type]) -> Type {
+        let line = expr.span.line;
+        match self.vars.get(fname) {
+            None => {
+                self.report(line, QueryError::VariableNotDefined(fname.to_string()));
+                return Type::Any;
+            }
+            Some(Type::Function) => (),
+            Some(Type::Any) => return Type::Any,
+            Some(_) => {
+                self.report(line, QueryError::InvalidType(fname.to_string()));
+                return Type::Any;
+            }
+        };
+        // Functions which have been assigned to other variables are not checked
+        let sig = match functions::signature(fname) {
+            Some(sig) => sig,
+            None => return Type::Any,
+        };
+        let max_args = sig.params.len();
+        if arg_types.len() < sig.required || (!sig.variadic && arg_types.len() > max_args) {
+            let expected = if sig.variadic {
+                format!("at least {}", sig.required)
+            } else if sig.required == max_args {
+                format!("{}", max_args)
+            } else {
+                format!("{} to {}", sig.required, max_args)
+            };
+            self.report(
+                line,
+                QueryError::InvalidFunctionParameters(format!(
+                    "Expected {} parameters in function {}, got {}",
+                    expected,
+                    fname,
+                    arg_types.len()
+                )),
+            );
+        }
+        for (i, arg_type) in arg_types.iter().enumerate() {
+            let param_type = match sig.params.get(i) {
+                Some(t) => *t,
+                None if sig.variadic => *sig.params.last().unwrap(),
+                None => break,
+            };
+            if !compatible(param_type, *arg_type) {
+                self.report(
+                    line,
+                    QueryError::InvalidFunctionParameters(format!(
+                        "Expected parameter {} of function {} to be of type {:?}, got {:?}",
+                        i + 1,
+                        fname,
+                        param_type,
+                        arg_type
+                    )),
+                );
+            }
+        }
+        sig.ret
+    }
+}
+
+fn type_of(val: &DataType) -> Type {
+    match val {
+        DataType::None() => Type::None,
+        DataType::Bool(_) => Type::Bool,
+        DataType::Number(_) => Type::Number,
+        DataType::String(_) => Type::String,
+        DataType::DateTime(_) => Type::DateTime,
+        DataType::Event(_) => Type::Any,
+        DataType::List(_) => Type::List,
+        DataType::Dict(_) => Type::Dict,
+        DataType::Function(_, _) => Type::Function,
+    }
+}
+
+fn compatible(expected: Type, actual: Type) -> bool {
+    expected == Type::Any || actual == Type::Any || expected == actual
+}
@@ -2,10 +2,12 @@ use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 
+use chrono::{DateTime, Utc};
+
 use super::functions;
 use super::QueryError;
 use aw_models::Event;
-use aw_transform::classify::{RegexRule, Rule};
+use aw_transform::classify::{GlobRule, RegexRule, Rule};
 
 use serde::Serializer;
 use serde_json::value::Value;
@@ -20,6 +22,7 @@ pub enum DataType {
     Bool(bool),
     Number(f64),
     String(String),
+    DateTime(DateTime<Utc>),
     Event(Event),
     List(Vec<DataType>),
     Dict(HashMap<String, DataType>),
@@ -50,6 +53,7 @@ impl fmt::Debug for DataType {
             DataType::Bool(b) => write!(f, "Bool({})", b),
             DataType::Number(n) => write!(f, "Number({})", n),
             DataType::String(s) => write!(f, "String({})", s),
+            DataType::DateTime(dt) => write!(f, "DateTime({})", dt.to_rfc3339()),
             DataType::Event(e) => write!(f, "Event({:?})", e),
             DataType::List(l) => write!(f, "List({:?})", l),
             DataType::Dict(d) => write!(f, "Dict({:?})", d),
@@ -68,6 +72,7 @@ impl DataType {
             (DataType::Bool(b1), DataType::Bool(b2)) => Ok(b1 == b2),
             (DataType::Number(n1), DataType::Number(n2)) => Ok(n1 == n2),
             (DataType::String(s1), DataType::String(s2)) => Ok(s1 == s2),
+            (DataType::DateTime(dt1), DataType::DateTime(dt2)) => Ok(dt1 == dt2),
             (DataType::Event(e1), DataType::Event(e2)) => Ok(e1 == e2),
             (DataType::List(l1), DataType::List(l2)) => Ok(l1 == l2),
             (DataType::Dict(d1), DataType::Dict(d2)) => Ok(d1 == d2),
@@ -89,6 +94,7 @@ impl PartialEq for DataType {
             (DataType::Bool(b1), DataType::Bool(b2)) => b1 == b2,
             (DataType::Number(n1), DataType::Number(n2)) => n1 == n2,
             (DataType::String(s1), DataType::String(s2)) => s1 == s2,
+            (DataType::DateTime(dt1), DataType::DateTime(dt2)) => dt1 == dt2,
             (DataType::Event(e1), DataType::Event(e2)) => e1 == e2,
             (DataType::List(l1), DataType::List(l2)) => l1 == l2,
             (DataType::Dict(d1), DataType::Dict(d2)) => d1 == d2,
@@ -189,11 +195,12 @@ impl TryFrom<&DataType> for Vec<(String, Rule)> {
     }
 }
 
-impl TryFrom<&DataType> for Vec<(Vec<String>, Rule)> {
+/// Category rules with an optional score as the third element
+impl TryFrom<&DataType> for Vec<(Vec<String>, Rule, Option<f64>)> {
     type Error = QueryError;
     fn try_from(value: &DataType) -> Result<Self, Self::Error> {
         let mut tagged_lists: Vec<DataType> = value.try_into()?;
-        let mut lists: Vec<(Vec<String>, Rule)> = Vec::new();
+        let mut lists: Vec<(Vec<String>, Rule, Option<f64>)> = Vec::new();
         for list in tagged_lists.drain(..) {
             match list {
                 DataType::List(ref l) => {
@@ -207,7 +214,17 @@ impl TryFrom<&DataType> for Vec<(Vec<String>, Rule)> {
                         None => return Err(QueryError::InvalidFunctionParameters(
                             format!("Expected function parameter of type list of (category, rule) tuples, list contains {:?}", l)))
                     };
-                    lists.push((category, rule));
+                    let score: Option<f64> = match l.get(2) {
+                        Some(DataType::Number(score)) => Some(*score),
+                        Some(score) => {
+                            return Err(QueryError::InvalidFunctionParameters(format!(
+                                "Expected the score of a category rule to be a number, got {:?}",
+                                score
+                            )))
+                        }
+                        None => None,
+                    };
+                    lists.push((category, rule, score));
                 }
                 ref invalid_type => {
                     return Err(QueryError::InvalidFunctionParameters(format!(
@@ -221,6 +238,27 @@ impl TryFrom<&DataType> for Vec<(Vec<String>, Rule)> {
     }
 }
 
+/// Datetimes can also be given as RFC3339 strings
+impl TryFrom<&DataType> for DateTime<Utc> {
+    type Error = QueryError;
+    fn try_from(value: &DataType) -> Result<Self, Self::Error> {
+        match value {
+            DataType::DateTime(dt) => Ok(*dt),
+            DataType::String(s) => match DateTime::parse_from_rfc3339(s) {
+                Ok(dt) => Ok(dt.with_timezone(&Utc)),
+                Err(e) => Err(QueryError::InvalidFunctionParameters(format!(
+                    "Failed to parse datetime '{}': {}",
+                    s, e
+                ))),
+            },
+            ref invalid_type => Err(QueryError::InvalidFunctionParameters(format!(
+                "Expected function parameter of type DateTime, got {:?}",
+                invalid_type
+            ))),
+        }
+    }
+}
+
 impl TryFrom<&DataType> for f64 {
     type Error = QueryError;
     fn try_from(value: &DataType) -> Result<Self, Self::Error> {
@@ -250,6 +288,7 @@ impl TryFrom<&DataType> for Value {
             DataType::Bool(b) => Ok(Value::Bool(*b)),
             DataType::Number(n) => Ok(Value::Number(Number::from_f64(*n).unwrap())),
             DataType::String(s) => Ok(Value::String(s.to_string())),
+            DataType::DateTime(dt) => Ok(Value::String(dt.to_rfc3339())),
             DataType::List(_l) => {
                 let mut tagged_values: Vec<DataType> = value.try_into()?;
                 let mut values: Vec<Value> = Vec::new();
@@ -266,6 +305,24 @@ impl TryFrom<&DataType> for Value {
     }
 }
 
+impl From<&Value> for DataType {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => DataType::None(),
+            Value::Bool(b) => DataType::Bool(*b),
+            // serde_json numbers are always representable as a f64
+            Value::Number(n) => DataType::Number(n.as_f64().unwrap()),
+            Value::String(s) => DataType::String(s.to_string()),
+            Value::Array(a) => DataType::List(a.iter().map(DataType::from).collect()),
+            Value::Object(o) => DataType::Dict(
+                o.iter()
+                    .map(|(k, v)| (k.to_string(), DataType::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
 impl TryFrom<&DataType> for Vec<Value> {
     type Error = QueryError;
     fn try_from(value: &DataType) -> Result<Self, Self::Error> {
@@ -307,52 +364,128 @@ impl TryFrom<&DataType> for Rule {
                 ))
             }
         };
-        if rtype == "none" {
-            Ok(Self::None)
-        } else if rtype == "regex" {
-            let regex_val = match obj.get("regex") {
-                Some(regex_val) => regex_val,
-                None => {
-                    return Err(QueryError::InvalidFunctionParameters(
-                        "regex rule is missing the 'regex' field".to_string(),
-                    ))
+        let rule = match rtype.as_str() {
+            "none" => Self::None,
+            "regex" => {
+                let regex_str = rule_string(obj, rtype, "regex")?;
+                let ignore_case = rule_ignore_case(obj, rtype)?;
+                let regex_rule = match RegexRule::new(regex_str, ignore_case) {
+                    Ok(regex_rule) => regex_rule,
+                    Err(err) => {
+                        return Err(QueryError::RegexCompileError(format!(
+                            "Failed to compile regex string '{}': '{:?}",
+                            regex_str, err
+                        )))
+                    }
+                };
+                match rule_select_keys(obj, rtype)? {
+                    Some(keys) => Self::Regex(regex_rule.select_keys(keys)),
+                    None => Self::Regex(regex_rule),
                 }
-            };
-            let regex_str = match regex_val {
-                DataType::String(s) => s,
-                _ => {
-                    return Err(QueryError::InvalidFunctionParameters(
-                        "the regex field of the regex rule is not a string".to_string(),
-                    ))
+            }
+            "glob" => {
+                let pattern = rule_string(obj, rtype, "glob")?;
+                let ignore_case = rule_ignore_case(obj, rtype)?;
+                let glob_rule = match GlobRule::new(pattern, ignore_case) {
+                    Ok(glob_rule) => glob_rule,
+                    Err(err) => {
+                        return Err(QueryError::InvalidFunctionParameters(format!(
+                            "Invalid glob pattern '{}': '{:?}",
+                            pattern, err
+                        )))
+                    }
+                };
+                match rule_select_keys(obj, rtype)? {
+                    Some(keys) => Self::Glob(glob_rule.select_keys(keys)),
+                    None => Self::Glob(glob_rule),
                 }
-            };
-            let ignore_case_val = match obj.get("ignore_case") {
-                Some(case_val) => case_val,
-                None => &DataType::Bool(false),
-            };
-            let ignore_case = match ignore_case_val {
-                DataType::Bool(b) => b,
-                _ => {
+            }
+            "and" | "or" => {
+                let rules: Vec<Rule> = match obj.get("rules") {
+                    Some(DataType::List(rules)) => {
+                        let mut parsed_rules = Vec::new();
+                        for rule in rules {
+                            parsed_rules.push(rule.try_into()?);
+                        }
+                        parsed_rules
+                    }
+                    _ => {
+                        return Err(QueryError::InvalidFunctionParameters(format!(
+                            "the rules field of the {} rule is not a list",
+                            rtype
+                        )))
+                    }
+                };
+                if rtype == "and" {
+                    Self::And(rules)
+                } else {
+                    Self::Or(rules)
+                }
+            }
+            "not" => match obj.get("rule") {
+                Some(rule) => Self::Not(Box::new(rule.try_into()?)),
+                None => {
                     return Err(QueryError::InvalidFunctionParameters(
-                        "the ignore_case field of the regex rule is not a bool".to_string(),
+                        "not rule is missing the 'rule' field".to_string(),
                     ))
                 }
-            };
-            let regex_rule = match RegexRule::new(regex_str, *ignore_case) {
-                Ok(regex_rule) => regex_rule,
-                Err(err) => {
-                    return Err(QueryError::RegexCompileError(format!(
-                        "Failed to compile regex string '{}': '{:?}",
-                        regex_str, err
-                    )))
-                }
-            };
-            Ok(Self::Regex(regex_rule))
-        } else {
-            Err(QueryError::InvalidFunctionParameters(format!(
-                "Unknown rule type '{}'",
-                rtype
-            )))
+            },
+            _ => {
+                return Err(QueryError::InvalidFunctionParameters(format!(
+                    "Unknown rule type '{}'",
+                    rtype
+                )))
+            }
+        };
+        // Any rule can exclude the events which match another rule
+        match obj.get("exclude") {
+            Some(exclude) => Ok(rule.exclude(exclude.try_into()?)),
+            None => Ok(rule),
         }
     }
 }
+
+fn rule_string<'a>(
+    obj: &'a HashMap<String, DataType>,
+    rtype: &str,
+    field: &str,
+) -> Result<&'a String, QueryError> {
+    match obj.get(field) {
+        Some(DataType::String(s)) => Ok(s),
+        Some(_) => Err(QueryError::InvalidFunctionParameters(format!(
+            "the {} field of the {} rule is not a string",
+            field, rtype
+        ))),
+        None => Err(QueryError::InvalidFunctionParameters(format!(
+            "{} rule is missing the '{}' field",
+            rtype, field
+        ))),
+    }
+}
+
+fn rule_ignore_case(obj: &HashMap<String, DataType>, rtype: &str) -> Result<bool, QueryError> {
+    match obj.get("ignore_case") {
+        Some(DataType::Bool(b)) => Ok(*b),
+        Some(_) => Err(QueryError::InvalidFunctionParameters(format!(
+            "the ignore_case field of the {} rule is not a bool",
+            rtype
+        ))),
+        None => Ok(false),
+    }
+}
+
+fn rule_select_keys(
+    obj: &HashMap<String, DataType>,
+    rtype: &str,
+) -> Result<Option<Vec<String>>, QueryError> {
+    match obj.get("select_keys") {
+        Some(keys) => match Vec::<String>::try_from(keys) {
+            Ok(keys) => Ok(Some(keys)),
+            Err(_) => Err(QueryError::InvalidFunctionParameters(format!(
+                "the select_keys field of the {} rule is not a list of strings",
+                rtype
+            ))),
+        },
+        None => Ok(None),
+    }
+}
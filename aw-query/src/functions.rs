@@ -9,6 +9,120 @@ pub type QueryFn = fn(
     ds: &Datastore,
 ) -> Result<DataType, QueryError>;
 
+/// Type of a function parameter or return value, used when statically checking a query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Any,
+    None,
+    Bool,
+    Number,
+    String,
+    DateTime,
+    List,
+    Dict,
+    Function,
+}
+
+/// Describes the parameters and return type of a query function
+///
+/// The first `required` parameters are mandatory, the rest are optional. If `variadic` is set the
+/// last parameter type may be repeated any number of times.
+pub struct Signature {
+    pub params: &'static [Type],
+    pub required: usize,
+    pub variadic: bool,
+    pub ret: Type,
+}
+
+impl Signature {
+    const fn new(params: &'static [Type], ret: Type) -> Signature {
+        Signature {
+            params,
+            required: params.len(),
+            variadic: false,
+            ret,
+        }
+    }
+
+    const fn optional(params: &'static [Type], required: usize, ret: Type) -> Signature {
+        Signature {
+            params,
+            required,
+            variadic: false,
+            ret,
+        }
+    }
+
+    const fn variadic(param: &'static [Type], ret: Type) -> Signature {
+        Signature {
+            params: param,
+            required: 0,
+            variadic: true,
+            ret,
+        }
+    }
+}
+
+/// Returns the signature of a function registered in fill_env
+pub fn signature(fname: &str) -> Option<Signature> {
+    use Type::*;
+    let sig = match fname {
+        "print" => Signature::variadic(&[Any], None),
+        "query_bucket" => Signature::new(&[String], List),
+        "query_bucket_names" => Signature::new(&[], List),
+        "sort_by_duration" => Signature::new(&[List], List),
+        "sort_by_timestamp" => Signature::new(&[List], List),
+        "sum_durations" => Signature::new(&[List], Number),
+        "limit_events" => Signature::new(&[List, Number], List),
+        "contains" => Signature::new(&[Any, Any], Bool),
+        "flood" => Signature::new(&[List], List),
+        "find_bucket" => Signature::new(&[String], String),
+        "merge_events_by_keys" => Signature::new(&[List, List], List),
+        "chunk_events_by_key" => Signature::optional(&[List, String, String], 2, List),
+        "filter_keyvals" => Signature::new(&[List, String, List], List),
+        "filter_period_intersect" => Signature::new(&[List, List], List),
+        "filter_period_exclude" => Signature::new(&[List, List], List),
+        "period_union" => Signature::variadic(&[List], List),
+        "resolve_overlaps" => Signature::variadic(&[List], List),
+        "split_url_events" => Signature::new(&[List], List),
+        "concat" => Signature::variadic(&[List], List),
+        "categorize" => Signature::new(&[List, List], List),
+        "tag" => Signature::new(&[List, List], List),
+        "category_tree" => Signature::optional(&[List, Number], 1, Dict),
+        "replace_keyvals" => Signature::new(&[List, String, String, String], List),
+        "redact" => Signature::optional(&[List, List, String, String], 2, List),
+        "lower" => Signature::new(&[String], String),
+        "upper" => Signature::new(&[String], String),
+        "trim" => Signature::new(&[String], String),
+        "split" => Signature::new(&[String, String], List),
+        "replace" => Signature::new(&[String, String, String], String),
+        "startswith" => Signature::new(&[String, String], Bool),
+        "regex_match" => Signature::new(&[String, String], Bool),
+        // Returns None if the regex does not match
+        "regex_capture" => Signature::new(&[String, String], Any),
+        "format" => Signature {
+            params: &[String, Any],
+            required: 1,
+            variadic: true,
+            ret: String,
+        },
+        "timeinterval_start" => Signature::new(&[], DateTime),
+        "timeinterval_end" => Signature::new(&[], DateTime),
+        "datetime" => Signature::new(&[String], DateTime),
+        "split_interval" => Signature::optional(&[String, String, Number], 1, List),
+        "split_by_day" => Signature::optional(&[List, String, Number], 1, List),
+        "daily_periods" => Signature::optional(&[String, String, List, String], 3, List),
+        "histogram" => Signature::optional(&[List, String, String, String, Number], 2, List),
+        "productivity" => Signature::optional(&[List, String, String, Number], 2, List),
+        "group_by" => Signature::optional(&[List, List, List], 2, Dict),
+        "sessionize" => Signature::optional(&[List, Number, String], 2, List),
+        "resample" => Signature::optional(&[List, Number, String], 2, List),
+        "find_gaps" => Signature::optional(&[List, Number, List], 2, List),
+        _ => return Option::None,
+    };
+    Some(sig)
+}
+
 pub fn fill_env<'a>(env: &mut HashMap<&'a str, DataType>) {
     env.insert(
         "print",
@@ -81,6 +195,21 @@ pub fn fill_env<'a>(env: &mut HashMap<&'a str, DataType>) {
             qfunctions::filter_period_intersect,
         ),
     );
+    env.insert(
+        "filter_period_exclude",
+        DataType::Function(
+            "filter_period_exclude".to_string(),
+            qfunctions::filter_period_exclude,
+        ),
+    );
+    env.insert(
+        "period_union",
+        DataType::Function("period_union".to_string(), qfunctions::period_union),
+    );
+    env.insert(
+        "resolve_overlaps",
+        DataType::Function("resolve_overlaps".to_string(), qfunctions::resolve_overlaps),
+    );
     env.insert(
         "split_url_events",
         DataType::Function("split_url_events".to_string(), qfunctions::split_url_events),
@@ -94,6 +223,105 @@ pub fn fill_env<'a>(env: &mut HashMap<&'a str, DataType>) {
         DataType::Function("categorize".into(), qfunctions::categorize),
     );
     env.insert("tag", DataType::Function("tag".into(), qfunctions::tag));
+    env.insert(
+        "category_tree",
+        DataType::Function("category_tree".into(), qfunctions::category_tree),
+    );
+    env.insert(
+        "replace_keyvals",
+        DataType::Function("replace_keyvals".to_string(), qfunctions::replace_keyvals),
+    );
+    env.insert(
+        "redact",
+        DataType::Function("redact".to_string(), qfunctions::redact),
+    );
+    env.insert(
+        "lower",
+        DataType::Function("lower".to_string(), qfunctions::lower),
+    );
+    env.insert(
+        "upper",
+        DataType::Function("upper".to_string(), qfunctions::upper),
+    );
+    env.insert(
+        "trim",
+        DataType::Function("trim".to_string(), qfunctions::trim),
+    );
+    env.insert(
+        "split",
+        DataType::Function("split".to_string(), qfunctions::split),
+    );
+    env.insert(
+        "replace",
+        DataType::Function("replace".to_string(), qfunctions::replace),
+    );
+    env.insert(
+        "startswith",
+        DataType::Function("startswith".to_string(), qfunctions::startswith),
+    );
+    env.insert(
+        "regex_match",
+        DataType::Function("regex_match".to_string(), qfunctions::regex_match),
+    );
+    env.insert(
+        "regex_capture",
+        DataType::Function("regex_capture".to_string(), qfunctions::regex_capture),
+    );
+    env.insert(
+        "format",
+        DataType::Function("format".to_string(), qfunctions::format),
+    );
+    env.insert(
+        "timeinterval_start",
+        DataType::Function(
+            "timeinterval_start".to_string(),
+            qfunctions::timeinterval_start,
+        ),
+    );
+    env.insert(
+        "timeinterval_end",
+        DataType::Function("timeinterval_end".to_string(), qfunctions::timeinterval_end),
+    );
+    env.insert(
+        "datetime",
+        DataType::Function("datetime".to_string(), qfunctions::datetime),
+    );
+    env.insert(
+        "split_interval",
+        DataType::Function("split_interval".to_string(), qfunctions::split_interval),
+    );
+    env.insert(
+        "split_by_day",
+        DataType::Function("split_by_day".to_string(), qfunctions::split_by_day),
+    );
+    env.insert(
+        "daily_periods",
+        DataType::Function("daily_periods".to_string(), qfunctions::daily_periods),
+    );
+    env.insert(
+        "histogram",
+        DataType::Function("histogram".to_string(), qfunctions::histogram),
+    );
+    env.insert(
+        "productivity",
+        DataType::Function("productivity".to_string(), qfunctions::productivity),
+    );
+    env.insert(
+        "group_by",
+        DataType::Function("group_by".to_string(), qfunctions::group_by),
+    );
+    env.insert(
+        "sessionize",
+        DataType::Function("sessionize".to_string(), qfunctions::sessionize),
+    );
+    env.insert(
+        "resample",
+        DataType::Function("resample".to_string(), qfunctions::resample),
+    );
+    env.insert(
+        "find_gaps",
+        DataType::Function("find_gaps".to_string(), qfunctions::find_gaps),
+    );
 }
 
 mod qfunctions {
@@ -101,14 +329,24 @@ mod qfunctions {
     use std::convert::TryFrom;
     use std::convert::TryInto;
 
+    use chrono::{DateTime, Utc};
+    use chrono_tz::Tz;
+
     use aw_datastore::Datastore;
     use aw_models::Event;
     use aw_transform::classify::Rule;
+    use aw_transform::timeslots;
+    use aw_transform::Aggregation;
 
     use super::validate;
     use crate::DataType;
     use crate::QueryError;
 
+    /// Durations are returned from queries as seconds
+    fn seconds(duration: chrono::Duration) -> DataType {
+        DataType::Number(duration.num_milliseconds() as f64 / 1000.0)
+    }
+
     pub fn print(
         args: Vec<DataType>,
         _env: &HashMap<&str, DataType>,
@@ -129,12 +367,15 @@ mod qfunctions {
         validate::args_length(&args, 1)?;
         let bucket_id: String = (&args[0]).try_into()?;
         let interval = validate::get_timeinterval(env)?;
+        // Read one event more than allowed so the interpreter notices that the limit is exceeded
+        // without the whole bucket being loaded
+        let limit = ds.event_budget().map(|remaining| remaining + 1);
 
         let events = match ds.get_events(
             bucket_id.as_str(),
             Some(*interval.start()),
             Some(*interval.end()),
-            None,
+            limit,
         ) {
             Ok(events) => events,
             Err(e) => {
@@ -255,9 +496,9 @@ mod qfunctions {
         // typecheck
         validate::args_length(&args, 2)?;
         let events: Vec<Event> = Vec::try_from(&args[0])?;
-        let rules: Vec<(Vec<String>, Rule)> = Vec::try_from(&args[1])?;
+        let rules: Vec<(Vec<String>, Rule, Option<f64>)> = Vec::try_from(&args[1])?;
         // Run categorize
-        let mut flooded_events = aw_transform::classify::categorize(events, &rules);
+        let mut flooded_events = aw_transform::classify::categorize_scored(events, &rules);
         // Put events back into DataType::Event container
         let mut tagged_flooded_events = Vec::new();
         for event in flooded_events.drain(..) {
@@ -285,6 +526,39 @@ mod qfunctions {
         Ok(DataType::List(tagged_flooded_events))
     }
 
+    pub fn category_tree(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 1, 2)?;
+        let events: Vec<Event> = Vec::try_from(&args[0])?;
+        let top_n: usize = match args.get(1) {
+            Some(arg) => arg.try_into()?,
+            None => 5,
+        };
+
+        let root = aw_transform::category_tree(&events, top_n);
+        Ok(category_node_to_dict(root))
+    }
+
+    fn category_node_to_dict(mut node: aw_transform::CategoryNode) -> DataType {
+        let mut top_events = Vec::new();
+        for event in node.top_events.drain(..) {
+            top_events.push(DataType::Event(event));
+        }
+        let mut children = HashMap::new();
+        for (name, child) in node.children.drain() {
+            children.insert(name, category_node_to_dict(child));
+        }
+        let mut dict = HashMap::new();
+        dict.insert("duration".to_string(), seconds(node.duration));
+        dict.insert("total".to_string(), seconds(node.total));
+        dict.insert("top_events".to_string(), DataType::List(top_events));
+        dict.insert("children".to_string(), DataType::Dict(children));
+        DataType::Dict(dict)
+    }
+
     pub fn sort_by_duration(
         args: Vec<DataType>,
         _env: &HashMap<&str, DataType>,
@@ -386,11 +660,15 @@ mod qfunctions {
         _ds: &Datastore,
     ) -> Result<DataType, QueryError> {
         // typecheck
-        validate::args_length(&args, 2)?;
+        validate::args_length_range(&args, 2, 3)?;
         let events: Vec<Event> = (&args[0]).try_into()?;
         let key: String = (&args[1]).try_into()?;
+        let subkey: Option<String> = match args.get(2) {
+            Some(arg) => Some(arg.try_into()?),
+            None => None,
+        };
 
-        let mut merged_events = aw_transform::chunk_events_by_key(events, &key);
+        let mut merged_events = aw_transform::chunk_events_by_key(events, &key, subkey.as_deref());
         let mut merged_tagged_events = Vec::new();
         for event in merged_events.drain(..) {
             merged_tagged_events.push(DataType::Event(event));
@@ -435,6 +713,59 @@ mod qfunctions {
         Ok(DataType::List(filtered_tagged_events))
     }
 
+    pub fn filter_period_exclude(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        // typecheck
+        validate::args_length(&args, 2)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let filter_events: Vec<Event> = (&args[1]).try_into()?;
+
+        let mut filtered_events = aw_transform::filter_period_exclude(&events, &filter_events);
+        let mut filtered_tagged_events = Vec::new();
+        for event in filtered_events.drain(..) {
+            filtered_tagged_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(filtered_tagged_events))
+    }
+
+    pub fn period_union(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        let mut union_events = Vec::new();
+        for arg in args {
+            let events: Vec<Event> = (&arg).try_into()?;
+            union_events = aw_transform::period_union(&union_events, &events);
+        }
+        let mut tagged_union_events = Vec::new();
+        for event in union_events.drain(..) {
+            tagged_union_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_union_events))
+    }
+
+    pub fn resolve_overlaps(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        let mut event_lists = Vec::new();
+        for arg in args {
+            let events: Vec<Event> = (&arg).try_into()?;
+            event_lists.push(events);
+        }
+        let mut resolved_events = aw_transform::resolve_overlaps(event_lists);
+        let mut tagged_resolved_events = Vec::new();
+        for event in resolved_events.drain(..) {
+            tagged_resolved_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_resolved_events))
+    }
+
     pub fn split_url_events(
         args: Vec<DataType>,
         _env: &HashMap<&str, DataType>,
@@ -466,12 +797,587 @@ mod qfunctions {
         }
         Ok(DataType::List(event_list))
     }
+
+    pub fn replace_keyvals(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        // typecheck
+        validate::args_length(&args, 4)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let key: String = (&args[1]).try_into()?;
+        let pattern: String = (&args[2]).try_into()?;
+        let replacement: String = (&args[3]).try_into()?;
+        let regex = validate::regex(&pattern)?;
+
+        let mut replaced_events = aw_transform::replace_keyvals(events, &key, &regex, &replacement);
+        let mut replaced_tagged_events = Vec::new();
+        for event in replaced_events.drain(..) {
+            replaced_tagged_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(replaced_tagged_events))
+    }
+
+    pub fn redact(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 2, 4)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let keys: Vec<String> = (&args[1]).try_into()?;
+        let method: String = match args.get(2) {
+            Some(arg) => arg.try_into()?,
+            None => "hash".to_string(),
+        };
+        let salt: Option<String> = match args.get(3) {
+            Some(arg) => Some(arg.try_into()?),
+            None => None,
+        };
+        let redaction = aw_transform::Redaction::from_method(&method, salt.as_deref())
+            .map_err(QueryError::InvalidFunctionParameters)?;
+
+        let mut redacted_events = aw_transform::redact(events, &keys, &redaction);
+        let mut tagged_redacted_events = Vec::new();
+        for event in redacted_events.drain(..) {
+            tagged_redacted_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_redacted_events))
+    }
+
+    pub fn lower(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let s: String = (&args[0]).try_into()?;
+        Ok(DataType::String(s.to_lowercase()))
+    }
+
+    pub fn upper(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let s: String = (&args[0]).try_into()?;
+        Ok(DataType::String(s.to_uppercase()))
+    }
+
+    pub fn trim(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let s: String = (&args[0]).try_into()?;
+        Ok(DataType::String(s.trim().to_string()))
+    }
+
+    pub fn split(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let s: String = (&args[0]).try_into()?;
+        let separator: String = (&args[1]).try_into()?;
+        if separator.is_empty() {
+            return Err(QueryError::InvalidFunctionParameters(
+                "function split got an empty separator".to_string(),
+            ));
+        }
+        let parts = s
+            .split(separator.as_str())
+            .map(|part| DataType::String(part.to_string()))
+            .collect();
+        Ok(DataType::List(parts))
+    }
+
+    pub fn replace(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 3)?;
+        let s: String = (&args[0]).try_into()?;
+        let from: String = (&args[1]).try_into()?;
+        let to: String = (&args[2]).try_into()?;
+        Ok(DataType::String(s.replace(from.as_str(), to.as_str())))
+    }
+
+    pub fn startswith(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let s: String = (&args[0]).try_into()?;
+        let prefix: String = (&args[1]).try_into()?;
+        Ok(DataType::Bool(s.starts_with(prefix.as_str())))
+    }
+
+    pub fn regex_match(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let s: String = (&args[0]).try_into()?;
+        let pattern: String = (&args[1]).try_into()?;
+        let regex = validate::regex(&pattern)?;
+        Ok(DataType::Bool(regex.is_match(&s)))
+    }
+
+    /// Returns the whole match followed by all capture groups of the first match, groups which
+    /// did not participate in the match are None
+    pub fn regex_capture(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let s: String = (&args[0]).try_into()?;
+        let pattern: String = (&args[1]).try_into()?;
+        let regex = validate::regex(&pattern)?;
+        let captures = match regex.captures(&s) {
+            Some(captures) => captures,
+            None => return Ok(DataType::None()),
+        };
+        let groups = captures
+            .iter()
+            .map(|group| match group {
+                Some(m) => DataType::String(m.as_str().to_string()),
+                None => DataType::None(),
+            })
+            .collect();
+        Ok(DataType::List(groups))
+    }
+
+    /// Replaces each {} in the first argument with the following arguments in order
+    pub fn format(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        if args.is_empty() {
+            return Err(QueryError::InvalidFunctionParameters(
+                "Expected at least 1 parameter in function format, got 0".to_string(),
+            ));
+        }
+        let fmt: String = (&args[0]).try_into()?;
+        let parts: Vec<&str> = fmt.split("{}").collect();
+        let values = &args[1..];
+        if parts.len() - 1 != values.len() {
+            return Err(QueryError::InvalidFunctionParameters(format!(
+                "function format got {} placeholders but {} values",
+                parts.len() - 1,
+                values.len()
+            )));
+        }
+        let mut formatted = parts[0].to_string();
+        for (value, part) in values.iter().zip(parts[1..].iter()) {
+            match value {
+                DataType::String(s) => formatted.push_str(s),
+                DataType::Number(n) => formatted.push_str(&n.to_string()),
+                DataType::Bool(b) => formatted.push_str(&b.to_string()),
+                DataType::None() => formatted.push_str("None"),
+                _ => {
+                    return Err(QueryError::InvalidFunctionParameters(format!(
+                        "function format cannot format {:?}",
+                        value
+                    )))
+                }
+            }
+            formatted.push_str(part);
+        }
+        Ok(DataType::String(formatted))
+    }
+
+    pub fn timeinterval_start(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 0)?;
+        let interval = validate::get_timeinterval(env)?;
+        Ok(DataType::DateTime(*interval.start()))
+    }
+
+    pub fn timeinterval_end(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 0)?;
+        let interval = validate::get_timeinterval(env)?;
+        Ok(DataType::DateTime(*interval.end()))
+    }
+
+    pub fn datetime(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let dt: DateTime<Utc> = (&args[0]).try_into()?;
+        Ok(DataType::DateTime(dt))
+    }
+
+    /// Splits TIMEINTERVAL into hours, days or weeks in a timezone, days and weeks can start at
+    /// an offset in hours from midnight. Returns an event for each part.
+    pub fn split_interval(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 1, 3)?;
+        let resolution = validate::resolution(&args[0])?;
+        let tz = match args.get(1) {
+            Some(arg) => validate::timezone(arg)?,
+            None => Tz::UTC,
+        };
+        let offset = match args.get(2) {
+            Some(arg) => validate::day_offset(arg)?,
+            None => chrono::Duration::zero(),
+        };
+        let interval = validate::get_timeinterval(env)?;
+
+        let mut events = timeslots::split_period_events(
+            *interval.start(),
+            *interval.end(),
+            resolution,
+            &tz,
+            offset,
+        )
+        .map_err(QueryError::InvalidFunctionParameters)?;
+        let mut tagged_events = Vec::new();
+        for event in events.drain(..) {
+            tagged_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_events))
+    }
+
+    /// Splits events at the boundaries of local days in a timezone, days can start at an offset
+    /// in hours from midnight
+    pub fn split_by_day(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 1, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let tz = match args.get(1) {
+            Some(arg) => validate::timezone(arg)?,
+            None => Tz::UTC,
+        };
+        let offset = match args.get(2) {
+            Some(arg) => validate::day_offset(arg)?,
+            None => chrono::Duration::zero(),
+        };
+
+        let mut split_events = timeslots::split_events_by_day(events, &tz, offset)
+            .map_err(QueryError::InvalidFunctionParameters)?;
+        let mut tagged_split_events = Vec::new();
+        for event in split_events.drain(..) {
+            tagged_split_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_split_events))
+    }
+
+    /// Returns an event for the time between two local times such as "09:00" and "17:00" on
+    /// every day in TIMEINTERVAL which is one of the ISO weekday numbers (1 is monday)
+    pub fn daily_periods(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 3, 4)?;
+        let from = validate::time_of_day(&args[0])?;
+        let to = validate::time_of_day(&args[1])?;
+        let weekdays = validate::weekdays(&args[2])?;
+        let tz = match args.get(3) {
+            Some(arg) => validate::timezone(arg)?,
+            None => Tz::UTC,
+        };
+        let interval = validate::get_timeinterval(env)?;
+
+        let mut events =
+            timeslots::daily_periods(*interval.start(), *interval.end(), from, to, &weekdays, &tz)
+                .map_err(QueryError::InvalidFunctionParameters)?;
+        let mut tagged_events = Vec::new();
+        for event in events.drain(..) {
+            tagged_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_events))
+    }
+
+    /// Sums the durations of events per hour, day or week of TIMEINTERVAL, optionally also per
+    /// value of a key. An empty key disables the grouping.
+    ///
+    /// Returns a dict per time slot with its "start" and "end", the total "duration" and the
+    /// durations per key value as "totals".
+    pub fn histogram(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 2, 5)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let resolution = validate::resolution(&args[1])?;
+        let key: String = match args.get(2) {
+            Some(arg) => arg.try_into()?,
+            None => "".to_string(),
+        };
+        let tz = match args.get(3) {
+            Some(arg) => validate::timezone(arg)?,
+            None => Tz::UTC,
+        };
+        let offset = match args.get(4) {
+            Some(arg) => validate::day_offset(arg)?,
+            None => chrono::Duration::zero(),
+        };
+        let interval = validate::get_timeinterval(env)?;
+        let key = if key.is_empty() {
+            None
+        } else {
+            Some(key.as_str())
+        };
+
+        let slots = aw_transform::histogram(
+            &events,
+            *interval.start(),
+            *interval.end(),
+            resolution,
+            &tz,
+            offset,
+            key,
+        )
+        .map_err(QueryError::InvalidFunctionParameters)?;
+        let mut tagged_slots = Vec::new();
+        for slot in slots {
+            let mut totals = HashMap::new();
+            for (value, duration) in slot.totals {
+                totals.insert(value, seconds(duration));
+            }
+            let mut dict = HashMap::new();
+            dict.insert("start".to_string(), DataType::DateTime(slot.start));
+            dict.insert("end".to_string(), DataType::DateTime(slot.end));
+            dict.insert("duration".to_string(), seconds(slot.duration));
+            dict.insert("totals".to_string(), DataType::Dict(totals));
+            tagged_slots.push(DataType::Dict(dict));
+        }
+        Ok(DataType::List(tagged_slots))
+    }
+
+    pub fn productivity(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 2, 4)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let resolution = validate::resolution(&args[1])?;
+        let tz = match args.get(2) {
+            Some(arg) => validate::timezone(arg)?,
+            None => Tz::UTC,
+        };
+        let offset = match args.get(3) {
+            Some(arg) => validate::day_offset(arg)?,
+            None => chrono::Duration::zero(),
+        };
+        let interval = validate::get_timeinterval(env)?;
+
+        let slots = aw_transform::productivity(
+            &events,
+            *interval.start(),
+            *interval.end(),
+            resolution,
+            &tz,
+            offset,
+        )
+        .map_err(QueryError::InvalidFunctionParameters)?;
+        let mut tagged_slots = Vec::new();
+        for slot in slots {
+            let mut dict = HashMap::new();
+            dict.insert("start".to_string(), DataType::DateTime(slot.start));
+            dict.insert("end".to_string(), DataType::DateTime(slot.end));
+            dict.insert("duration".to_string(), seconds(slot.duration));
+            dict.insert("productive".to_string(), seconds(slot.productive));
+            dict.insert("neutral".to_string(), seconds(slot.neutral));
+            dict.insert("distracting".to_string(), seconds(slot.distracting));
+            dict.insert("score".to_string(), DataType::Number(slot.score));
+            tagged_slots.push(DataType::Dict(dict));
+        }
+        Ok(DataType::List(tagged_slots))
+    }
+
+    /// Groups events by the values of keys, returns a dict with a dict of statistics for each
+    /// group
+    ///
+    /// The statistics are the ones listed in the aggregations, which default to "duration",
+    /// "count", "first", "last" and "longest". "top:<key>:<n>" lists the n values of key with
+    /// the longest durations within the group. The values of the grouped keys are always
+    /// included as "values".
+    pub fn group_by(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 2, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let keys: Vec<String> = (&args[1]).try_into()?;
+        let aggregation_names: Vec<String> = match args.get(2) {
+            Some(arg) => arg.try_into()?,
+            None => vec!["duration", "count", "first", "last", "longest"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        };
+        let mut aggregations = Vec::new();
+        for name in &aggregation_names {
+            let aggregation: Aggregation = name
+                .parse()
+                .map_err(QueryError::InvalidFunctionParameters)?;
+            aggregations.push(aggregation);
+        }
+
+        let groups = aw_transform::group_by(&events, &keys, &aggregations);
+        let mut tagged_groups = HashMap::new();
+        for (group_id, mut group) in groups {
+            let mut dict = HashMap::new();
+            let values = serde_json::Value::Object(group.values);
+            dict.insert("values".to_string(), DataType::from(&values));
+            for aggregation in &aggregations {
+                let (name, value) = match aggregation {
+                    Aggregation::Duration => ("duration".to_string(), seconds(group.duration)),
+                    Aggregation::Count => {
+                        ("count".to_string(), DataType::Number(group.count as f64))
+                    }
+                    Aggregation::First => ("first".to_string(), DataType::DateTime(group.first)),
+                    Aggregation::Last => ("last".to_string(), DataType::DateTime(group.last)),
+                    Aggregation::Longest => ("longest".to_string(), seconds(group.longest)),
+                    Aggregation::Top(key, _n) => {
+                        let mut top = Vec::new();
+                        for (value, duration) in group.top.remove(key).unwrap_or_default() {
+                            let mut entry = HashMap::new();
+                            entry.insert("value".to_string(), DataType::from(&value));
+                            entry.insert("duration".to_string(), seconds(duration));
+                            top.push(DataType::Dict(entry));
+                        }
+                        (format!("top:{}", key), DataType::List(top))
+                    }
+                };
+                dict.insert(name, value);
+            }
+            tagged_groups.insert(group_id, DataType::Dict(dict));
+        }
+        Ok(DataType::Dict(tagged_groups))
+    }
+
+    pub fn sessionize(
+        args: Vec<DataType>,
+        _env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 2, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let max_gap = validate::duration(&args[1], "Max gap of sessionize")?;
+        let key: String = match args.get(2) {
+            Some(arg) => arg.try_into()?,
+            None => "app".to_string(),
+        };
+
+        let mut sessions = aw_transform::sessionize(events, max_gap, &key);
+        let mut tagged_sessions = Vec::new();
+        for session in sessions.drain(..) {
+            tagged_sessions.push(DataType::Event(session));
+        }
+        Ok(DataType::List(tagged_sessions))
+    }
+
+    pub fn resample(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 2, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let resolution: f64 = (&args[1]).try_into()?;
+        let key: String = match args.get(2) {
+            Some(arg) => arg.try_into()?,
+            None => "".to_string(),
+        };
+        // Also rejects NaN, casting it or too large values to an integer is not defined
+        if !(resolution >= 0.001 && resolution <= 1e12) {
+            return Err(QueryError::InvalidFunctionParameters(format!(
+                "Resolution of resample has to be between 1ms and 1e12s, got {}",
+                resolution
+            )));
+        }
+        let resolution = chrono::Duration::milliseconds((resolution * 1000.0) as i64);
+        let interval = validate::get_timeinterval(env)?;
+        let key = if key.is_empty() {
+            None
+        } else {
+            Some(key.as_str())
+        };
+
+        let mut resampled_events =
+            aw_transform::resample(&events, *interval.start(), *interval.end(), resolution, key)
+                .map_err(QueryError::InvalidFunctionParameters)?;
+        let mut tagged_resampled_events = Vec::new();
+        for event in resampled_events.drain(..) {
+            tagged_resampled_events.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_resampled_events))
+    }
+
+    /// Returns an event with `{"$gap": true}` for every gap of at least min_gap seconds between
+    /// the events within TIMEINTERVAL, gaps are labelled with the status of covering AFK events
+    pub fn find_gaps(
+        args: Vec<DataType>,
+        env: &HashMap<&str, DataType>,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 2, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let min_gap = validate::duration(&args[1], "Minimum gap of find_gaps")?;
+        let afk_events: Vec<Event> = match args.get(2) {
+            Some(arg) => arg.try_into()?,
+            None => Vec::new(),
+        };
+        let interval = validate::get_timeinterval(env)?;
+
+        let mut gaps = aw_transform::find_gaps(
+            &events,
+            *interval.start(),
+            *interval.end(),
+            min_gap,
+            &afk_events,
+        );
+        let mut tagged_gaps = Vec::new();
+        for event in gaps.drain(..) {
+            tagged_gaps.push(DataType::Event(event));
+        }
+        Ok(DataType::List(tagged_gaps))
+    }
 }
 
 mod validate {
     use crate::{DataType, QueryError};
     use aw_models::TimeInterval;
+    use aw_transform::timeslots::{self, Resolution};
+    use chrono::{Duration, NaiveTime, Weekday};
+    use chrono_tz::Tz;
+    use regex::Regex;
     use std::collections::HashMap;
+    use std::convert::TryInto;
+
+    /// Max duration in seconds accepted by duration
+    const MAX_DURATION_SECONDS: f64 = 1e12;
 
     pub fn args_length(args: &[DataType], len: usize) -> Result<(), QueryError> {
         if args.len() != len {
@@ -484,6 +1390,104 @@ mod validate {
         Ok(())
     }
 
+    pub fn args_length_range(args: &[DataType], min: usize, max: usize) -> Result<(), QueryError> {
+        if args.len() < min || args.len() > max {
+            return Err(QueryError::InvalidFunctionParameters(format!(
+                "Expected {} to {} parameters in function, got {}",
+                min,
+                max,
+                args.len()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn timezone(arg: &DataType) -> Result<Tz, QueryError> {
+        let name: String = arg.try_into()?;
+        match name.parse() {
+            Ok(tz) => Ok(tz),
+            Err(e) => Err(QueryError::InvalidFunctionParameters(format!(
+                "Unknown timezone '{}': {}",
+                name, e
+            ))),
+        }
+    }
+
+    pub fn resolution(arg: &DataType) -> Result<Resolution, QueryError> {
+        let name: String = arg.try_into()?;
+        name.parse().map_err(QueryError::InvalidFunctionParameters)
+    }
+
+    /// Non-negative duration from a number of seconds, name describes the argument in errors
+    pub fn duration(arg: &DataType, name: &str) -> Result<Duration, QueryError> {
+        let seconds: f64 = arg.try_into()?;
+        // Also rejects NaN, larger durations do not fit in Duration
+        if !(seconds >= 0.0 && seconds <= MAX_DURATION_SECONDS) {
+            return Err(QueryError::InvalidFunctionParameters(format!(
+                "{} has to be between 0 and {}s, got {}",
+                name, MAX_DURATION_SECONDS, seconds
+            )));
+        }
+        Ok(Duration::milliseconds((seconds * 1000.0) as i64))
+    }
+
+    /// Offset of the start of days from midnight from a number of hours
+    pub fn day_offset(arg: &DataType) -> Result<Duration, QueryError> {
+        let hours: f64 = arg.try_into()?;
+        timeslots::day_offset(hours).map_err(QueryError::InvalidFunctionParameters)
+    }
+
+    /// Time of day formatted as HH:MM or HH:MM:SS
+    pub fn time_of_day(arg: &DataType) -> Result<NaiveTime, QueryError> {
+        let s: String = arg.try_into()?;
+        NaiveTime::parse_from_str(&s, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(&s, "%H:%M"))
+            .map_err(|_| {
+                QueryError::InvalidFunctionParameters(format!(
+                    "Expected a time formatted as HH:MM, got '{}'",
+                    s
+                ))
+            })
+    }
+
+    /// List of ISO weekday numbers where 1 is monday and 7 is sunday
+    pub fn weekdays(arg: &DataType) -> Result<Vec<Weekday>, QueryError> {
+        let numbers: Vec<DataType> = arg.try_into()?;
+        let mut weekdays = Vec::new();
+        for number in &numbers {
+            let n: f64 = number.try_into()?;
+            // Fractions would otherwise be truncated to a weekday, 0 is not a weekday
+            let day = if n.fract() == 0.0 { n as i64 } else { 0 };
+            let weekday = match day {
+                1 => Weekday::Mon,
+                2 => Weekday::Tue,
+                3 => Weekday::Wed,
+                4 => Weekday::Thu,
+                5 => Weekday::Fri,
+                6 => Weekday::Sat,
+                7 => Weekday::Sun,
+                _ => {
+                    return Err(QueryError::InvalidFunctionParameters(format!(
+                        "Expected weekday numbers between 1 and 7, got {}",
+                        n
+                    )))
+                }
+            };
+            weekdays.push(weekday);
+        }
+        Ok(weekdays)
+    }
+
+    pub fn regex(pattern: &str) -> Result<Regex, QueryError> {
+        match Regex::new(pattern) {
+            Ok(regex) => Ok(regex),
+            Err(err) => Err(QueryError::RegexCompileError(format!(
+                "Failed to compile regex string '{}': '{:?}",
+                pattern, err
+            ))),
+        }
+    }
+
     pub fn get_timeinterval(env: &HashMap<&str, DataType>) -> Result<TimeInterval, QueryError> {
         let interval_str = match env.get("TIMEINTERVAL") {
             Some(data_ti) => match data_ti {
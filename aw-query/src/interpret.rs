@@ -1,47 +1,140 @@
 use std::collections::HashMap;
+use std::time::Instant;
 
 use crate::functions;
 
 use aw_datastore::Datastore;
 use aw_models::TimeInterval;
+use chrono::{DateTime, Utc};
 
 use crate::ast::*;
+use crate::limits::{check_output_size, Limits, Usage};
+use crate::profile::{count_events, Profile};
 use crate::DataType;
 use crate::QueryError;
+use crate::QueryOptions;
 
-fn init_env<'a>(ti: &TimeInterval) -> HashMap<&'a str, DataType> {
+/// State which is kept during the whole execution of a query
+struct Context {
+    profile: Option<Profile>,
+    limits: Limits,
+    usage: Usage,
+}
+
+fn init_env<'a>(
+    ti: &TimeInterval,
+    params: &'a HashMap<String, DataType>,
+) -> Result<HashMap<&'a str, DataType>, QueryError> {
     let mut env = HashMap::new();
     env.insert("TIMEINTERVAL", DataType::String(ti.to_string()));
     functions::fill_env(&mut env);
-    env
+    for (name, value) in params {
+        if env.contains_key(name.as_str()) {
+            return Err(QueryError::InvalidParameter(format!(
+                "Parameter {} would replace a builtin variable",
+                name
+            )));
+        }
+        if !is_identifier(name) {
+            return Err(QueryError::InvalidParameter(format!(
+                "Parameter name {} is not a valid variable name",
+                name
+            )));
+        }
+        env.insert(name, value.clone());
+    }
+    Ok(env)
+}
+
+/// Whether a name can be referred to as a variable in a query
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    };
+    let keywords = [
+        "if", "elif", "else", "return", "true", "false", "True", "False",
+    ];
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !keywords.contains(&name)
 }
 
 pub fn interpret_prog<'a>(
     p: &'a Program,
     ti: &TimeInterval,
     ds: &Datastore,
-) -> Result<DataType, QueryError> {
-    let mut env = init_env(ti);
+    options: &'a QueryOptions,
+) -> Result<(DataType, Option<Profile>), QueryError> {
+    let mut env = init_env(ti, &options.params)?;
+    // Count the rows read and the events budget of this query separately from other queries
+    // using the same datastore
+    let ds = &ds.with_row_counter().with_event_budget();
+    let mut ctx = Context {
+        profile: if options.profile {
+            Some(Profile::default())
+        } else {
+            None
+        },
+        limits: options.limits.clone(),
+        usage: Usage::new(),
+    };
     let mut ret = None;
     for expr in &p.stmts {
-        ret = Some(interpret_expr(&mut env, ds, expr)?)
+        ret = Some(interpret_expr(&mut env, ds, &mut ctx, expr)?)
+    }
+    let mut profile = ctx.profile;
+    if let Some(ref mut profile) = profile {
+        profile.time = ctx.usage.elapsed().as_secs_f64();
     }
     match ret {
-        Some(ret) => Ok(ret),
+        Some(ret) => {
+            check_output_size(&ctx.limits, &ret)?;
+            Ok((ret, profile))
+        }
         None => Err(QueryError::EmptyQuery()),
     }
 }
 
+/// Adds n seconds to a datetime, fails instead of panicking if the result is out of range
+fn add_seconds(dt: DateTime<Utc>, n: f64) -> Result<DateTime<Utc>, QueryError> {
+    let out_of_range = || {
+        QueryError::InvalidFunctionParameters(format!(
+            "Adding {} seconds to {} is out of the range of datetimes",
+            n, dt
+        ))
+    };
+    let ms = n * 1000.0;
+    // Also guards the cast below, which is undefined for values which do not fit an i64
+    if !ms.is_finite() || ms.abs() >= i64::MAX as f64 {
+        return Err(out_of_range());
+    }
+    let duration = chrono::Duration::milliseconds(ms as i64);
+    dt.checked_add_signed(duration).ok_or_else(out_of_range)
+}
+
 fn interpret_expr<'a>(
     env: &mut HashMap<&'a str, DataType>,
     ds: &Datastore,
+    ctx: &mut Context,
+    expr: &'a Expr,
+) -> Result<DataType, QueryError> {
+    ctx.usage.enter(&ctx.limits)?;
+    let res = interpret_node(env, ds, ctx, expr);
+    ctx.usage.exit();
+    res
+}
+
+fn interpret_node<'a>(
+    env: &mut HashMap<&'a str, DataType>,
+    ds: &Datastore,
+    ctx: &mut Context,
     expr: &'a Expr,
 ) -> Result<DataType, QueryError> {
     use crate::ast::Expr_::*;
     match expr.node {
         Add(ref a, ref b) => {
-            let a_res = interpret_expr(env, ds, a)?;
-            let b_res = interpret_expr(env, ds, b)?;
+            let a_res = interpret_expr(env, ds, ctx, a)?;
+            let b_res = interpret_expr(env, ds, ctx, b)?;
             let res = match a_res {
                 DataType::Number(n1) => match b_res {
                     DataType::Number(n2) => DataType::Number(n1 + n2),
@@ -75,9 +168,19 @@ fn interpret_expr<'a>(
                         ))
                     }
                 },
+                // Adding a number to a datetime adds that many seconds
+                DataType::DateTime(dt) => match b_res {
+                    DataType::Number(n) => DataType::DateTime(add_seconds(dt, n)?),
+                    _ => {
+                        return Err(QueryError::InvalidType(
+                            "Cannot use + on something that is not a number with a datetime!"
+                                .to_string(),
+                        ))
+                    }
+                },
                 _ => {
                     return Err(QueryError::InvalidType(
-                        "Cannot use + on something that is not a number, list or string!"
+                        "Cannot use + on something that is not a number, list, string or datetime!"
                             .to_string(),
                     ))
                 }
@@ -85,8 +188,20 @@ fn interpret_expr<'a>(
             Ok(res)
         }
         Sub(ref a, ref b) => {
-            let a_res = interpret_expr(env, ds, a)?;
-            let b_res = interpret_expr(env, ds, b)?;
+            let a_res = interpret_expr(env, ds, ctx, a)?;
+            let b_res = interpret_expr(env, ds, ctx, b)?;
+            // Subtracting datetimes gives the difference in seconds
+            match (&a_res, &b_res) {
+                (DataType::DateTime(dt), DataType::Number(n)) => {
+                    return Ok(DataType::DateTime(add_seconds(*dt, -*n)?))
+                }
+                (DataType::DateTime(dt1), DataType::DateTime(dt2)) => {
+                    return Ok(DataType::Number(
+                        (*dt1 - *dt2).num_milliseconds() as f64 / 1000.0,
+                    ))
+                }
+                _ => (),
+            };
             let a_num = match a_res {
                 DataType::Number(n) => n,
                 _ => {
@@ -106,8 +221,8 @@ fn interpret_expr<'a>(
             Ok(DataType::Number(a_num - b_num))
         }
         Mul(ref a, ref b) => {
-            let a_res = interpret_expr(env, ds, a)?;
-            let b_res = interpret_expr(env, ds, b)?;
+            let a_res = interpret_expr(env, ds, ctx, a)?;
+            let b_res = interpret_expr(env, ds, ctx, b)?;
             let a_num = match a_res {
                 DataType::Number(n) => n,
                 _ => {
@@ -127,8 +242,8 @@ fn interpret_expr<'a>(
             Ok(DataType::Number(a_num * b_num))
         }
         Div(ref a, ref b) => {
-            let a_res = interpret_expr(env, ds, a)?;
-            let b_res = interpret_expr(env, ds, b)?;
+            let a_res = interpret_expr(env, ds, ctx, a)?;
+            let b_res = interpret_expr(env, ds, ctx, b)?;
             let a_num = match a_res {
                 DataType::Number(n) => n,
                 _ => {
@@ -153,8 +268,8 @@ fn interpret_expr<'a>(
             Ok(DataType::Number(a_num / b_num))
         }
         Mod(ref a, ref b) => {
-            let a_res = interpret_expr(env, ds, a)?;
-            let b_res = interpret_expr(env, ds, b)?;
+            let a_res = interpret_expr(env, ds, ctx, a)?;
+            let b_res = interpret_expr(env, ds, ctx, b)?;
             let a_num = match a_res {
                 DataType::Number(n) => n,
                 _ => {
@@ -174,12 +289,12 @@ fn interpret_expr<'a>(
             Ok(DataType::Number(a_num % b_num))
         }
         Equal(ref lhs, ref rhs) => {
-            let lhs_res = interpret_expr(env, ds, lhs)?;
-            let rhs_res = interpret_expr(env, ds, rhs)?;
+            let lhs_res = interpret_expr(env, ds, ctx, lhs)?;
+            let rhs_res = interpret_expr(env, ds, ctx, rhs)?;
             Ok(DataType::Bool(lhs_res.query_eq(&rhs_res)?))
         }
         Assign(ref var, ref b) => {
-            let val = interpret_expr(env, ds, b)?;
+            let val = interpret_expr(env, ds, ctx, b)?;
             // FIXME: avoid clone, it's slow
             env.insert(var, val.clone());
             Ok(val)
@@ -193,15 +308,15 @@ fn interpret_expr<'a>(
         Number(lit) => Ok(DataType::Number(lit)),
         String(ref litstr) => Ok(DataType::String(litstr.to_string())),
         Return(ref e) => {
-            let val = interpret_expr(env, ds, e)?;
+            let val = interpret_expr(env, ds, ctx, e)?;
             Ok(val)
         }
         If(ref ifs) => {
             for (ref cond, ref block) in ifs {
-                let c = interpret_expr(env, ds, cond)?;
+                let c = interpret_expr(env, ds, ctx, cond)?;
                 if c.query_eq(&DataType::Bool(true))? {
                     for expr in block {
-                        interpret_expr(env, ds, expr)?;
+                        interpret_expr(env, ds, ctx, expr)?;
                     }
                     break;
                 }
@@ -209,24 +324,50 @@ fn interpret_expr<'a>(
             Ok(DataType::None())
         }
         Function(ref fname, ref e) => {
-            let args = match interpret_expr(env, ds, e)? {
+            let args = match interpret_expr(env, ds, ctx, e)? {
                 DataType::List(l) => l,
                 _ => unreachable!(),
             };
+            // Lets functions which read from the datastore stop once max_events is exceeded
+            ds.set_event_budget(
+                ctx.usage
+                    .remaining_events(&ctx.limits)
+                    .map(|remaining| remaining as u64),
+            );
             let var = match env.get(&fname[..]) {
                 Some(v) => v,
                 None => return Err(QueryError::VariableNotDefined(fname.clone())),
             };
-            let (_name, fun) = match var {
+            let (name, fun) = match var {
                 DataType::Function(name, fun) => (name, fun),
                 _data => return Err(QueryError::InvalidType(fname.to_string())),
             };
-            fun(args, env, ds)
+            let ret = match ctx.profile {
+                Some(ref mut profile) => {
+                    let input_events = args.iter().map(count_events).sum();
+                    let start = Instant::now();
+                    let rows_before = ds.rows_read();
+                    let ret = fun(args, env, ds)?;
+                    let rows_read = ds.rows_read() - rows_before;
+                    profile.record(
+                        name,
+                        expr.span.line,
+                        start.elapsed(),
+                        input_events,
+                        rows_read,
+                        &ret,
+                    );
+                    ret
+                }
+                None => fun(args, env, ds)?,
+            };
+            ctx.usage.add_events(&ctx.limits, &ret)?;
+            Ok(ret)
         }
         List(ref list) => {
             let mut l = Vec::new();
             for entry in list {
-                let res = interpret_expr(env, ds, entry)?;
+                let res = interpret_expr(env, ds, ctx, entry)?;
                 l.push(res);
             }
             Ok(DataType::List(l))
@@ -234,7 +375,7 @@ fn interpret_expr<'a>(
         Dict(ref d) => {
             let mut dict = HashMap::new();
             for (key, val_uninterpreted) in d {
-                let val = interpret_expr(env, ds, val_uninterpreted)?;
+                let val = interpret_expr(env, ds, ctx, val_uninterpreted)?;
                 dict.insert(key.clone(), val);
             }
             Ok(DataType::Dict(dict))
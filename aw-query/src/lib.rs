@@ -5,6 +5,7 @@ extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 
+use std::collections::HashMap;
 use std::fmt;
 
 use aw_models::TimeInterval;
@@ -14,13 +15,19 @@ use aw_datastore::Datastore;
 pub mod datatype;
 
 mod ast;
+mod check;
 mod functions;
 mod interpret;
 mod lexer;
+mod limits;
 #[allow(clippy::match_single_binding, clippy::redundant_closure_call)]
 mod parser;
+mod profile;
 
+pub use crate::check::{check, check_with_params, Diagnostic};
 pub use crate::datatype::DataType;
+pub use crate::limits::Limits;
+pub use crate::profile::{FunctionProfile, Profile};
 
 // TODO: add line numbers to errors
 // (works during lexing, but not during parsing I believe)
@@ -39,6 +46,8 @@ pub enum QueryError {
     TimeIntervalError(String),
     BucketQueryError(String),
     RegexCompileError(String),
+    InvalidParameter(String),
+    LimitExceeded(String),
 }
 
 impl fmt::Display for QueryError {
@@ -47,7 +56,28 @@ impl fmt::Display for QueryError {
     }
 }
 
+/// Options which change how a query is executed
+#[derive(Clone, Default)]
+pub struct QueryOptions {
+    /// Record a trace of every function call made by the query
+    pub profile: bool,
+    /// Variables which are defined before the query is run
+    pub params: HashMap<String, DataType>,
+    pub limits: Limits,
+}
+
 pub fn query(code: &str, ti: &TimeInterval, ds: &Datastore) -> Result<DataType, QueryError> {
+    let (result, _profile) = query_with_options(code, ti, ds, &QueryOptions::default())?;
+    Ok(result)
+}
+
+/// Runs a query, the profile is only returned if it was enabled in the options
+pub fn query_with_options(
+    code: &str,
+    ti: &TimeInterval,
+    ds: &Datastore,
+    options: &QueryOptions,
+) -> Result<(DataType, Option<Profile>), QueryError> {
     let lexer = lexer::Lexer::new(code);
     let program = match parser::parse(lexer) {
         Ok(p) => p,
@@ -57,5 +87,5 @@ pub fn query(code: &str, ti: &TimeInterval, ds: &Datastore) -> Result<DataType,
             return Err(QueryError::ParsingError(format!("{:?}", e)));
         }
     };
-    interpret::interpret_prog(&program, ti, ds)
+    interpret::interpret_prog(&program, ti, ds, options)
 }
@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+use crate::profile::count_events;
+use crate::DataType;
+use crate::QueryError;
+
+/// Limits on the resources a query may use, None means unlimited
+///
+/// A query which exceeds a limit is aborted with QueryError::LimitExceeded. Time is only checked
+/// between evaluation of expressions, so a single slow function call can overshoot max_time.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Max wall time of the whole query
+    pub max_time: Option<Duration>,
+    /// Max sum of events returned from all function calls made by the query
+    pub max_events: Option<usize>,
+    /// Max depth of nested expressions during evaluation
+    ///
+    /// Expressions are evaluated recursively, so deeply nested expressions such as `[[[...]]]`
+    /// would otherwise overflow the stack, which aborts the whole process instead of failing the
+    /// query.
+    pub max_depth: Option<usize>,
+    /// Max size in bytes of the result when serialized as JSON
+    pub max_output_size: Option<usize>,
+}
+
+/// Keeps track of the resources used by a running query
+pub struct Usage {
+    start: Instant,
+    depth: usize,
+    events: usize,
+}
+
+impl Usage {
+    pub fn new() -> Usage {
+        Usage {
+            start: Instant::now(),
+            depth: 0,
+            events: 0,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Called before an expression is evaluated, must be followed by a call to exit
+    pub fn enter(&mut self, limits: &Limits) -> Result<(), QueryError> {
+        self.depth += 1;
+        if let Some(max_depth) = limits.max_depth {
+            if self.depth > max_depth {
+                return Err(QueryError::LimitExceeded(format!(
+                    "Expressions are nested deeper than the max depth of {}",
+                    max_depth
+                )));
+            }
+        }
+        if let Some(max_time) = limits.max_time {
+            if self.elapsed() > max_time {
+                return Err(QueryError::LimitExceeded(format!(
+                    "Query took longer than the max time of {}s",
+                    max_time.as_secs_f64()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn exit(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// How many more events may be returned before max_events is exceeded
+    pub fn remaining_events(&self, limits: &Limits) -> Option<usize> {
+        limits
+            .max_events
+            .map(|max_events| max_events.saturating_sub(self.events))
+    }
+
+    /// Adds the events of a value returned by a function
+    pub fn add_events(&mut self, limits: &Limits, ret: &DataType) -> Result<(), QueryError> {
+        if let Some(max_events) = limits.max_events {
+            self.events += count_events(ret);
+            if self.events > max_events {
+                return Err(QueryError::LimitExceeded(format!(
+                    "Query materialized more than the max of {} events",
+                    max_events
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn check_output_size(limits: &Limits, ret: &DataType) -> Result<(), QueryError> {
+    if let Some(max_output_size) = limits.max_output_size {
+        let size = output_size(ret);
+        if size > max_output_size {
+            return Err(QueryError::LimitExceeded(format!(
+                "Result is {} bytes which is larger than the max output size of {} bytes",
+                size, max_output_size
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Size of a value when serialized as JSON, functions are counted by the length of their name
+/// as they cannot be serialized
+fn output_size(data: &DataType) -> usize {
+    match data {
+        DataType::None() => "null".len(),
+        DataType::Bool(b) => b.to_string().len(),
+        DataType::Number(_) | DataType::String(_) | DataType::DateTime(_) | DataType::Event(_) => {
+            serde_json::to_vec(data).map(|v| v.len()).unwrap_or(0)
+        }
+        // Brackets and commas
+        DataType::List(l) => 1 + l.len().max(1) + l.iter().map(output_size).sum::<usize>(),
+        // Braces, commas, colons and quotes around keys
+        DataType::Dict(d) => {
+            1 + d.len().max(1)
+                + d.iter()
+                    .map(|(k, v)| k.len() + 3 + output_size(v))
+                    .sum::<usize>()
+        }
+        DataType::Function(name, _) => name.len(),
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use crate::DataType;
+
+/// Measurements of a single function call during a query
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionProfile {
+    pub function: String,
+    pub line: usize,
+    /// Wall time spent in the function in seconds, not including evaluation of its arguments
+    pub time: f64,
+    pub input_events: usize,
+    pub output_events: usize,
+    /// Rows read from the datastore by the function
+    pub rows_read: usize,
+}
+
+/// Trace of all function calls made while executing a query, in the order they finished
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Profile {
+    /// Total wall time of the query in seconds
+    pub time: f64,
+    pub calls: Vec<FunctionProfile>,
+}
+
+impl Profile {
+    pub fn record(
+        &mut self,
+        function: &str,
+        line: usize,
+        time: Duration,
+        input_events: usize,
+        rows_read: usize,
+        ret: &DataType,
+    ) {
+        let output_events = count_events(ret);
+        self.calls.push(FunctionProfile {
+            function: function.to_string(),
+            line,
+            time: time.as_secs_f64(),
+            input_events,
+            output_events,
+            rows_read,
+        });
+    }
+}
+
+/// Counts the events in a value, including events in nested lists and dicts
+pub fn count_events(data: &DataType) -> usize {
+    match data {
+        DataType::Event(_) => 1,
+        DataType::List(l) => l.iter().map(count_events).sum(),
+        DataType::Dict(d) => d.values().map(count_events).sum(),
+        _ => 0,
+    }
+}
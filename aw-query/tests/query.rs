@@ -24,6 +24,7 @@ mod query_tests {
     use chrono::Duration;
     use serde_json::json;
     use std::convert::TryFrom;
+    use std::str::FromStr;
 
     use aw_query::DataType;
     use aw_query::QueryError;
@@ -357,6 +358,8 @@ mod query_tests {
             print("test", "test2");
             url_events = split_url_events (events);
             filtered_events = filter_period_intersect(events, events);
+            filtered_events = filter_period_exclude(events, events);
+            union_events = period_union(events, events);
             filtered_events = filter_keyvals(events, "$category", [["Uncategorized"]]);
             chunked_events = chunk_events_by_key(events, "key");
             merged_events = merge_events_by_keys(events, ["key"]);
@@ -492,6 +495,26 @@ mod query_tests {
             RETURN = events;"#;
         let res = aw_query::query(&code, &interval, &ds);
         assert_err_type!(res, QueryError::RegexCompileError(_));
+
+        // Test rules with invalid fields
+        for rule in &[
+            r#"{ "type": "glob" }"#,
+            r#"{ "type": "regex", "regex": "test", "select_keys": "app" }"#,
+            r#"{ "type": "and", "rules": { "type": "none" } }"#,
+            r#"{ "type": "or", "rules": [false] }"#,
+            r#"{ "type": "not" }"#,
+            r#"{ "type": "none", "exclude": { "type": "rgex" } }"#,
+        ] {
+            let code = format!(
+                r#"
+                events = [];
+                events = tag(events, [["testtag", {}]]);
+                RETURN = events;"#,
+                rule
+            );
+            let res = aw_query::query(&code, &interval, &ds);
+            assert_err_type!(res, QueryError::InvalidFunctionParameters(_));
+        }
     }
 
     #[test]
@@ -647,4 +670,988 @@ mod query_tests {
             num => panic!("Expected number, got {:?}", num),
         };
     }
+
+    #[test]
+    fn test_check() {
+        // Valid query
+        let code = r#"
+            events = query_bucket(find_bucket("aw-watcher-window_"));
+            events = merge_events_by_keys(events, ["app"]);
+            if contains(events, 1) { n = 1; }
+            RETURN = sort_by_duration(events);"#;
+        let diagnostics = aw_query::check(&code);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        // All errors are reported at once, with the line they occurred on
+        let code = r#"
+            events = query_bucket(undefined_var);
+            events = flood(events, 5);
+            events = no_such_function(events);
+            n = sum_durations("not a list") + [1];
+            RETURN = events;"#;
+        let diagnostics = aw_query::check(&code);
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![2, 3, 4, 5, 5], "{:?}", diagnostics);
+        assert_eq!(
+            diagnostics[0].message,
+            r#"VariableNotDefined("undefined_var")"#
+        );
+        assert!(diagnostics[1]
+            .message
+            .starts_with("InvalidFunctionParameters"));
+        assert_eq!(
+            diagnostics[2].message,
+            r#"VariableNotDefined("no_such_function")"#
+        );
+        assert!(diagnostics[3]
+            .message
+            .starts_with("InvalidFunctionParameters"));
+        assert!(diagnostics[4].message.starts_with("InvalidType"));
+
+        // Calling something which is not a function
+        let diagnostics = aw_query::check("flood = 1; flood([]);");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, r#"InvalidType("flood")"#);
+
+        // Parsing errors
+        let diagnostics = aw_query::check("a = ;");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.starts_with("ParsingError"));
+    }
+
+    #[test]
+    fn test_profile() {
+        let ds = setup_datastore_populated();
+        let interval = TimeInterval::new_from_string(TIME_INTERVAL).unwrap();
+        let options = aw_query::QueryOptions {
+            profile: true,
+            ..Default::default()
+        };
+
+        let code = String::from(
+            "events = query_bucket(\"testid\");
+            events = flood(events);
+            RETURN = sum_durations(events);",
+        );
+        let (res, profile) = aw_query::query_with_options(&code, &interval, &ds, &options).unwrap();
+        assert_eq!(res, DataType::Number(0.0));
+        let profile = profile.unwrap();
+        let calls: Vec<&str> = profile.calls.iter().map(|c| c.function.as_str()).collect();
+        assert_eq!(calls, vec!["query_bucket", "flood", "sum_durations"]);
+
+        let query_bucket = &profile.calls[0];
+        assert_eq!(query_bucket.line, 1);
+        assert_eq!(query_bucket.input_events, 0);
+        assert_eq!(query_bucket.output_events, 2);
+        assert_eq!(query_bucket.rows_read, 2);
+
+        // The two identical events are merged by flood
+        let flood = &profile.calls[1];
+        assert_eq!(flood.line, 2);
+        assert_eq!(flood.input_events, 2);
+        assert_eq!(flood.output_events, 1);
+        assert_eq!(flood.rows_read, 0);
+
+        // Profile is only returned when enabled
+        let options = aw_query::QueryOptions::default();
+        let (_res, profile) =
+            aw_query::query_with_options(&code, &interval, &ds, &options).unwrap();
+        assert!(profile.is_none());
+    }
+
+    #[test]
+    fn test_params() {
+        let ds = setup_datastore_populated();
+        let interval = TimeInterval::new_from_string(TIME_INTERVAL).unwrap();
+        let mut options = aw_query::QueryOptions::default();
+        options.params.insert(
+            "bucket".to_string(),
+            DataType::from(&json!("testid\"); query_bucket(\"other")),
+        );
+        options
+            .params
+            .insert("keys".to_string(), DataType::from(&json!(["key"])));
+
+        // Params are ordinary variables, so quotes in them cannot change the query
+        let code = String::from("RETURN = query_bucket(bucket);");
+        match aw_query::query_with_options(&code, &interval, &ds, &options) {
+            Err(QueryError::BucketQueryError(_)) => (),
+            res => panic!("Expected BucketQueryError, got {:?}", res),
+        };
+
+        options.params.insert(
+            "bucket".to_string(),
+            DataType::from(&json!(BUCKET_ID.to_string())),
+        );
+        let code = String::from(
+            "events = query_bucket(bucket);
+            RETURN = merge_events_by_keys(events, keys);",
+        );
+        let (res, _) = aw_query::query_with_options(&code, &interval, &ds, &options).unwrap();
+        let events: Vec<Event> = Vec::try_from(&res).unwrap();
+        assert_eq!(events.len(), 1);
+
+        // JSON values are converted to the corresponding types
+        let value = json!({"n": 1, "b": true, "l": [null, "s"]});
+        let mut expected = std::collections::HashMap::new();
+        expected.insert("n".to_string(), DataType::Number(1.0));
+        expected.insert("b".to_string(), DataType::Bool(true));
+        expected.insert(
+            "l".to_string(),
+            DataType::List(vec![DataType::None(), DataType::String("s".to_string())]),
+        );
+        assert_eq!(DataType::from(&value), DataType::Dict(expected));
+
+        // Params cannot replace builtins or have names which cannot be referred to
+        for name in &["query_bucket", "TIMEINTERVAL", "not-a-var", "return"] {
+            let mut options = aw_query::QueryOptions::default();
+            options
+                .params
+                .insert(name.to_string(), DataType::Number(1.0));
+            match aw_query::query_with_options("RETURN = 1;", &interval, &ds, &options) {
+                Err(QueryError::InvalidParameter(_)) => (),
+                res => panic!("Expected InvalidParameter for {}, got {:?}", name, res),
+            };
+        }
+
+        // Params are known variables when checking a query
+        let code = "RETURN = query_bucket(bucket);";
+        assert_eq!(aw_query::check(code).len(), 1);
+        let mut params = std::collections::HashMap::new();
+        params.insert("bucket".to_string(), DataType::String("testid".to_string()));
+        assert!(aw_query::check_with_params(code, &params).is_empty());
+        params.insert("bucket".to_string(), DataType::Number(1.0));
+        assert_eq!(aw_query::check_with_params(code, &params).len(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let ds = setup_datastore_populated();
+        let interval = TimeInterval::new_from_string(TIME_INTERVAL).unwrap();
+        let code = String::from(
+            "events = query_bucket(\"testid\");
+            events = concat(events, events);
+            RETURN = events;",
+        );
+        let expect_limit_exceeded = |limits: aw_query::Limits, code: &str| {
+            let options = aw_query::QueryOptions {
+                limits,
+                ..Default::default()
+            };
+            match aw_query::query_with_options(code, &interval, &ds, &options) {
+                Err(QueryError::LimitExceeded(_)) => (),
+                res => panic!("Expected LimitExceeded, got {:?}", res),
+            };
+        };
+
+        // query_bucket materializes 2 events and concat 4 more
+        let (res, _) = aw_query::query_with_options(
+            &code,
+            &interval,
+            &ds,
+            &aw_query::QueryOptions {
+                limits: aw_query::Limits {
+                    max_events: Some(6),
+                    max_depth: Some(4),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let events: Vec<Event> = Vec::try_from(&res).unwrap();
+        assert_eq!(events.len(), 4);
+        expect_limit_exceeded(
+            aw_query::Limits {
+                max_events: Some(5),
+                ..Default::default()
+            },
+            &code,
+        );
+
+        // query_bucket only reads one event more than the limit allows
+        expect_limit_exceeded(
+            aw_query::Limits {
+                max_events: Some(1),
+                ..Default::default()
+            },
+            "RETURN = query_bucket(\"testid\");",
+        );
+        let options = aw_query::QueryOptions {
+            limits: aw_query::Limits {
+                max_events: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let code = "RETURN = query_bucket(\"testid\");";
+        let (res, _) = aw_query::query_with_options(code, &interval, &ds, &options).unwrap();
+        assert_eq!(Vec::<Event>::try_from(&res).unwrap().len(), 2);
+
+        expect_limit_exceeded(
+            aw_query::Limits {
+                max_depth: Some(3),
+                ..Default::default()
+            },
+            "RETURN = [[[1]]];",
+        );
+        // Nesting which would overflow the stack fails instead
+        let nested = format!("RETURN = {}1{};", "[".repeat(10_000), "]".repeat(10_000));
+        expect_limit_exceeded(
+            aw_query::Limits {
+                max_depth: Some(100),
+                ..Default::default()
+            },
+            &nested,
+        );
+        expect_limit_exceeded(
+            aw_query::Limits {
+                max_time: Some(std::time::Duration::from_secs(0)),
+                ..Default::default()
+            },
+            "RETURN = 1;",
+        );
+        expect_limit_exceeded(
+            aw_query::Limits {
+                max_output_size: Some(10),
+                ..Default::default()
+            },
+            "RETURN = \"a string which is too long\";",
+        );
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let ds = setup_datastore_empty();
+        let interval = TimeInterval::new_from_string(TIME_INTERVAL).unwrap();
+        let query_str = |code: &str| aw_query::query(code, &interval, &ds).unwrap();
+        let s = |s: &str| DataType::String(s.to_string());
+
+        assert_eq!(query_str(r#"return lower("Inbox");"#), s("inbox"));
+        assert_eq!(query_str(r#"return upper("Inbox");"#), s("INBOX"));
+        assert_eq!(query_str(r#"return trim("  Inbox ");"#), s("Inbox"));
+        assert_eq!(
+            query_str(r#"return split("a - b", " - ");"#),
+            DataType::List(vec![s("a"), s("b")])
+        );
+        assert_eq!(query_str(r#"return replace("a-b-c", "-", "");"#), s("abc"));
+        assert_eq!(
+            query_str(r#"return startswith("Inbox - Gmail", "Inbox");"#),
+            DataType::Bool(true)
+        );
+        assert_eq!(
+            query_str(r#"return regex_match("(3) Inbox", "^\(\d+\)");"#),
+            DataType::Bool(true)
+        );
+        assert_eq!(
+            query_str(r#"return regex_capture("Inbox - Gmail", "(\w+) - (\w+)|(x)");"#),
+            DataType::List(vec![
+                s("Inbox - Gmail"),
+                s("Inbox"),
+                s("Gmail"),
+                DataType::None()
+            ])
+        );
+        assert_eq!(
+            query_str(r#"return regex_capture("Inbox", "Gmail");"#),
+            DataType::None()
+        );
+        assert_eq!(
+            query_str(r#"return format("{}: {} ({})", "Inbox", 3, true);"#),
+            s("Inbox: 3 (true)")
+        );
+
+        match aw_query::query(r#"return format("{} {}", 1);"#, &interval, &ds) {
+            Err(QueryError::InvalidFunctionParameters(_)) => (),
+            res => panic!("Expected InvalidFunctionParameters, got {:?}", res),
+        };
+        match aw_query::query(r#"return regex_match("a", "(");"#, &interval, &ds) {
+            Err(QueryError::RegexCompileError(_)) => (),
+            res => panic!("Expected RegexCompileError, got {:?}", res),
+        };
+    }
+
+    #[test]
+    fn test_replace_keyvals() {
+        let ds = setup_datastore_populated();
+        let interval = TimeInterval::new_from_string(TIME_INTERVAL).unwrap();
+        let code = String::from(
+            r#"events = query_bucket("testid");
+            RETURN = replace_keyvals(events, "key", "^val", "new_val");"#,
+        );
+        let res = aw_query::query(&code, &interval, &ds).unwrap();
+        let events: Vec<Event> = Vec::try_from(&res).unwrap();
+        assert_eq!(events.len(), 2);
+        for event in events {
+            assert_eq!(event.data["key"], json!("new_value"));
+        }
+    }
+
+    #[test]
+    fn test_datetime_functions() {
+        let ds = setup_datastore_empty();
+        let interval =
+            TimeInterval::new_from_string("2020-01-03T00:00:00Z/2020-01-07T00:00:00Z").unwrap();
+        let dt = |s: &str| DataType::DateTime(chrono::DateTime::from_str(s).unwrap());
+
+        let code = "RETURN = [timeinterval_start(), timeinterval_end()];";
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        assert_eq!(
+            res,
+            DataType::List(vec![dt("2020-01-03T00:00:00Z"), dt("2020-01-07T00:00:00Z")])
+        );
+
+        // Arithmetic with seconds
+        let code = r#"
+            start = datetime("2020-01-03T01:00:00+01:00");
+            RETURN = [start + 60, start - 60, (start + 60) - start, start == timeinterval_start()];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        assert_eq!(
+            res,
+            DataType::List(vec![
+                dt("2020-01-03T00:01:00Z"),
+                dt("2020-01-02T23:59:00Z"),
+                DataType::Number(60.0),
+                DataType::Bool(true),
+            ])
+        );
+
+        // Days in Stockholm, which is one hour ahead of UTC in winter
+        let code = r#"RETURN = split_interval("day", "Europe/Stockholm");"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let days: Vec<Event> = Vec::try_from(&res).unwrap();
+        assert_eq!(days.len(), 5);
+        assert_eq!(days[0].duration, Duration::hours(23));
+        assert_eq!(days[1].timestamp.to_rfc3339(), "2020-01-03T23:00:00+00:00");
+        assert_eq!(days[1].data["date"], json!("2020-01-04"));
+        assert_eq!(days[4].duration, Duration::hours(1));
+
+        // Working hours on weekdays, 2020-01-04 and 2020-01-05 are a weekend
+        let code = r#"
+            working_hours = daily_periods("09:00", "17:00", [1, 2, 3, 4, 5], "Europe/Stockholm");
+            RETURN = working_hours;"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let periods: Vec<Event> = Vec::try_from(&res).unwrap();
+        let dates: Vec<_> = periods.iter().map(|e| e.data["date"].clone()).collect();
+        assert_eq!(dates, vec![json!("2020-01-03"), json!("2020-01-06")]);
+        assert_eq!(
+            periods[0].timestamp.to_rfc3339(),
+            "2020-01-03T08:00:00+00:00"
+        );
+        assert_eq!(periods[0].duration, Duration::hours(8));
+
+        for code in &[
+            r#"RETURN = split_interval("month");"#,
+            r#"RETURN = split_interval("day", "Mars/Olympus_Mons");"#,
+            r#"RETURN = daily_periods("9 o'clock", "17:00", [1]);"#,
+            r#"RETURN = daily_periods("09:00", "17:00", [8]);"#,
+            r#"RETURN = daily_periods("09:00", "17:00", [1.5]);"#,
+            // Out of the range of durations and of datetimes
+            "RETURN = timeinterval_start() + 100000000000000000000;",
+            "RETURN = timeinterval_start() - 10000000000000;",
+        ] {
+            match aw_query::query(code, &interval, &ds) {
+                Err(QueryError::InvalidFunctionParameters(_)) => (),
+                res => panic!("Expected InvalidFunctionParameters, got {:?}", res),
+            };
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:30:00Z").unwrap(),
+            duration: Duration::minutes(60),
+            data: json_map! {"key": json!("value")},
+        };
+        ds.insert_events(&BUCKET_ID, &[e1]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T03:00:00Z").unwrap();
+        let code = r#"
+            events = query_bucket("testid");
+            RETURN = histogram(events, "hour", "key", "UTC");"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let slots: Vec<DataType> = Vec::try_from(&res).unwrap();
+        assert_eq!(slots.len(), 3);
+        let slot = match &slots[0] {
+            DataType::Dict(d) => d,
+            _ => panic!("Expected slot to be a dict"),
+        };
+        assert_eq!(slot["duration"], DataType::Number(1800.0));
+        let mut totals = std::collections::HashMap::new();
+        totals.insert("value".to_string(), DataType::Number(1800.0));
+        assert_eq!(slot["totals"], DataType::Dict(totals));
+        assert_eq!(
+            slot["start"],
+            DataType::DateTime(chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap())
+        );
+        assert_eq!(
+            slot["end"],
+            DataType::DateTime(chrono::DateTime::from_str("2000-01-01T01:00:00Z").unwrap())
+        );
+
+        assert_err_type!(
+            aw_query::query(r#"RETURN = histogram([], "minute");"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
+
+    #[test]
+    fn test_group_by() {
+        let ds = setup_datastore_populated();
+        let interval = TimeInterval::new_from_string(TIME_INTERVAL).unwrap();
+        let code = r#"
+            events = query_bucket("testid");
+            RETURN = group_by(events, ["key"], ["count", "duration", "top:key:1"]);"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let groups = match res {
+            DataType::Dict(groups) => groups,
+            res => panic!("Expected a dict, got {:?}", res),
+        };
+        let group = match &groups[r#""value""#] {
+            DataType::Dict(group) => group,
+            res => panic!("Expected a dict, got {:?}", res),
+        };
+        assert_eq!(group["count"], DataType::Number(2.0));
+        assert_eq!(group["duration"], DataType::Number(0.0));
+        assert!(!group.contains_key("first"));
+        let mut values = std::collections::HashMap::new();
+        values.insert("key".to_string(), DataType::String("value".to_string()));
+        assert_eq!(group["values"], DataType::Dict(values));
+        let top = Vec::<DataType>::try_from(&group["top:key"]).unwrap();
+        assert_eq!(top.len(), 1);
+
+        // All statistics except top values by default
+        let code = r#"RETURN = group_by(query_bucket("testid"), ["key"]);"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let groups = match res {
+            DataType::Dict(groups) => groups,
+            res => panic!("Expected a dict, got {:?}", res),
+        };
+        match &groups[r#""value""#] {
+            DataType::Dict(group) => {
+                for key in &["values", "duration", "count", "first", "last", "longest"] {
+                    assert!(group.contains_key(*key), "{} missing", key);
+                }
+            }
+            res => panic!("Expected a dict, got {:?}", res),
+        };
+
+        assert_err_type!(
+            aw_query::query(
+                r#"RETURN = group_by([], ["key"], ["median"]);"#,
+                &interval,
+                &ds
+            ),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
+
+    #[test]
+    fn test_period_exclude_union() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"key": json!("value")},
+        };
+        ds.insert_events(&BUCKET_ID, &[e1]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        // Two AFK sources which each cover part of the event
+        let code = r#"
+            events = query_bucket("testid");
+            afk1 = split_interval("hour");
+            afk1 = filter_period_exclude(afk1, daily_periods("00:00:02", "00:00:03", [6]));
+            afk1 = filter_period_exclude(afk1, daily_periods("00:00:04", "01:00", [6]));
+            afk2 = filter_period_exclude(split_interval("hour"), daily_periods("00:00:06", "01:00", [6]));
+            afk = period_union(afk1, afk2);
+            RETURN = [sum_durations(afk), sum_durations(filter_period_exclude(events, afk))];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        assert_eq!(
+            res,
+            DataType::List(vec![DataType::Number(6.0), DataType::Number(4.0)])
+        );
+
+        let code = r#"RETURN = period_union();"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        assert_eq!(res, DataType::List(vec![]));
+    }
+
+    #[test]
+    fn test_sessionize() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"app": json!("firefox"), "title": json!("a")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = chrono::DateTime::from_str("2000-01-01T00:00:20Z").unwrap();
+        e2.data = json_map! {"app": json!("firefox"), "title": json!("b")};
+        let mut e3 = e1.clone();
+        e3.timestamp = chrono::DateTime::from_str("2000-01-01T00:10:00Z").unwrap();
+        ds.insert_events(&BUCKET_ID, &[e1, e2, e3]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            sessions = sessionize(query_bucket("testid"), 60);
+            RETURN = [sessions, sessionize(query_bucket("testid"), 60, "title")];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let res = Vec::<DataType>::try_from(&res).unwrap();
+        let sessions = Vec::<Event>::try_from(&res[0]).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].duration, Duration::seconds(30));
+        assert_eq!(sessions[0].data["switches"], json!(0));
+        assert_eq!(sessions[0].data["distinct"], json!(1));
+        let sessions = Vec::<Event>::try_from(&res[1]).unwrap();
+        assert_eq!(sessions[0].data["switches"], json!(1));
+        assert_eq!(sessions[0].data["distinct"], json!(2));
+
+        assert_err_type!(
+            aw_query::query(r#"RETURN = sessionize([], 0 - 1);"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+        assert_err_type!(
+            aw_query::query(
+                r#"RETURN = sessionize([], 10000000000000);"#,
+                &interval,
+                &ds
+            ),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
+
+    #[test]
+    fn test_categorize_rule_types() {
+        let ds = setup_datastore_with_bucket();
+        let interval = TimeInterval::new_from_string(TIME_INTERVAL).unwrap();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::Utc::now(),
+            duration: Duration::seconds(0),
+            data: json_map! {"app": json!("Code"), "title": json!("main.rs - aw-server")},
+        };
+        let mut e2 = e1.clone();
+        e2.data = json_map! {"app": json!("Firefox"), "title": json!("VS Code docs")};
+        ds.insert_events(&BUCKET_ID, &[e1, e2]).unwrap();
+
+        let code = r#"
+            events = sort_by_timestamp(query_bucket("testid"));
+            events = categorize(events, [
+                [["Editor"], { "type": "regex", "regex": "code", "ignore_case": true, "select_keys": ["app"] }],
+                [["Work", "Rust"], { "type": "and", "rules": [
+                    { "type": "glob", "glob": "*.rs - *", "select_keys": ["title"] },
+                    { "type": "not", "rule": { "type": "regex", "regex": "Firefox" } }
+                ] }],
+                [["Docs"], { "type": "or", "rules": [{ "type": "glob", "glob": "*DOCS", "ignore_case": true }], "exclude": { "type": "glob", "glob": "Code" } }]
+            ]);
+            RETURN = events;"#;
+        let result: DataType = aw_query::query(&code, &interval, &ds).unwrap();
+        let events: Vec<Event> = Vec::try_from(&result).unwrap();
+        assert_eq!(events[0].data["$category"], json!(["Work", "Rust"]));
+        assert_eq!(events[1].data["$category"], json!(["Docs"]));
+    }
+
+    #[test]
+    fn test_productivity() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::minutes(30),
+            data: json_map! {"app": json!("vim")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = chrono::DateTime::from_str("2000-01-01T00:30:00Z").unwrap();
+        e2.data = json_map! {"app": json!("reddit")};
+        let mut e3 = e1.clone();
+        e3.timestamp = chrono::DateTime::from_str("2000-01-01T01:00:00Z").unwrap();
+        e3.data = json_map! {"app": json!("vim")};
+        ds.insert_events(&BUCKET_ID, &[e1, e2, e3]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T02:00:00Z").unwrap();
+
+        let code = r#"
+            events = categorize(query_bucket("testid"), [
+                [["Work"], { "type": "regex", "regex": "vim" }, 2],
+                [["Media"], { "type": "regex", "regex": "reddit" }, 0 - 1]
+            ]);
+            RETURN = productivity(events, "hour");"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let slots: Vec<DataType> = Vec::try_from(&res).unwrap();
+        assert_eq!(slots.len(), 2);
+        let slot = match &slots[0] {
+            DataType::Dict(d) => d,
+            _ => panic!("Expected slot to be a dict"),
+        };
+        assert_eq!(slot["score"], DataType::Number(0.5));
+        assert_eq!(slot["productive"], DataType::Number(1800.0));
+        assert_eq!(slot["distracting"], DataType::Number(1800.0));
+        assert_eq!(slot["neutral"], DataType::Number(0.0));
+        match &slots[1] {
+            DataType::Dict(d) => assert_eq!(d["score"], DataType::Number(2.0)),
+            _ => panic!("Expected slot to be a dict"),
+        };
+
+        // Scores have to be numbers
+        let code = r#"RETURN = categorize([], [[["Work"], { "type": "none" }, "high"]]);"#;
+        assert_err_type!(
+            aw_query::query(code, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
+
+    #[test]
+    fn test_category_tree() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"app": json!("vim")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = chrono::DateTime::from_str("2000-01-01T00:00:10Z").unwrap();
+        e2.duration = Duration::seconds(5);
+        e2.data = json_map! {"app": json!("code")};
+        ds.insert_events(&BUCKET_ID, &[e1, e2]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            events = categorize(query_bucket("testid"), [
+                [["Work"], { "type": "regex", "regex": "code" }],
+                [["Work", "Vim"], { "type": "regex", "regex": "vim" }]
+            ]);
+            RETURN = category_tree(events, 1);"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let dict = |data: &DataType| match data {
+            DataType::Dict(d) => d.clone(),
+            data => panic!("Expected a dict, got {:?}", data),
+        };
+        let root = dict(&res);
+        assert_eq!(root["total"], DataType::Number(15.0));
+        assert_eq!(root["duration"], DataType::Number(0.0));
+        let work = dict(&dict(&root["children"])["Work"]);
+        assert_eq!(work["total"], DataType::Number(15.0));
+        assert_eq!(work["duration"], DataType::Number(5.0));
+        let top_events: Vec<Event> = Vec::try_from(&work["top_events"]).unwrap();
+        assert_eq!(top_events.len(), 1);
+        assert_eq!(top_events[0].data["app"], json!("vim"));
+        let vim = dict(&dict(&work["children"])["Vim"]);
+        assert_eq!(vim["total"], DataType::Number(10.0));
+        assert_eq!(
+            vim["children"],
+            DataType::Dict(std::collections::HashMap::new())
+        );
+    }
+
+    #[test]
+    fn test_chunk_events_by_key_subchunks() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"app": json!("firefox"), "title": json!("a")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = chrono::DateTime::from_str("2000-01-01T00:00:10Z").unwrap();
+        e2.data = json_map! {"app": json!("firefox"), "title": json!("b")};
+        ds.insert_events(&BUCKET_ID, &[e1, e2]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            events = sort_by_timestamp(query_bucket("testid"));
+            RETURN = chunk_events_by_key(events, "app", "title");"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let events: Vec<Event> = Vec::try_from(&res).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].duration, Duration::seconds(20));
+        assert_eq!(events[0].data["$subchunks"], json!({"a": 10.0, "b": 10.0}));
+    }
+
+    #[test]
+    fn test_resolve_overlaps() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(30),
+            data: json_map! {"url": json!("https://example.com")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = chrono::DateTime::from_str("2000-01-01T00:00:20Z").unwrap();
+        e2.data = json_map! {"url": json!("https://activitywatch.net")};
+        ds.insert_events(&BUCKET_ID, &[e1]).unwrap();
+        // A second watcher tracking the same thing
+        let bucket = Bucket {
+            bid: None,
+            id: "testid2".to_string(),
+            _type: "testtype".to_string(),
+            client: "testclient".to_string(),
+            hostname: "testhost".to_string(),
+            created: Some(chrono::Utc::now()),
+            data: json_map! {},
+            metadata: BucketMetadata::default(),
+            events: None,
+            last_updated: None,
+        };
+        ds.create_bucket(&bucket).unwrap();
+        ds.insert_events("testid2", &[e2]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            events = resolve_overlaps(query_bucket("testid"), query_bucket("testid2"));
+            RETURN = [sum_durations(events), events];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let res = Vec::<DataType>::try_from(&res).unwrap();
+        assert_eq!(res[0], DataType::Number(50.0));
+        let events = Vec::<Event>::try_from(&res[1]).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].duration, Duration::seconds(20));
+        assert_eq!(events[1].data["url"], json!("https://activitywatch.net"));
+    }
+
+    #[test]
+    fn test_redact() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"title": json!("Secret plans"), "url": json!("https://example.com/a")},
+        };
+        ds.insert_events(&BUCKET_ID, &[e1]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            events = query_bucket("testid");
+            events = categorize(events, [[["Work"], { "type": "regex", "regex": "Secret" }]]);
+            RETURN = [
+                redact(events, ["title"], "hash", "salt"),
+                redact(events, ["url"], "domain"),
+                redact(events, ["title", "url"], "category"),
+                redact(events, ["title"], "remove")
+            ];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let res: Vec<Vec<Event>> = Vec::<DataType>::try_from(&res)
+            .unwrap()
+            .iter()
+            .map(|events| Vec::<Event>::try_from(events).unwrap())
+            .collect();
+        assert_eq!(
+            res[0][0].data["title"],
+            json!("eefeb180fc3dd5848cfb98723d41b4a07f06e5982e3ff34b0265bd826d6d2c82")
+        );
+        assert_eq!(res[1][0].data["url"], json!("example.com"));
+        assert_eq!(res[2][0].data["title"], json!(["Work"]));
+        assert_eq!(res[2][0].data["url"], json!(["Work"]));
+        assert!(res[3][0].data.get("title").is_none());
+
+        // Hashing requires a salt
+        assert_err_type!(
+            aw_query::query(r#"RETURN = redact([], ["title"]);"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+        assert_err_type!(
+            aw_query::query(
+                r#"RETURN = redact([], ["title"], "rot13");"#,
+                &interval,
+                &ds
+            ),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
+
+    #[test]
+    fn test_split_url_events() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"url": json!("https://github.com/ActivityWatch/aw-server-rust/issues?q=is%3Aopen")},
+        };
+        ds.insert_events(&BUCKET_ID, &[e1]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            events = split_url_events(query_bucket("testid"));
+            RETURN = merge_events_by_keys(events, ["$repository"]);"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let events: Vec<Event> = Vec::try_from(&res).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].data["$repository"],
+            json!("ActivityWatch/aw-server-rust")
+        );
+
+        let code = r#"RETURN = split_url_events(query_bucket("testid"));"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let events: Vec<Event> = Vec::try_from(&res).unwrap();
+        assert_eq!(events[0].data["$query"], json!({"q": "is:open"}));
+        assert_eq!(
+            events[0].data["$path_segments"],
+            json!(["ActivityWatch", "aw-server-rust", "issues"])
+        );
+        assert_eq!(events[0].data["$registrable_domain"], json!("github.com"));
+    }
+
+    #[test]
+    fn test_resample() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::minutes(4),
+            data: json_map! {"app": json!("firefox")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = chrono::DateTime::from_str("2000-01-01T00:04:00Z").unwrap();
+        e2.duration = Duration::minutes(7);
+        e2.data = json_map! {"app": json!("vim")};
+        ds.insert_events(&BUCKET_ID, &[e1, e2]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            events = resample(query_bucket("testid"), 300, "app");
+            RETURN = [sum_durations(events), events];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let res = Vec::<DataType>::try_from(&res).unwrap();
+        assert_eq!(res[0], DataType::Number(660.0));
+        let events: Vec<Event> = Vec::try_from(&res[1]).unwrap();
+        let apps: Vec<&serde_json::Value> = events.iter().map(|e| &e.data["app"]).collect();
+        assert_eq!(apps, vec!["firefox", "vim", "vim"]);
+
+        assert_err_type!(
+            aw_query::query(r#"RETURN = resample([], 0);"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+        assert_err_type!(
+            aw_query::query(r#"RETURN = resample([], 10000000000000);"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+        // An hour of 1ms slots is more than the max amount of slots
+        assert_err_type!(
+            aw_query::query(r#"RETURN = resample([], 0.001);"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
+
+    #[test]
+    fn test_split_by_day() {
+        let ds = setup_datastore_with_bucket();
+        // From 23:00 to 05:00 in Stockholm
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T22:00:00Z").unwrap(),
+            duration: Duration::hours(6),
+            data: json_map! {"status": json!("afk")},
+        };
+        ds.insert_events(&BUCKET_ID, &[e1]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-03T00:00:00Z").unwrap();
+
+        let code = r#"
+            events = split_by_day(query_bucket("testid"), "Europe/Stockholm");
+            late_days = split_by_day(query_bucket("testid"), "Europe/Stockholm", 4);
+            RETURN = [events, late_days];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let res = Vec::<DataType>::try_from(&res).unwrap();
+        let events: Vec<Event> = Vec::try_from(&res[0]).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].duration, Duration::hours(1));
+        assert_eq!(events[1].duration, Duration::hours(5));
+        assert_eq!(events[1].data, events[0].data);
+        let late_days: Vec<Event> = Vec::try_from(&res[1]).unwrap();
+        assert_eq!(late_days.len(), 2);
+        assert_eq!(late_days[0].duration, Duration::hours(5));
+        assert_eq!(late_days[1].duration, Duration::hours(1));
+
+        assert_err_type!(
+            aw_query::query(
+                r#"RETURN = split_by_day([], "Mars/Olympus_Mons");"#,
+                &interval,
+                &ds
+            ),
+            QueryError::InvalidFunctionParameters(_)
+        );
+        assert_err_type!(
+            aw_query::query(
+                r#"RETURN = split_by_day([], "UTC", 1000000000000);"#,
+                &interval,
+                &ds
+            ),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let ds = setup_datastore_with_bucket();
+        let e1 = Event {
+            id: None,
+            timestamp: chrono::DateTime::from_str("2000-01-01T00:10:00Z").unwrap(),
+            duration: Duration::minutes(20),
+            data: json_map! {"app": json!("vim")},
+        };
+        let mut afk = e1.clone();
+        afk.timestamp = chrono::DateTime::from_str("2000-01-01T00:30:00Z").unwrap();
+        afk.duration = Duration::minutes(30);
+        afk.data = json_map! {"status": json!("afk")};
+        ds.insert_events(&BUCKET_ID, &[e1]).unwrap();
+        let bucket = Bucket {
+            bid: None,
+            id: "testid2".to_string(),
+            _type: "afkstatus".to_string(),
+            client: "testclient".to_string(),
+            hostname: "testhost".to_string(),
+            created: Some(chrono::Utc::now()),
+            data: json_map! {},
+            metadata: BucketMetadata::default(),
+            events: None,
+            last_updated: None,
+        };
+        ds.create_bucket(&bucket).unwrap();
+        ds.insert_events("testid2", &[afk]).unwrap();
+        let interval =
+            TimeInterval::new_from_string("2000-01-01T00:00:00Z/2000-01-01T01:00:00Z").unwrap();
+
+        let code = r#"
+            events = query_bucket("testid");
+            afk = query_bucket("testid2");
+            RETURN = [find_gaps(events, 900), find_gaps(events, 60, afk)];"#;
+        let res = aw_query::query(code, &interval, &ds).unwrap();
+        let res = Vec::<DataType>::try_from(&res).unwrap();
+        let gaps: Vec<Event> = Vec::try_from(&res[0]).unwrap();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].duration, Duration::minutes(30));
+        assert_eq!(gaps[0].data, json_map! {"$gap": json!(true)});
+        let gaps: Vec<Event> = Vec::try_from(&res[1]).unwrap();
+        assert_eq!(gaps.len(), 2);
+        assert!(gaps[0].data.get("status").is_none());
+        assert_eq!(gaps[1].data["status"], json!("afk"));
+
+        assert_err_type!(
+            aw_query::query(r#"RETURN = find_gaps([], 0 - 1);"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+        assert_err_type!(
+            aw_query::query(r#"RETURN = find_gaps([], 10000000000000);"#, &interval, &ds),
+            QueryError::InvalidFunctionParameters(_)
+        );
+    }
 }
@@ -5,7 +5,6 @@ extern crate android_logger;
 
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
-use std::sync::Mutex;
 
 use crate::dirs;
 
@@ -104,10 +103,7 @@ pub mod android {
         let asset_path = jstring_to_string(&env, java_asset_path);
         info!("Using asset dir: {}", asset_path);
 
-        let server_state = endpoints::ServerState {
-            datastore: Mutex::new(openDatastore()),
-            asset_path: PathBuf::from(asset_path),
-        };
+        let server_state = endpoints::ServerState::new(openDatastore(), PathBuf::from(asset_path));
 
         let mut config = AWConfig::default();
         config.port = 5600;
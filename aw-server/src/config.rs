@@ -1,8 +1,10 @@
 use rocket::config::{Config, Environment, Limits};
 use std::fs::File;
 use std::io::{Read, Write};
+use std::time::Duration;
 
 use crate::dirs;
+use crate::triggers::Trigger;
 
 /* Far from an optimal way to solve it, but works and is simple */
 static mut TESTING: bool = true;
@@ -15,6 +17,10 @@ pub fn is_testing() -> bool {
     unsafe { TESTING }
 }
 
+/// Max seconds between evaluations of the triggers, periods are at most a week long and their
+/// current day should not be missed
+pub const MAX_TRIGGER_INTERVAL: f64 = 86400.0;
+
 #[derive(Serialize, Deserialize)]
 pub struct AWConfig {
     #[serde(default = "default_address")]
@@ -25,6 +31,14 @@ pub struct AWConfig {
     pub testing: bool, // This is not written to the config file (serde(skip))
     #[serde(default = "default_cors")]
     pub cors: Vec<String>,
+    /// Seconds between evaluations of the triggers, between 1 and MAX_TRIGGER_INTERVAL
+    #[serde(default = "default_trigger_interval")]
+    pub trigger_interval: f64,
+    // Tables need to be the last fields as toml requires tables to come after all values
+    #[serde(default)]
+    pub query_limits: QueryLimits,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<Trigger>,
 }
 
 impl Default for AWConfig {
@@ -34,11 +48,72 @@ impl Default for AWConfig {
             port: default_port(),
             testing: default_testing(),
             cors: default_cors(),
+            trigger_interval: default_trigger_interval(),
+            query_limits: QueryLimits::default(),
+            triggers: Vec::new(),
+        }
+    }
+}
+
+/// Limits for queries made through the API, a value of 0 disables the limit
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct QueryLimits {
+    /// Max wall time of a query in seconds
+    pub max_time: f64,
+    pub max_events: usize,
+    /// Max nesting of expressions, deeper nesting could overflow the stack of the server
+    pub max_depth: usize,
+    /// Max size of the result of a query in bytes
+    pub max_output_size: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> QueryLimits {
+        QueryLimits {
+            max_time: 60.0,
+            max_events: 10_000_000,
+            max_depth: 100,
+            max_output_size: 500_000_000,
+        }
+    }
+}
+
+impl QueryLimits {
+    pub fn to_query_limits(&self) -> aw_query::Limits {
+        fn nonzero(n: usize) -> Option<usize> {
+            if n == 0 {
+                None
+            } else {
+                Some(n)
+            }
+        }
+        aw_query::Limits {
+            max_time: if self.max_time > 0.0 {
+                Some(Duration::from_secs_f64(self.max_time))
+            } else {
+                None
+            },
+            max_events: nonzero(self.max_events),
+            max_depth: nonzero(self.max_depth),
+            max_output_size: nonzero(self.max_output_size),
         }
     }
 }
 
 impl AWConfig {
+    /// Checks the values which are not restricted by their type
+    pub fn validate(&self) -> Result<(), String> {
+        // Also rejects NaN
+        if !(1.0..=MAX_TRIGGER_INTERVAL).contains(&self.trigger_interval) {
+            return Err(format!(
+                "trigger_interval has to be between 1 and {} seconds, got {}",
+                MAX_TRIGGER_INTERVAL, self.trigger_interval
+            ));
+        }
+        Ok(())
+    }
+
     pub fn to_rocket_config(&self) -> rocket::Config {
         let env = if self.testing {
             Environment::Production
@@ -66,6 +141,10 @@ fn default_cors() -> Vec<String> {
     Vec::<String>::new()
 }
 
+fn default_trigger_interval() -> f64 {
+    60.0
+}
+
 fn default_testing() -> bool {
     is_testing()
 }
@@ -113,6 +192,9 @@ pub fn create_config(testing: bool) -> AWConfig {
         .read_to_string(&mut content)
         .expect("Failed to read config as a string");
     let aw_config: AWConfig = toml::from_str(&content).expect("Failed to parse config file");
+    if let Err(e) = aw_config.validate() {
+        panic!("Invalid config file: {}", e);
+    }
 
     aw_config
 }
@@ -4,7 +4,9 @@ use std::io::Cursor;
 use rocket_contrib::json::Json;
 
 use chrono::DateTime;
+use chrono::Duration;
 use chrono::Utc;
+use chrono_tz::Tz;
 
 use aw_models::Bucket;
 use aw_models::BucketsExport;
@@ -16,9 +18,11 @@ use rocket::response::status;
 use rocket::response::Response;
 use rocket::State;
 
+use crate::endpoints::export::redact_export;
 use crate::endpoints::ServerState;
 
 use aw_datastore::DatastoreError;
+use aw_transform::timeslots;
 
 #[get("/")]
 pub fn buckets_get(state: State<ServerState>) -> Result<Json<HashMap<String, Bucket>>, Status> {
@@ -82,12 +86,14 @@ pub fn bucket_new(
     }
 }
 
-#[get("/<bucket_id>/events?<start>&<end>&<limit>")]
+#[get("/<bucket_id>/events?<start>&<end>&<limit>&<tz>&<day_offset>")]
 pub fn bucket_events_get(
     bucket_id: String,
     start: Option<String>,
     end: Option<String>,
     limit: Option<u64>,
+    tz: Option<String>,
+    day_offset: Option<f64>,
     state: State<ServerState>,
 ) -> Result<Json<Vec<Event>>, Status> {
     let starttime: Option<DateTime<Utc>> = match start {
@@ -116,8 +122,37 @@ pub fn bucket_events_get(
         },
         None => None,
     };
+    // Events are split at local day boundaries if a timezone or day offset (in hours) is given
+    let split_days: Option<(Tz, Duration)> = match (tz, day_offset) {
+        (None, None) => None,
+        (tz, day_offset) => {
+            let tz: Tz = match tz {
+                Some(tz) => match tz.parse() {
+                    Ok(tz) => tz,
+                    Err(e) => {
+                        warn!("Failed to parse timezone: {}", e);
+                        return Err(Status::BadRequest);
+                    }
+                },
+                None => Tz::UTC,
+            };
+            let offset = match timeslots::day_offset(day_offset.unwrap_or(0.0)) {
+                Ok(offset) => offset,
+                Err(e) => {
+                    warn!("Invalid day offset: {}", e);
+                    return Err(Status::BadRequest);
+                }
+            };
+            Some((tz, offset))
+        }
+    };
     let datastore = endpoints_get_lock!(state.datastore);
-    let res = datastore.get_events(&bucket_id, starttime, endtime, limit);
+    let res = match split_days {
+        Some((tz, offset)) => {
+            datastore.get_events_split_by_day(&bucket_id, starttime, endtime, limit, &tz, offset)
+        }
+        None => datastore.get_events(&bucket_id, starttime, endtime, limit),
+    };
     match res {
         Ok(events) => Ok(Json(events)),
         Err(err) => match err {
@@ -209,8 +244,14 @@ pub fn bucket_events_delete_by_id(
     }
 }
 
-#[get("/<bucket_id>/export")]
-pub fn bucket_export(bucket_id: String, state: State<ServerState>) -> Result<Response, Status> {
+#[get("/<bucket_id>/export?<redact>&<method>&<salt>")]
+pub fn bucket_export(
+    bucket_id: String,
+    redact: Option<String>,
+    method: Option<String>,
+    salt: Option<String>,
+    state: State<ServerState>,
+) -> Result<Response, Status> {
     let datastore = endpoints_get_lock!(state.datastore);
     let mut export = BucketsExport {
         buckets: HashMap::new(),
@@ -231,6 +272,7 @@ pub fn bucket_export(bucket_id: String, state: State<ServerState>) -> Result<Res
             .expect("Failed to get events for bucket"),
     );
     export.buckets.insert(bucket_id.clone(), bucket);
+    redact_export(&mut export, redact, method, salt)?;
     let filename = format!("aw-bucket-export_{}.json", bucket_id);
 
     let header_content = format!("attachment; filename={}", filename);
@@ -6,12 +6,57 @@ use rocket::http::Status;
 use rocket::response::Response;
 use rocket::State;
 
+use uuid::Uuid;
+
 use aw_models::BucketsExport;
+use aw_transform::Redaction;
 
 use crate::endpoints::ServerState;
 
-#[get("/")]
-pub fn buckets_export(state: State<ServerState>) -> Result<Response, Status> {
+/// Redacts the events of an export for sharing
+///
+/// redact is a comma separated list of data keys, method is one of the methods of
+/// aw_transform::Redaction and defaults to hash. Nothing is redacted if no keys are given. If no
+/// salt is given for hashing a random one is used, which is not part of the export, so hashes can
+/// only be compared within the same export.
+pub fn redact_export(
+    export: &mut BucketsExport,
+    redact: Option<String>,
+    method: Option<String>,
+    salt: Option<String>,
+) -> Result<(), Status> {
+    let keys: Vec<String> = match redact {
+        Some(redact) => redact
+            .split(',')
+            .filter(|key| !key.is_empty())
+            .map(|key| key.to_string())
+            .collect(),
+        None => return Ok(()),
+    };
+    let method = method.unwrap_or_else(|| "hash".to_string());
+    let salt = salt.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let redaction = match Redaction::from_method(&method, Some(&salt)) {
+        Ok(redaction) => redaction,
+        Err(err) => {
+            warn!("Invalid export redaction: {}", err);
+            return Err(Status::BadRequest);
+        }
+    };
+    for bucket in export.buckets.values_mut() {
+        if let Some(events) = bucket.events.take() {
+            bucket.events = Some(aw_transform::redact(events, &keys, &redaction));
+        }
+    }
+    Ok(())
+}
+
+#[get("/?<redact>&<method>&<salt>")]
+pub fn buckets_export(
+    redact: Option<String>,
+    method: Option<String>,
+    salt: Option<String>,
+    state: State<ServerState>,
+) -> Result<Response, Status> {
     let datastore = endpoints_get_lock!(state.datastore);
     let mut export = BucketsExport {
         buckets: HashMap::new(),
@@ -25,6 +70,7 @@ pub fn buckets_export(state: State<ServerState>) -> Result<Response, Status> {
         );
         export.buckets.insert(bid, bucket);
     }
+    redact_export(&mut export, redact, method, salt)?;
 
     Ok(Response::build()
         .status(Status::Ok)
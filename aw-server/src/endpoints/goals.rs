@@ -0,0 +1,81 @@
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::State;
+use rocket_contrib::json::JsonValue;
+
+use aw_datastore::DatastoreError;
+use aw_query::QueryOptions;
+
+use crate::config::AWConfig;
+use crate::endpoints::ServerState;
+use crate::goals::{evaluate_goals, parse_goals, GOALS_SETTING};
+
+/// Max amount of previous periods which can be requested
+const MAX_HISTORY: usize = 366;
+
+fn error(status: Status, message: String) -> status::Custom<JsonValue> {
+    status::Custom(
+        status,
+        json!({
+            "status": status.code,
+            "reason": status.reason,
+            "message": message,
+        }),
+    )
+}
+
+/// Returns the progress of the goals in the goals setting for the current period and the
+/// history previous periods, 7 by default
+#[get("/?<history>")]
+pub fn goals_get(
+    history: Option<usize>,
+    state: State<ServerState>,
+    config: State<AWConfig>,
+) -> status::Custom<JsonValue> {
+    let history = history.unwrap_or(7);
+    if history > MAX_HISTORY {
+        return error(
+            Status::BadRequest,
+            format!("History can be at most {} periods", MAX_HISTORY),
+        );
+    }
+    let (value, datastore) = match state.datastore.lock() {
+        Ok(ds) => {
+            let value = match ds.get_key_value(GOALS_SETTING) {
+                Ok(kv) => kv.value,
+                Err(DatastoreError::NoSuchKey) => return status::Custom(Status::Ok, json!([])),
+                Err(e) => {
+                    warn!("Failed to get goals setting: {:?}", e);
+                    return error(Status::InternalServerError, format!("{:?}", e));
+                }
+            };
+            match ds.parallel_reader() {
+                Ok(reader) => (value, reader),
+                Err(e) => return error(Status::InternalServerError, format!("{:?}", e)),
+            }
+        }
+        Err(e) => {
+            warn!("Taking datastore lock failed, returning 504: {}", e);
+            return error(
+                Status::ServiceUnavailable,
+                "Taking datastore lock failed, see aw-server logs".to_string(),
+            );
+        }
+    };
+    let goals_config = match parse_goals(&value) {
+        Ok(goals_config) => goals_config,
+        Err(message) => return error(Status::InternalServerError, message),
+    };
+    let options = QueryOptions {
+        limits: config.query_limits.to_query_limits(),
+        ..QueryOptions::default()
+    };
+    match evaluate_goals(&goals_config, Utc::now(), history, &datastore, &options) {
+        Ok(statuses) => status::Custom(Status::Ok, json!(statuses)),
+        Err(message) => {
+            warn!("Failed to evaluate goals: {}", message);
+            error(Status::InternalServerError, message)
+        }
+    }
+}
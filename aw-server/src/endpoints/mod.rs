@@ -1,6 +1,6 @@
 use std::fs;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard};
 
 use gethostname::gethostname;
 use rocket::response::NamedFile;
@@ -10,6 +10,7 @@ use uuid::Uuid;
 
 use crate::config::AWConfig;
 use crate::dirs;
+use crate::query_cache::QueryCache;
 
 #[macro_export]
 macro_rules! endpoints_get_lock {
@@ -27,6 +28,7 @@ macro_rules! endpoints_get_lock {
 mod bucket;
 mod cors;
 mod export;
+mod goals;
 mod import;
 mod query;
 mod settings;
@@ -35,9 +37,31 @@ use aw_datastore::Datastore;
 
 pub struct ServerState {
     pub datastore: Mutex<Datastore>,
+    pub query_cache: Mutex<QueryCache>,
     pub asset_path: PathBuf,
 }
 
+impl ServerState {
+    pub fn new(datastore: Datastore, asset_path: PathBuf) -> ServerState {
+        ServerState {
+            datastore: Mutex::new(datastore),
+            query_cache: Mutex::new(QueryCache::new()),
+            asset_path,
+        }
+    }
+}
+
+/// Takes the query cache lock
+///
+/// The cache only contains plain data, so it is still consistent even if a thread panicked while
+/// holding the lock.
+fn query_cache_lock(state: &ServerState) -> MutexGuard<'_, QueryCache> {
+    match state.query_cache.lock() {
+        Ok(cache) => cache,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
 #[get("/")]
 fn root_index(state: State<ServerState>) -> Option<NamedFile> {
     NamedFile::open(state.asset_path.join("index.html")).ok()
@@ -150,12 +174,13 @@ pub fn build_rocket(server_state: ServerState, config: AWConfig) -> rocket::Rock
                 bucket::bucket_export
             ],
         )
-        .mount("/api/0/query", routes![query::query])
+        .mount("/api/0/query", routes![query::query, query::query_check])
         .mount(
             "/api/0/import",
             routes![import::bucket_import_json, import::bucket_import_form],
         )
         .mount("/api/0/export", routes![export::buckets_export])
+        .mount("/api/0/goals", routes![goals::goals_get])
         .mount(
             "/api/0/settings",
             routes![
@@ -1,12 +1,20 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::State;
 use rocket_contrib::json::{Json, JsonValue};
+use serde_json::{Map, Value};
 
-use aw_models::Query;
+use aw_datastore::Datastore;
+use aw_models::{Query, TimeInterval};
 
-use crate::endpoints::ServerState;
-use aw_query::QueryError;
+use crate::config::AWConfig;
+use crate::endpoints::{query_cache_lock, ServerState};
+use aw_query::{Diagnostic, Profile, QueryError, QueryOptions};
 
 #[derive(Serialize)]
 struct QueryErrorJson {
@@ -21,6 +29,16 @@ fn ok(data: Vec<aw_query::DataType>) -> status::Custom<JsonValue> {
     status::Custom(Status::Ok, json!(data))
 }
 
+fn ok_profiled(data: Vec<aw_query::DataType>, profiles: Vec<Profile>) -> status::Custom<JsonValue> {
+    status::Custom(
+        Status::Ok,
+        json!({
+            "result": data,
+            "profile": profiles,
+        }),
+    )
+}
+
 fn error(err: QueryError) -> status::Custom<JsonValue> {
     let body = QueryErrorJson {
         status: 500,
@@ -30,34 +48,203 @@ fn error(err: QueryError) -> status::Custom<JsonValue> {
     status::Custom(Status::InternalServerError, json!(body))
 }
 
+/// Runs the query for each of the timeperiods
+///
+/// Responds with a list of the results, in the order of the timeperiods. If profile is set in
+/// the request the response is an object instead, which has that list as "result" and a list
+/// with the profile of each timeperiod in the same order as "profile":
+///
+/// ```json
+/// {
+///     "result": [[...]],
+///     "profile": [{"time": 0.01, "calls": [{"function": "query_bucket", "line": 1, "time": 0.01,
+///                  "input_events": 0, "output_events": 10, "rows_read": 10}]}]
+/// }
+/// ```
 #[post("/", data = "<query_req>")]
-pub fn query(query_req: Json<Query>, state: State<ServerState>) -> status::Custom<JsonValue> {
+pub fn query(
+    query_req: Json<Query>,
+    state: State<ServerState>,
+    config: State<AWConfig>,
+) -> status::Custom<JsonValue> {
     let query_code = query_req.0.query.join("\n");
     let intervals = &query_req.0.timeperiods;
-    let mut results = Vec::new();
-    for interval in intervals {
-        // Cannot re-use endpoints_get_lock!() here because it returns Err(Status) on failure and this
-        // function returns status::Custom
-        let datastore = match state.datastore.lock() {
-            Ok(ds) => ds,
+    let options = QueryOptions {
+        profile: query_req.0.profile,
+        params: params_to_datatypes(&query_req.0.params),
+        limits: config.query_limits.to_query_limits(),
+    };
+    // Results depend on the params as well, so they are part of the cache key
+    let cache_key = format!(
+        "{}\n{}",
+        query_code,
+        Value::Object(query_req.0.params.clone())
+    );
+    // Cannot re-use endpoints_get_lock!() here because it returns Err(Status) on failure and this
+    // function returns status::Custom
+    let datastore = match state.datastore.lock() {
+        Ok(ds) => ds.clone(),
+        Err(e) => {
+            warn!("Taking datastore lock failed, returning 500: {}", e);
+            let body = QueryErrorJson {
+                status: 504,
+                reason: "Service Unavailable".to_string(),
+                message: "Taking datastore lock failed, see aw-server logs".to_string(),
+            };
+            return status::Custom(Status::ServiceUnavailable, json!(body));
+        }
+    };
+    let mut results: Vec<Option<Result<QueryResult, QueryError>>> =
+        intervals.iter().map(|_| None).collect();
+    let mut jobs = Vec::new();
+    for (i, interval) in intervals.iter().enumerate() {
+        // Profiled queries are always executed as the profile would otherwise be missing. The
+        // versions have to be retrieved before running the query so writes made during the query
+        // invalidate the result.
+        let cache_versions = if options.profile {
+            None
+        } else {
+            let versions = datastore.write_versions(*interval.start(), *interval.end());
+            if let Some(result) = query_cache_lock(&state).get(&cache_key, &interval, &versions) {
+                results[i] = Some(Ok((result, None)));
+                continue;
+            }
+            Some(versions)
+        };
+        jobs.push((i, interval.clone(), cache_versions));
+    }
+    if !jobs.is_empty() {
+        let datastore = match datastore.parallel_reader() {
+            Ok(reader) => reader,
             Err(e) => {
-                warn!("Taking datastore lock failed, returning 500: {}", e);
-                let body = QueryErrorJson {
-                    status: 504,
-                    reason: "Service Unavailable".to_string(),
-                    message: "Taking datastore lock failed, see aw-server logs".to_string(),
-                };
-                return status::Custom(Status::ServiceUnavailable, json!(body));
+                return error(QueryError::BucketQueryError(format!(
+                    "Failed to prepare datastore for reading: {:?}",
+                    e
+                )))
             }
         };
-        let result = match aw_query::query(&query_code, &interval, &datastore) {
-            Ok(data) => data,
+        let job_intervals = jobs.iter().map(|job| job.1.clone()).collect();
+        let finished = run_parallel(&query_code, job_intervals, datastore, options.clone());
+        for (job, (result, buckets_read)) in jobs.into_iter().zip(finished) {
+            let (i, interval, cache_versions) = job;
+            if let (Some(versions), Ok((data, _))) = (&cache_versions, &result) {
+                query_cache_lock(&state).insert(
+                    &cache_key,
+                    &interval,
+                    versions,
+                    &buckets_read,
+                    data.clone(),
+                );
+            }
+            results[i] = Some(result);
+        }
+    }
+    let mut data = Vec::new();
+    let mut profiles = Vec::new();
+    for result in results {
+        match result.unwrap() {
+            Ok((result, profile)) => {
+                data.push(result);
+                if let Some(profile) = profile {
+                    profiles.push(profile);
+                }
+            }
             Err(e) => {
                 warn!("Query failed: {:?}", e);
                 return error(e);
             }
-        };
-        results.push(result);
+        }
+    }
+    if options.profile {
+        ok_profiled(data, profiles)
+    } else {
+        ok(data)
     }
-    ok(results)
+}
+
+/// Max amount of timeperiods of a single request which are queried at the same time
+const MAX_PARALLEL_TIMEPERIODS: usize = 4;
+
+type QueryResult = (aw_query::DataType, Option<Profile>);
+
+/// Result of a query together with the buckets it read
+type JobResult = (Result<QueryResult, QueryError>, HashSet<String>);
+
+/// Runs the query for each interval, using at most MAX_PARALLEL_TIMEPERIODS threads
+///
+/// Returns the results in the same order as the intervals, together with the buckets each
+/// query read.
+fn run_parallel(
+    query_code: &str,
+    intervals: Vec<TimeInterval>,
+    datastore: Datastore,
+    options: QueryOptions,
+) -> Vec<JobResult> {
+    let job_count = intervals.len();
+    let query_code = Arc::new(query_code.to_string());
+    let options = Arc::new(options);
+    let queue = Arc::new(Mutex::new(intervals.into_iter().enumerate()));
+    let (sender, receiver) = mpsc::channel();
+    let mut threads = Vec::new();
+    for _ in 0..std::cmp::min(job_count, MAX_PARALLEL_TIMEPERIODS) {
+        let query_code = query_code.clone();
+        let options = options.clone();
+        let queue = queue.clone();
+        let sender = sender.clone();
+        let datastore = datastore.clone();
+        threads.push(thread::spawn(move || loop {
+            let next = queue.lock().unwrap().next();
+            let (i, interval) = match next {
+                Some(job) => job,
+                None => break,
+            };
+            let datastore = datastore.with_read_tracking();
+            let result = aw_query::query_with_options(&query_code, &interval, &datastore, &options);
+            sender
+                .send((i, (result, datastore.buckets_read())))
+                .unwrap();
+        }));
+    }
+    drop(sender);
+    let mut results: Vec<Option<JobResult>> = (0..job_count).map(|_| None).collect();
+    for (i, result) in receiver {
+        results[i] = Some(result);
+    }
+    for thread in threads {
+        if thread.join().is_err() {
+            warn!("Query thread panicked");
+        }
+    }
+    results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| {
+                let error = QueryError::BucketQueryError(
+                    "Query thread panicked, see aw-server logs".to_string(),
+                );
+                (Err(error), HashSet::new())
+            })
+        })
+        .collect()
+}
+
+fn params_to_datatypes(params: &Map<String, Value>) -> HashMap<String, aw_query::DataType> {
+    params
+        .iter()
+        .map(|(name, value)| (name.to_string(), aw_query::DataType::from(value)))
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct QueryCheck {
+    query: Vec<String>,
+    #[serde(default)]
+    params: Map<String, Value>,
+}
+
+#[post("/check", data = "<check_req>")]
+pub fn query_check(check_req: Json<QueryCheck>) -> Json<Vec<Diagnostic>> {
+    let query_code = check_req.0.query.join("\n");
+    let params = params_to_datatypes(&check_req.0.params);
+    Json(aw_query::check_with_params(&query_code, &params))
 }
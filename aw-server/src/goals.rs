@@ -0,0 +1,203 @@
+//! Goals for the time spent in categories, such as at most 1h per day in "Social"
+//!
+//! Goals are stored as JSON in the "goals" setting together with a query which returns the
+//! events categorized with categorize(). The query is run once for every distinct period of the
+//! goals and the durations of the events in the category of a goal, or any of its subcategories,
+//! are summed.
+//!
+//! ```json
+//! {
+//!     "query": ["events = flood(query_bucket(find_bucket(\"aw-watcher-window_\")));",
+//!               "RETURN = categorize(events, [[[\"Social\"], {\"type\": \"regex\", \"regex\": \"Twitter\"}]]);"],
+//!     "goals": [{"name": "Less social media", "category": ["Social"], "at_most": 3600, "period": "day"}]
+//! }
+//! ```
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde_json::Value;
+
+use aw_datastore::Datastore;
+use aw_models::{Event, TimeInterval};
+use aw_query::QueryOptions;
+use aw_transform::timeslots::{self, next_slot_start, slot_start, Resolution};
+
+/// Key of the setting which contains the goals
+pub const GOALS_SETTING: &str = "settings.goals";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct GoalsConfig {
+    /// Query which returns categorized events, lines are joined like in /api/0/query
+    pub query: Vec<String>,
+    pub goals: Vec<Goal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Goal {
+    pub name: String,
+    /// Category as set by categorize, an empty category contains all events
+    pub category: Vec<String>,
+    /// Minimum time in seconds per period, either this or at_most has to be set
+    #[serde(default)]
+    pub at_least: Option<f64>,
+    /// Maximum time in seconds per period
+    #[serde(default)]
+    pub at_most: Option<f64>,
+    /// Length of the periods, "hour", "day" or "week"
+    pub period: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    /// Hours after midnight when days and weeks start
+    #[serde(default)]
+    pub day_offset: f64,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+/// Time spent in the category of a goal within a period
+#[derive(Serialize, Clone, Debug)]
+pub struct GoalPeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Seconds spent in the category
+    pub duration: f64,
+    /// Duration relative to the target of the goal
+    pub progress: f64,
+    /// Whether the goal is met, the current period can still change
+    pub met: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GoalStatus {
+    #[serde(flatten)]
+    pub goal: Goal,
+    pub current: GoalPeriod,
+    /// Previous periods, the most recent one first
+    pub history: Vec<GoalPeriod>,
+}
+
+impl Goal {
+    fn target(&self) -> Result<f64, String> {
+        match (self.at_least, self.at_most) {
+            (Some(target), None) | (None, Some(target)) if target >= 0.0 => Ok(target),
+            (Some(_), None) | (None, Some(_)) => Err(format!(
+                "Target of goal '{}' can not be negative",
+                self.name
+            )),
+            _ => Err(format!(
+                "Goal '{}' needs exactly one of at_least and at_most",
+                self.name
+            )),
+        }
+    }
+
+    fn period_status(
+        &self,
+        target: f64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        events: &[Event],
+    ) -> GoalPeriod {
+        let duration = events
+            .iter()
+            .filter(|event| in_category(event, &self.category))
+            .fold(Duration::zero(), |acc, event| acc + event.duration);
+        let duration = duration.num_milliseconds() as f64 / 1000.0;
+        let met = match self.at_least {
+            Some(_) => duration >= target,
+            None => duration <= target,
+        };
+        let progress = if target > 0.0 {
+            duration / target
+        } else if duration > 0.0 {
+            std::f64::INFINITY
+        } else {
+            1.0
+        };
+        GoalPeriod {
+            start,
+            end,
+            duration,
+            progress,
+            met,
+        }
+    }
+}
+
+/// Whether the `$category` of an event is category or one of its subcategories
+fn in_category(event: &Event, category: &[String]) -> bool {
+    match event.data.get("$category") {
+        Some(Value::Array(path)) => {
+            path.len() >= category.len()
+                && category
+                    .iter()
+                    .zip(path)
+                    .all(|(name, value)| value.as_str() == Some(name))
+        }
+        _ => false,
+    }
+}
+
+/// Evaluates goals for the period containing now and the history periods before it
+pub fn evaluate_goals(
+    config: &GoalsConfig,
+    now: DateTime<Utc>,
+    history: usize,
+    datastore: &Datastore,
+    options: &QueryOptions,
+) -> Result<Vec<GoalStatus>, String> {
+    let query_code = config.query.join("\n");
+    // Goals with the same periods share the result of the query
+    let mut results: HashMap<(DateTime<Utc>, DateTime<Utc>), Vec<Event>> = HashMap::new();
+    let mut statuses = Vec::new();
+    for goal in &config.goals {
+        let target = goal.target()?;
+        let invalid = |e: String| format!("Invalid goal '{}': {}", goal.name, e);
+        let resolution: Resolution = goal.period.parse().map_err(invalid)?;
+        let tz: Tz = goal.timezone.parse().map_err(invalid)?;
+        let offset = timeslots::day_offset(goal.day_offset).map_err(invalid)?;
+
+        let mut periods = Vec::new();
+        let mut start = slot_start(now, resolution, &tz, offset)?;
+        let mut end = next_slot_start(now, resolution, &tz, offset)?;
+        for _ in 0..=history {
+            let events = match results.entry((start, end)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let interval = TimeInterval::new(start, end);
+                    let result =
+                        aw_query::query_with_options(&query_code, &interval, datastore, options)
+                            .map_err(|e| format!("Query of goal '{}' failed: {}", goal.name, e))?;
+                    let events = Vec::<Event>::try_from(&result.0).map_err(|e| {
+                        format!(
+                            "Query of goal '{}' has to return a list of events: {}",
+                            goal.name, e
+                        )
+                    })?;
+                    entry.insert(events)
+                }
+            };
+            periods.push(goal.period_status(target, start, end, events));
+            end = start;
+            start = slot_start(end - Duration::nanoseconds(1), resolution, &tz, offset)?;
+        }
+        let current = periods.remove(0);
+        statuses.push(GoalStatus {
+            goal: goal.clone(),
+            current,
+            history: periods,
+        });
+    }
+    Ok(statuses)
+}
+
+/// Parses the value of the goals setting
+pub fn parse_goals(value: &str) -> Result<GoalsConfig, String> {
+    serde_json::from_str(value).map_err(|e| format!("Invalid goals setting: {}", e))
+}
@@ -33,7 +33,10 @@ pub mod macros;
 pub mod config;
 pub mod dirs;
 pub mod endpoints;
+pub mod goals;
 pub mod logging;
+pub mod query_cache;
+pub mod triggers;
 
 #[cfg(target_os = "android")]
 pub mod android;
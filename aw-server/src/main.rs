@@ -14,8 +14,6 @@ fn print_usage(program: &str, opts: Options) {
 }
 
 fn main() {
-    use std::sync::Mutex;
-
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
@@ -52,12 +50,12 @@ fn main() {
     let asset_path = get_asset_path();
     info!("Using aw-webui assets at path {:?}", asset_path);
 
-    let server_state = endpoints::ServerState {
-        // Even if legacy_import is set to true it is disabled on Android so
-        // it will not happen there
-        datastore: Mutex::new(aw_datastore::Datastore::new(db_path, true)),
-        asset_path,
-    };
+    // Even if legacy_import is set to true it is disabled on Android so
+    // it will not happen there
+    let datastore = aw_datastore::Datastore::new(db_path, true);
+    triggers::start_trigger_thread(&config, datastore.clone());
+
+    let server_state = endpoints::ServerState::new(datastore, asset_path);
 
     endpoints::build_rocket(server_state, config).launch();
 }
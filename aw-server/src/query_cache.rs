@@ -0,0 +1,91 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+
+use aw_datastore::IntervalVersions;
+use aw_models::TimeInterval;
+use aw_query::DataType;
+
+/// Max amount of cached results, when exceeded the whole cache is cleared
+const MAX_ENTRIES: usize = 1000;
+
+/// Cache for results of queries over time intervals which have already passed
+///
+/// Results are stored with the versions of the buckets their query read within their interval
+/// (see `Datastore::write_versions`), so writes to those buckets within the interval invalidate
+/// them. Writes to other buckets, or to the current day, do not invalidate results for past
+/// days. Creating or deleting any bucket invalidates all results, as queries can look up buckets
+/// by name.
+#[derive(Default)]
+pub struct QueryCache {
+    entries: HashMap<(String, String), CacheEntry>,
+}
+
+struct CacheEntry {
+    buckets_version: u64,
+    /// Versions of the buckets the query read
+    events_versions: HashMap<String, u64>,
+    result: DataType,
+}
+
+impl CacheEntry {
+    fn is_current(&self, versions: &IntervalVersions) -> bool {
+        self.buckets_version == versions.buckets
+            && self
+                .events_versions
+                .iter()
+                .all(|(bucket_id, version)| versions.events_version(bucket_id) == *version)
+    }
+}
+
+impl QueryCache {
+    pub fn new() -> QueryCache {
+        QueryCache::default()
+    }
+
+    /// Returns the cached result if the buckets it read still have the same versions
+    pub fn get(
+        &self,
+        query: &str,
+        interval: &TimeInterval,
+        versions: &IntervalVersions,
+    ) -> Option<DataType> {
+        let key = (query.to_string(), interval.to_string());
+        match self.entries.get(&key) {
+            Some(entry) if entry.is_current(versions) => Some(entry.result.clone()),
+            _ => None,
+        }
+    }
+
+    /// Caches a result, versions should be retrieved before the query was run
+    ///
+    /// Results for intervals which have not yet ended are not cached as new events can still be
+    /// added to them.
+    pub fn insert(
+        &mut self,
+        query: &str,
+        interval: &TimeInterval,
+        versions: &IntervalVersions,
+        buckets_read: &HashSet<String>,
+        result: DataType,
+    ) {
+        if *interval.end() >= Utc::now() {
+            return;
+        }
+        if self.entries.len() >= MAX_ENTRIES {
+            debug!("Query cache is full, clearing it");
+            self.entries.clear();
+        }
+        let events_versions = buckets_read
+            .iter()
+            .map(|bucket_id| (bucket_id.to_string(), versions.events_version(bucket_id)))
+            .collect();
+        let key = (query.to_string(), interval.to_string());
+        let entry = CacheEntry {
+            buckets_version: versions.buckets,
+            events_versions,
+            result,
+        };
+        self.entries.insert(key, entry);
+    }
+}
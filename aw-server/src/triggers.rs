@@ -0,0 +1,446 @@
+//! Actions which are run when the condition of a trigger in the config becomes true
+//!
+//! The query of every trigger is run periodically over the current hour, day or week. It has to
+//! return a bool, or a number which is compared with the `above` threshold of the trigger. Once
+//! the condition changes from false to true the command of the trigger is run and its url is sent
+//! a POST request with the result as JSON. Triggers are also fired if their condition is already
+//! true when the server starts.
+//!
+//! ```toml
+//! [[triggers]]
+//! name = "Social media"
+//! query = ["events = categorize(query_bucket(find_bucket(\"aw-watcher-window_\")), [[[\"Social\"], {\"type\": \"regex\", \"regex\": \"Twitter\"}]]);",
+//!          "RETURN = sum_durations(filter_keyvals(events, \"$category\", [[\"Social\"]]));"]
+//! above = 2700
+//! command = ["notify-send", "More than 45 minutes of social media today"]
+//! url = "http://localhost:8080/nudge"
+//! ```
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::process::Command;
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+
+use aw_datastore::Datastore;
+use aw_models::TimeInterval;
+use aw_query::{DataType, QueryOptions};
+use aw_transform::timeslots::{self, next_slot_start, slot_start, Resolution};
+
+use crate::config::AWConfig;
+
+/// Timeout for connecting to, writing to and reading from the url of a trigger
+const URL_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Trigger {
+    pub name: String,
+    /// Query which returns a bool or a number, lines are joined like in /api/0/query
+    pub query: Vec<String>,
+    /// The condition is true if the query returns a number greater than this
+    #[serde(default)]
+    pub above: Option<f64>,
+    /// Interval the query is run over, the current "hour", "day" or "week"
+    #[serde(default = "default_period")]
+    pub period: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    /// Hours after midnight when days and weeks start
+    #[serde(default)]
+    pub day_offset: f64,
+    /// Program and arguments which are run when the trigger fires
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    /// URL on localhost which is sent a POST request when the trigger fires
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+fn default_period() -> String {
+    "day".to_string()
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+/// A trigger with its parsed config and whether its condition was true when last evaluated
+struct TriggerState {
+    trigger: Trigger,
+    resolution: Resolution,
+    tz: Tz,
+    offset: Duration,
+    url: Option<LocalUrl>,
+    active: bool,
+}
+
+/// Evaluates triggers and fires their actions
+pub struct TriggerRunner {
+    triggers: Vec<TriggerState>,
+    options: QueryOptions,
+}
+
+impl TriggerRunner {
+    /// Returns an error if the config of a trigger is invalid
+    pub fn new(triggers: Vec<Trigger>, options: QueryOptions) -> Result<TriggerRunner, String> {
+        let mut states = Vec::new();
+        for trigger in triggers {
+            let invalid = |e: String| format!("Invalid trigger '{}': {}", trigger.name, e);
+            let resolution: Resolution = trigger.period.parse().map_err(invalid)?;
+            let tz: Tz = trigger.timezone.parse().map_err(invalid)?;
+            let offset = timeslots::day_offset(trigger.day_offset).map_err(invalid)?;
+            let url = match &trigger.url {
+                Some(url) => Some(LocalUrl::parse(url).map_err(invalid)?),
+                None => None,
+            };
+            if let Some(command) = &trigger.command {
+                if command.is_empty() {
+                    return Err(invalid("command can not be empty".to_string()));
+                }
+            }
+            states.push(TriggerState {
+                resolution,
+                tz,
+                offset,
+                url,
+                trigger,
+                active: false,
+            });
+        }
+        Ok(TriggerRunner {
+            triggers: states,
+            options,
+        })
+    }
+
+    /// Evaluates all triggers and fires the ones whose condition became true
+    ///
+    /// Returns the names of the fired triggers. Triggers whose query fails are logged and keep
+    /// their previous state.
+    pub fn evaluate(&mut self, datastore: &Datastore, now: DateTime<Utc>) -> Vec<String> {
+        let mut fired = Vec::new();
+        for state in self.triggers.iter_mut() {
+            let trigger = &state.trigger;
+            let slot =
+                slot_start(now, state.resolution, &state.tz, state.offset).and_then(|start| {
+                    let end = next_slot_start(now, state.resolution, &state.tz, state.offset)?;
+                    Ok(TimeInterval::new(start, end))
+                });
+            let interval = match slot {
+                Ok(interval) => interval,
+                Err(e) => {
+                    warn!("Period of trigger '{}' is invalid: {}", trigger.name, e);
+                    continue;
+                }
+            };
+            let code = trigger.query.join("\n");
+            let result =
+                match aw_query::query_with_options(&code, &interval, datastore, &self.options) {
+                    Ok((result, _profile)) => result,
+                    Err(e) => {
+                        warn!("Query of trigger '{}' failed: {}", trigger.name, e);
+                        continue;
+                    }
+                };
+            let active = match (&result, trigger.above) {
+                (DataType::Bool(b), None) => *b,
+                (DataType::Number(n), Some(above)) => *n > above,
+                (result, _) => {
+                    warn!(
+                        "Query of trigger '{}' has to return a bool, or a number if above is set, got {:?}",
+                        trigger.name, result
+                    );
+                    continue;
+                }
+            };
+            if active && !state.active {
+                info!("Trigger '{}' fired", trigger.name);
+                fire(trigger, state.url.as_ref(), &result, now);
+                fired.push(trigger.name.clone());
+            }
+            state.active = active;
+        }
+        fired
+    }
+}
+
+fn fire(trigger: &Trigger, url: Option<&LocalUrl>, result: &DataType, now: DateTime<Utc>) {
+    let result = serde_json::to_value(result).unwrap();
+    if let Some(command) = &trigger.command {
+        match Command::new(&command[0])
+            .args(&command[1..])
+            .env("AW_TRIGGER_NAME", &trigger.name)
+            .env("AW_TRIGGER_RESULT", result.to_string())
+            .spawn()
+        {
+            Ok(mut child) => {
+                // Wait in another thread so slow commands do not delay other triggers
+                thread::spawn(move || child.wait());
+            }
+            Err(e) => warn!("Failed to run command of trigger '{}': {}", trigger.name, e),
+        }
+    }
+    if let Some(url) = url {
+        let body = json!({
+            "name": trigger.name,
+            "result": result,
+            "timestamp": now,
+        });
+        // Send in another thread so unreachable urls do not delay other triggers
+        let url = url.clone();
+        let name = trigger.name.clone();
+        thread::spawn(move || {
+            if let Err(e) = url.post(&body.to_string()) {
+                warn!("Failed to send trigger '{}' to url: {}", name, e);
+            }
+        });
+    }
+}
+
+/// A http URL on localhost
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl LocalUrl {
+    /// Parses an URL such as "http://localhost:8080/path", only http to localhost is allowed as
+    /// nudges are not supposed to leave the computer
+    pub fn parse(url: &str) -> Result<LocalUrl, String> {
+        if !url.starts_with("http://") {
+            return Err(format!("'{}' is not a http URL", url));
+        }
+        let rest = &url["http://".len()..];
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(i) if !authority[i..].contains(']') => {
+                let port = authority[i + 1..]
+                    .parse()
+                    .map_err(|_| format!("Invalid port in '{}'", url))?;
+                (&authority[..i], port)
+            }
+            _ => (authority, 80),
+        };
+        match host {
+            "localhost" | "127.0.0.1" | "[::1]" => Ok(LocalUrl {
+                host: host.to_string(),
+                port,
+                path: path.to_string(),
+            }),
+            _ => Err(format!("'{}' is not on localhost", url)),
+        }
+    }
+
+    /// Sends a POST request with a JSON body and checks that the response is successful
+    pub fn post(&self, body: &str) -> Result<(), String> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let addr = (host, self.port)
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("Failed to resolve {}", self.host))?;
+        let mut stream =
+            TcpStream::connect_timeout(&addr, URL_TIMEOUT).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(URL_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(URL_TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .map_err(|e| e.to_string())?;
+        let status = response.lines().next().unwrap_or("");
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(format!("Unexpected response '{}'", status)),
+        }
+    }
+}
+
+/// Starts a thread which evaluates the triggers of the config every trigger_interval seconds
+///
+/// Panics if the config of a trigger is invalid, like an invalid config file does.
+pub fn start_trigger_thread(config: &AWConfig, datastore: Datastore) {
+    if config.triggers.is_empty() {
+        return;
+    }
+    let options = QueryOptions {
+        limits: config.query_limits.to_query_limits(),
+        ..QueryOptions::default()
+    };
+    let mut runner = match TriggerRunner::new(config.triggers.clone(), options) {
+        Ok(runner) => runner,
+        Err(e) => panic!("{}", e),
+    };
+    let interval = StdDuration::from_secs_f64(config.trigger_interval);
+    info!(
+        "Evaluating {} triggers every {}s",
+        config.triggers.len(),
+        interval.as_secs()
+    );
+    thread::spawn(move || loop {
+        match datastore.parallel_reader() {
+            Ok(reader) => {
+                // A panic would otherwise silently stop all triggers
+                let evaluated =
+                    panic::catch_unwind(AssertUnwindSafe(|| runner.evaluate(&reader, Utc::now())));
+                if evaluated.is_err() {
+                    error!("Evaluating triggers panicked");
+                }
+            }
+            Err(e) => warn!("Failed to prepare datastore for triggers: {:?}", e),
+        }
+        thread::sleep(interval);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use chrono::{DateTime, Utc};
+
+    use aw_datastore::Datastore;
+    use aw_models::{Bucket, BucketMetadata, Event};
+
+    use super::{LocalUrl, Trigger, TriggerRunner};
+    use crate::config::AWConfig;
+
+    /// Command which runs script in a shell, there is none to rely on outside of unix
+    #[cfg(unix)]
+    fn shell_command(script: &str) -> Option<Vec<String>> {
+        Some(vec!["sh".to_string(), "-c".to_string(), script.to_string()])
+    }
+
+    #[cfg(not(unix))]
+    fn shell_command(_script: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    #[test]
+    fn test_triggers() {
+        let datastore = Datastore::new_in_memory(false);
+        let bucket = Bucket {
+            bid: None,
+            id: "id".to_string(),
+            _type: "type".to_string(),
+            client: "client".to_string(),
+            hostname: "hostname".to_string(),
+            created: None,
+            data: serde_json::map::Map::new(),
+            metadata: BucketMetadata::default(),
+            events: None,
+            last_updated: None,
+        };
+        datastore.create_bucket(&bucket).unwrap();
+
+        // Receives a single request and returns its body
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let receiver = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with('}') {
+                let n = stream.read(&mut buf).unwrap();
+                request.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            request.split("\r\n\r\n").nth(1).unwrap().to_string()
+        });
+        let out_path = std::env::temp_dir().join(format!("aw-trigger-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&out_path);
+
+        let trigger = Trigger {
+            name: "Too much".to_string(),
+            query: vec![r#"RETURN = sum_durations(query_bucket("id"));"#.to_string()],
+            above: Some(60.0),
+            period: "day".to_string(),
+            timezone: "UTC".to_string(),
+            day_offset: 0.0,
+            command: shell_command(&format!(
+                "echo \"$AW_TRIGGER_NAME $AW_TRIGGER_RESULT\" >> {}",
+                out_path.display()
+            )),
+            url: Some(format!("http://localhost:{}/nudge", port)),
+        };
+        let mut runner = TriggerRunner::new(vec![trigger], Default::default()).unwrap();
+        let now: DateTime<Utc> = "2000-01-01T12:00:00Z".parse().unwrap();
+        assert!(runner.evaluate(&datastore, now).is_empty());
+
+        let event = Event {
+            id: None,
+            timestamp: "2000-01-01T01:00:00Z".parse().unwrap(),
+            duration: chrono::Duration::seconds(120),
+            data: serde_json::map::Map::new(),
+        };
+        datastore.insert_events("id", &[event]).unwrap();
+        assert_eq!(runner.evaluate(&datastore, now), vec!["Too much"]);
+        // Only fired again once the condition has been false
+        assert!(runner.evaluate(&datastore, now).is_empty());
+        let tomorrow = now + chrono::Duration::days(1);
+        assert!(runner.evaluate(&datastore, tomorrow).is_empty());
+
+        let body: serde_json::Value = serde_json::from_str(&receiver.join().unwrap()).unwrap();
+        assert_eq!(body["name"], "Too much");
+        assert_eq!(body["result"], serde_json::json!(120.0));
+        // The command runs in the background
+        if cfg!(unix) {
+            let mut output = String::new();
+            for _ in 0..50 {
+                output = std::fs::read_to_string(&out_path).unwrap_or_default();
+                if !output.is_empty() {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            std::fs::remove_file(&out_path).unwrap();
+            assert_eq!(output, "Too much 120.0\n");
+        }
+
+        // Only localhost is allowed
+        assert_eq!(
+            LocalUrl::parse("http://[::1]:5600").unwrap(),
+            LocalUrl {
+                host: "[::1]".to_string(),
+                port: 5600,
+                path: "/".to_string()
+            }
+        );
+        assert!(LocalUrl::parse("http://example.com/nudge").is_err());
+        assert!(LocalUrl::parse("https://localhost/nudge").is_err());
+    }
+
+    #[test]
+    fn test_trigger_interval() {
+        let mut config = AWConfig::default();
+        assert!(config.validate().is_ok());
+        for interval in &[0.0, -1.0, std::f64::INFINITY, std::f64::NAN] {
+            config.trigger_interval = *interval;
+            assert!(config.validate().is_err());
+        }
+    }
+}
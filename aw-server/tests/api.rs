@@ -12,8 +12,9 @@ extern crate aw_server;
 mod api_tests {
     use chrono::{DateTime, Utc};
     use rocket::http::{ContentType, Header, Status};
+    use serde_json::json;
     use std::path::PathBuf;
-    use std::sync::Mutex;
+    use std::str::FromStr;
 
     use aw_server::config;
     use aw_server::endpoints;
@@ -23,10 +24,10 @@ mod api_tests {
     use rocket::local::Client;
 
     fn setup_testserver() -> rocket::Rocket {
-        let state = endpoints::ServerState {
-            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
-            asset_path: PathBuf::from("aw-webui/dist"),
-        };
+        let state = endpoints::ServerState::new(
+            aw_datastore::Datastore::new_in_memory(false),
+            PathBuf::from("aw-webui/dist"),
+        );
         let aw_config = config::AWConfig::default();
         endpoints::build_rocket(state, aw_config)
     }
@@ -236,6 +237,73 @@ mod api_tests {
         assert_eq!(res.status(), rocket::http::Status::Ok);
     }
 
+    #[test]
+    fn test_events_split_by_day() {
+        let server = setup_testserver();
+        let client = rocket::local::Client::new(server).expect("valid instance");
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                "id": "id",
+                "type": "type",
+                "client": "client",
+                "hostname": "hostname"
+            }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+
+        // From 23:00 to 05:00 in Stockholm
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                "timestamp": "2018-01-01T22:00:00Z",
+                "duration": 21600.0,
+                "data": {}
+            }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+
+        // Split at midnight, newest first
+        let mut res = client
+            .get("/api/0/buckets/id/events?tz=Europe%2FStockholm")
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert_eq!(
+            res.body_string().unwrap(),
+            r#"[{"id":1,"timestamp":"2018-01-01T23:00:00Z","duration":18000.0,"data":{}},{"id":1,"timestamp":"2018-01-01T22:00:00Z","duration":3600.0,"data":{}}]"#
+        );
+
+        // Split at 04:00 and trimmed to the end
+        let mut res = client
+            .get("/api/0/buckets/id/events?tz=Europe%2FStockholm&day_offset=4&end=2018-01-02T04:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert_eq!(
+            res.body_string().unwrap(),
+            r#"[{"id":1,"timestamp":"2018-01-02T03:00:00Z","duration":3600.0,"data":{}},{"id":1,"timestamp":"2018-01-01T22:00:00Z","duration":18000.0,"data":{}}]"#
+        );
+
+        let res = client
+            .get("/api/0/buckets/id/events?tz=Mars%2FOlympus_Mons")
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::BadRequest);
+
+        // Offsets of a day or more are rejected
+        for offset in &["24", "-24", "1e12", "NaN"] {
+            let res = client
+                .get(format!("/api/0/buckets/id/events?day_offset={}", offset))
+                .dispatch();
+            assert_eq!(res.status(), rocket::http::Status::BadRequest);
+        }
+    }
+
     #[test]
     fn test_import_export() {
         let server = setup_testserver();
@@ -323,6 +391,69 @@ mod api_tests {
         assert_eq!(buckets.len(), 0);
     }
 
+    #[test]
+    fn test_export_redact() {
+        let server = setup_testserver();
+        let client = rocket::local::Client::new(server).expect("valid instance");
+
+        let res = client
+            .post("/api/0/import")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"buckets":
+            {"id1": {
+                "id": "id1",
+                "type": "type",
+                "client": "client",
+                "hostname": "hostname",
+                "events": [{
+                    "timestamp":"2000-01-01T00:00:00Z",
+                    "duration":1.0,
+                    "data": {"title": "Secret plans", "url": "https://example.com/secret", "app": "firefox"}
+                }]
+            }}}"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+
+        // Hash titles and truncate urls to their domain
+        let mut res = client
+            .get("/api/0/export?redact=title&salt=salt")
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        let export: BucketsExport = serde_json::from_str(&res.body_string().unwrap()).unwrap();
+        let events = export.buckets["id1"].events.clone().unwrap();
+        assert_eq!(
+            events[0].data["title"],
+            json!("eefeb180fc3dd5848cfb98723d41b4a07f06e5982e3ff34b0265bd826d6d2c82")
+        );
+        assert_eq!(events[0].data["app"], json!("firefox"));
+
+        // Without a salt every export gets a random one which is not in the export
+        let mut hash_title = || {
+            let mut res = client.get("/api/0/export?redact=title").dispatch();
+            assert_eq!(res.status(), rocket::http::Status::Ok);
+            let body = res.body_string().unwrap();
+            let export: BucketsExport = serde_json::from_str(&body).unwrap();
+            export.buckets["id1"].events.clone().unwrap()[0].data["title"].clone()
+        };
+        assert_ne!(hash_title(), hash_title());
+
+        let mut res = client
+            .get("/api/0/buckets/id1/export?redact=title,url&method=domain")
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        let export: BucketsExport = serde_json::from_str(&res.body_string().unwrap()).unwrap();
+        let events = export.buckets["id1"].events.clone().unwrap();
+        assert_eq!(events[0].data["url"], json!("example.com"));
+        assert_eq!(events[0].data["title"], json!(""));
+
+        let res = client
+            .get("/api/0/export?redact=title&method=rot13")
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::BadRequest);
+    }
+
     #[test]
     fn test_query() {
         let server = setup_testserver();
@@ -388,6 +519,79 @@ mod api_tests {
             r#"[[{"data":{},"duration":1.0,"id":1,"timestamp":"2018-01-01T01:01:01Z"}]]"#
         );
 
+        // Query multiple timeperiods, results should be in the same order as the timeperiods
+        let mut res = client
+            .post("/api/0/query")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                "timeperiods": [
+                    "2017-01-01T00:00:00Z/2018-01-01T00:00:00Z",
+                    "2018-01-01T00:00:00Z/2019-01-01T00:00:00Z",
+                    "2019-01-01T00:00:00Z/2020-01-01T00:00:00Z",
+                    "2020-01-01T00:00:00Z/2021-01-01T00:00:00Z",
+                    "2021-01-01T00:00:00Z/2022-01-01T00:00:00Z"
+                ],
+                "query": ["return sum_durations(query_bucket(\"id\"));"]
+            }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert_eq!(res.body_string().unwrap(), r#"[0.0,1.0,0.0,0.0,0.0]"#);
+
+        // Query with params, which are available as variables in the query
+        let mut res = client
+            .post("/api/0/query")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                "timeperiods": ["2000-01-01T00:00:00Z/2020-01-01T00:00:00Z"],
+                "query": ["return [sum_durations(query_bucket(bucket)), n];"],
+                "params": {"bucket": "id", "n": 2}
+            }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert_eq!(res.body_string().unwrap(), r#"[[1.0,2.0]]"#);
+
+        // Query events with profiling enabled
+        let mut res = client
+            .post("/api/0/query")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                "timeperiods": ["2000-01-01T00:00:00Z/2020-01-01T00:00:00Z"],
+                "query": ["return query_bucket(\"id\");"],
+                "profile": true
+            }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&res.body_string().unwrap()).unwrap();
+        // The results are wrapped in an object together with a profile per timeperiod
+        let keys: Vec<&String> = body.as_object().unwrap().keys().collect();
+        assert_eq!(keys, vec!["profile", "result"]);
+        assert_eq!(body["result"].as_array().unwrap().len(), 1);
+        assert_eq!(body["result"][0].as_array().unwrap().len(), 1);
+        assert_eq!(body["profile"].as_array().unwrap().len(), 1);
+        assert!(body["profile"][0]["time"].is_number());
+        let call = &body["profile"][0]["calls"][0];
+        let keys: Vec<&String> = call.as_object().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            vec![
+                "function",
+                "input_events",
+                "line",
+                "output_events",
+                "rows_read",
+                "time"
+            ]
+        );
+        assert_eq!(call["function"], "query_bucket");
+        assert_eq!(call["output_events"], 1);
+        assert_eq!(call["rows_read"], 1);
+
         // Test error
         let mut res = client
             .post("/api/0/query")
@@ -404,6 +608,125 @@ mod api_tests {
             res.body_string().unwrap(),
             r#"{"message":"EmptyQuery","reason":"Internal Server Error (Query Error)","status":500}"#
         );
+
+        // Check query without running it
+        let mut res = client
+            .post("/api/0/query/check")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                "query": ["events = query_bucket(\"id\");", "return flood(event);"]
+            }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert_eq!(
+            res.body_string().unwrap(),
+            r#"[{"line":2,"message":"VariableNotDefined(\"event\")"}]"#
+        );
+    }
+
+    #[test]
+    fn test_query_limits() {
+        let state = endpoints::ServerState::new(
+            aw_datastore::Datastore::new_in_memory(false),
+            PathBuf::from("aw-webui/dist"),
+        );
+        let mut aw_config = config::AWConfig::default();
+        aw_config.query_limits.max_depth = 4;
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = rocket::local::Client::new(server).expect("valid instance");
+
+        let mut res = client
+            .post("/api/0/query")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                "timeperiods": ["2000-01-01T00:00:00Z/2020-01-01T00:00:00Z"],
+                "query": ["return [[[1]]];"]
+            }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::InternalServerError);
+        let body: serde_json::Value = serde_json::from_str(&res.body_string().unwrap()).unwrap();
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("LimitExceeded"));
+    }
+
+    #[test]
+    fn test_query_cache() {
+        let datastore = aw_datastore::Datastore::new_in_memory(false);
+        let state = endpoints::ServerState::new(datastore.clone(), PathBuf::from("aw-webui/dist"));
+        let server = endpoints::build_rocket(state, config::AWConfig::default());
+        let client = rocket::local::Client::new(server).expect("valid instance");
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(r#"{"id": "id", "type": "type", "client": "client", "hostname": "hostname"}"#)
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+
+        let insert_event = |timestamp: &str| {
+            let res = client
+                .post("/api/0/buckets/id/events")
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"[{{"timestamp": "{}", "duration": 1.0, "data": {{}}}}]"#,
+                    timestamp
+                ))
+                .dispatch();
+            assert_eq!(res.status(), rocket::http::Status::Ok);
+        };
+        let sum_durations = || {
+            let mut res = client
+                .post("/api/0/query")
+                .header(ContentType::JSON)
+                .body(
+                    r#"{
+                    "timeperiods": ["2000-01-01T00:00:00Z/2020-01-01T00:00:00Z"],
+                    "query": ["return sum_durations(query_bucket(\"id\"));"]
+                }"#,
+                )
+                .dispatch();
+            assert_eq!(res.status(), rocket::http::Status::Ok);
+            res.body_string().unwrap()
+        };
+
+        insert_event("2018-01-01T01:01:01Z");
+        assert_eq!(sum_durations(), "[1.0]");
+
+        // Events written without going through the API invalidate the cached result as well
+        let event: aw_models::Event = serde_json::from_str(
+            r#"{"timestamp": "2018-01-01T02:00:00Z", "duration": 1.0, "data": {}}"#,
+        )
+        .unwrap();
+        datastore.insert_events("id", &[event]).unwrap();
+        assert_eq!(sum_durations(), "[2.0]");
+
+        // Writes outside of the queried interval do not change the version of the cached result
+        let start = DateTime::from_str("2000-01-01T00:00:00Z").unwrap();
+        let end = DateTime::from_str("2020-01-01T00:00:00Z").unwrap();
+        let versions = datastore.write_versions(start, end);
+        insert_event("2021-01-01T01:01:01Z");
+        assert_eq!(datastore.write_versions(start, end), versions);
+        assert_eq!(sum_durations(), "[2.0]");
+
+        // Creating a bucket invalidates the cached result, as it could be found by find_bucket
+        let res = client
+            .post("/api/0/buckets/other")
+            .header(ContentType::JSON)
+            .body(r#"{"id": "other", "type": "type", "client": "client", "hostname": "hostname"}"#)
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert!(datastore.write_versions(start, end).buckets > versions.buckets);
+        assert_eq!(sum_durations(), "[2.0]");
+
+        // Writes within the interval invalidate the cached result
+        insert_event("2019-01-01T01:01:01Z");
+        assert_eq!(sum_durations(), "[3.0]");
     }
 
     fn set_setting_request(client: &Client, key: &str, value: &str) -> Status {
@@ -441,6 +764,108 @@ mod api_tests {
         );
     }
 
+    #[test]
+    fn test_goals() {
+        let server = setup_testserver();
+        let client = rocket::local::Client::new(server).expect("valid instance");
+
+        // No goals yet
+        let mut res = client.get("/api/0/goals").dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert_eq!(res.body_string().unwrap(), "[]");
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                "id": "id",
+                "type": "type",
+                "client": "client",
+                "hostname": "hostname"
+            }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        let today = Utc::now().date().and_hms(0, 0, 0);
+        let yesterday = today - chrono::Duration::days(1);
+        let events = json!([
+            {"timestamp": today, "duration": 1800.0, "data": {"title": "twitter"}},
+            {"timestamp": today, "duration": 3600.0, "data": {"title": "vim"}},
+            {"timestamp": yesterday, "duration": 7200.0, "data": {"title": "twitter"}}
+        ]);
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(events.to_string())
+            .dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+
+        let goals = json!({
+            "query": [
+                r#"RULES = [[["Social"], {"type": "regex", "regex": "twitter"}]];"#,
+                r#"RETURN = categorize(query_bucket("id"), RULES);"#
+            ],
+            "goals": [
+                {"name": "Less social", "category": ["Social"], "at_most": 3600, "period": "day"},
+                {"name": "Use computer", "category": [], "at_least": 7200, "period": "day"}
+            ]
+        });
+        let status = set_setting_request(&client, "goals", &goals.to_string());
+        assert_eq!(status, rocket::http::Status::Created);
+
+        let mut res = client.get("/api/0/goals?history=1").dispatch();
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        let statuses: serde_json::Value =
+            serde_json::from_str(&res.body_string().unwrap()).unwrap();
+        let social = &statuses[0];
+        assert_eq!(social["name"], "Less social");
+        assert_eq!(social["current"]["start"], json!(today));
+        assert_eq!(social["current"]["duration"], 1800.0);
+        assert_eq!(social["current"]["progress"], 0.5);
+        assert_eq!(social["current"]["met"], true);
+        assert_eq!(social["history"].as_array().unwrap().len(), 1);
+        assert_eq!(social["history"][0]["start"], json!(yesterday));
+        assert_eq!(social["history"][0]["duration"], 7200.0);
+        assert_eq!(social["history"][0]["met"], false);
+        let computer = &statuses[1];
+        assert_eq!(computer["current"]["duration"], 5400.0);
+        assert_eq!(computer["current"]["progress"], 0.75);
+        assert_eq!(computer["current"]["met"], false);
+
+        // Goals need a target
+        let goals = json!({
+            "query": ["RETURN = [];"],
+            "goals": [{"name": "No target", "category": [], "period": "day"}]
+        });
+        set_setting_request(&client, "goals", &goals.to_string());
+        let res = client.get("/api/0/goals").dispatch();
+        assert_eq!(res.status(), rocket::http::Status::InternalServerError);
+
+        // Errors name the invalid goal
+        let goals = json!({
+            "query": ["RETURN = [];"],
+            "goals": [{"name": "Decade", "category": [], "at_least": 1, "period": "decade"}]
+        });
+        set_setting_request(&client, "goals", &goals.to_string());
+        let mut res = client.get("/api/0/goals").dispatch();
+        assert_eq!(res.status(), rocket::http::Status::InternalServerError);
+        let body: serde_json::Value = serde_json::from_str(&res.body_string().unwrap()).unwrap();
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid goal 'Decade': "));
+
+        // Days can not start a day or more after midnight
+        let goals = json!({
+            "query": ["RETURN = [];"],
+            "goals": [{"name": "Late", "category": [], "at_least": 1, "period": "day", "day_offset": 1e12}]
+        });
+        set_setting_request(&client, "goals", &goals.to_string());
+        let res = client.get("/api/0/goals").dispatch();
+        assert_eq!(res.status(), rocket::http::Status::InternalServerError);
+    }
+
     #[test]
     fn test_illegally_long_key() {
         let server = setup_testserver();
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use chrono::Duration;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+/// A category and its subcategories, the root node contains all events
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryNode {
+    /// Sum of the durations of the events with exactly this category
+    pub duration: Duration,
+    /// Sum of the durations of the events with this category or one of its subcategories
+    pub total: Duration,
+    /// Longest events with this category or one of its subcategories, longest first
+    pub top_events: Vec<Event>,
+    pub children: HashMap<String, CategoryNode>,
+}
+
+impl CategoryNode {
+    fn new() -> CategoryNode {
+        CategoryNode {
+            duration: Duration::zero(),
+            total: Duration::zero(),
+            top_events: Vec::new(),
+            children: HashMap::new(),
+        }
+    }
+
+    fn add_top_event(&mut self, event: &Event, top_n: usize) {
+        self.top_events.push(event.clone());
+        // Truncate once in a while instead of on every event to avoid sorting all the time
+        if self.top_events.len() > 2 * top_n {
+            self.truncate_top_events(top_n);
+        }
+    }
+
+    fn truncate_top_events(&mut self, top_n: usize) {
+        self.top_events.sort_by(|a, b| {
+            b.duration
+                .cmp(&a.duration)
+                .then(a.timestamp.cmp(&b.timestamp))
+        });
+        self.top_events.truncate(top_n);
+    }
+
+    fn finish(&mut self, top_n: usize) {
+        self.truncate_top_events(top_n);
+        for child in self.children.values_mut() {
+            child.finish(top_n);
+        }
+    }
+}
+
+/// Builds a tree of the categories set by classify::categorize
+///
+/// Every node sums the durations of its own events and those of its subcategories and keeps the
+/// top_n longest events of them. Events without a valid `$category` are put in "Uncategorized".
+pub fn category_tree(events: &[Event], top_n: usize) -> CategoryNode {
+    let mut root = CategoryNode::new();
+    for event in events {
+        let category: Vec<&str> = match event.data.get("$category") {
+            Some(Value::Array(path)) if !path.is_empty() && path.iter().all(Value::is_string) => {
+                path.iter().map(|name| name.as_str().unwrap()).collect()
+            }
+            _ => vec!["Uncategorized"],
+        };
+        let mut node = &mut root;
+        node.total = node.total + event.duration;
+        node.add_top_event(event, top_n);
+        for name in category {
+            node = node
+                .children
+                .entry(name.to_string())
+                .or_insert_with(CategoryNode::new);
+            node.total = node.total + event.duration;
+            node.add_top_event(event, top_n);
+        }
+        node.duration = node.duration + event.duration;
+    }
+    root.finish(top_n);
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use chrono::Duration;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::category_tree;
+
+    #[test]
+    fn test_category_tree() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(1),
+            data: json_map! {"$category": json!(["Work"])},
+        };
+        let mut e2 = e1.clone();
+        e2.duration = Duration::seconds(2);
+        e2.data = json_map! {"$category": json!(["Work", "Programming"])};
+        let mut e3 = e1.clone();
+        e3.duration = Duration::seconds(4);
+        e3.data = json_map! {"$category": json!(["Work", "Programming", "Rust"])};
+        let mut e4 = e1.clone();
+        e4.duration = Duration::seconds(8);
+        e4.data = json_map! {};
+
+        let root = category_tree(&[e1, e2.clone(), e3.clone(), e4.clone()], 2);
+        assert_eq!(root.duration, Duration::zero());
+        assert_eq!(root.total, Duration::seconds(15));
+        assert_eq!(root.top_events, vec![e4.clone(), e3.clone()]);
+        assert_eq!(root.children.len(), 2);
+
+        let work = &root.children["Work"];
+        assert_eq!(work.duration, Duration::seconds(1));
+        assert_eq!(work.total, Duration::seconds(7));
+        assert_eq!(work.top_events, vec![e3.clone(), e2]);
+
+        let programming = &work.children["Programming"];
+        assert_eq!(programming.duration, Duration::seconds(2));
+        assert_eq!(programming.total, Duration::seconds(6));
+        let rust = &programming.children["Rust"];
+        assert_eq!(rust.duration, Duration::seconds(4));
+        assert_eq!(rust.top_events, vec![e3]);
+        assert!(rust.children.is_empty());
+
+        let uncategorized = &root.children["Uncategorized"];
+        assert_eq!(uncategorized.total, Duration::seconds(8));
+        assert_eq!(uncategorized.top_events, vec![e4]);
+    }
+}
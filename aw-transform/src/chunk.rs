@@ -1,11 +1,20 @@
+use serde_json::json;
+use serde_json::map::Map;
+use serde_json::value::Value;
+
 use aw_models::Event;
 
-pub fn chunk_events_by_key(events: Vec<Event>, key: &str) -> Vec<Event> {
+/// Merges consecutive events which have the same value for key
+///
+/// The chunks keep the data of their first event. If a subkey is given, each chunk also gets
+/// `$subchunks` in its data, an object with the summed durations in seconds of the events in the
+/// chunk for every value of the subkey, for instance the time spent on each title within a run
+/// of the same app. Events without the subkey are not counted in the sub-chunks.
+pub fn chunk_events_by_key(events: Vec<Event>, key: &str, subkey: Option<&str>) -> Vec<Event> {
     let mut chunked_events: Vec<Event> = Vec::new();
     for event in events {
         if chunked_events.is_empty() && event.data.get(key).is_some() {
-            // TODO: Add sub-chunks
-            chunked_events.push(event.clone());
+            chunked_events.push(new_chunk(&event, subkey));
         } else {
             let val = match event.data.get(key) {
                 None => continue,
@@ -14,19 +23,45 @@ pub fn chunk_events_by_key(events: Vec<Event>, key: &str) -> Vec<Event> {
             let mut last_event = chunked_events.pop().unwrap();
             let last_val = last_event.data.get(key).unwrap().clone();
             if &last_val == val {
-                // TODO: Add sub-chunks
                 last_event.duration = last_event.duration + event.duration;
+                add_subchunk(&mut last_event, &event, subkey);
             }
             chunked_events.push(last_event);
             if &last_val != val {
-                // TODO: Add sub-chunks
-                chunked_events.push(event.clone());
+                chunked_events.push(new_chunk(&event, subkey));
             }
         }
     }
     chunked_events
 }
 
+fn new_chunk(event: &Event, subkey: Option<&str>) -> Event {
+    let mut chunk = event.clone();
+    if subkey.is_some() {
+        chunk
+            .data
+            .insert("$subchunks".to_string(), Value::Object(Map::new()));
+        add_subchunk(&mut chunk, event, subkey);
+    }
+    chunk
+}
+
+fn add_subchunk(chunk: &mut Event, event: &Event, subkey: Option<&str>) {
+    let subval = match subkey.and_then(|subkey| event.data.get(subkey)) {
+        Some(Value::String(s)) => s.to_string(),
+        Some(v) => v.to_string(),
+        None => return,
+    };
+    let seconds = event.duration.num_milliseconds() as f64 / 1000.0;
+    if let Some(Value::Object(subchunks)) = chunk.data.get_mut("$subchunks") {
+        let total = subchunks
+            .get(&subval)
+            .and_then(Value::as_f64)
+            .unwrap_or(0.0);
+        subchunks.insert(subval, json!(total + seconds));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -53,9 +88,36 @@ mod tests {
         let mut e4 = e1.clone();
         e4.data = json_map! {"test": json!(2)};
 
-        let res = chunk_events_by_key(vec![e1, e2, e3, e4], "test");
+        let res = chunk_events_by_key(vec![e1, e2, e3, e4], "test", None);
         assert_eq!(res.len(), 2);
         assert_eq!(res[0].duration, Duration::seconds(2));
         assert_eq!(res[1].duration, Duration::seconds(1));
+        assert!(res[0].data.get("$subchunks").is_none());
+    }
+
+    #[test]
+    fn test_chunk_events_by_key_subchunks() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:01Z").unwrap(),
+            duration: Duration::seconds(1),
+            data: json_map! {"app": json!("firefox"), "title": json!("a")},
+        };
+        let mut e2 = e1.clone();
+        e2.duration = Duration::seconds(2);
+        e2.data = json_map! {"app": json!("firefox"), "title": json!("b")};
+        let mut e3 = e1.clone();
+        e3.duration = Duration::seconds(4);
+        let mut e4 = e1.clone();
+        e4.data = json_map! {"app": json!("firefox")};
+        let mut e5 = e1.clone();
+        e5.data = json_map! {"app": json!("vim"), "title": json!("a")};
+
+        let res = chunk_events_by_key(vec![e1, e2, e3, e4, e5], "app", Some("title"));
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].duration, Duration::seconds(8));
+        assert_eq!(res[0].data["title"], json!("a"));
+        assert_eq!(res[0].data["$subchunks"], json!({"a": 5.0, "b": 2.0}));
+        assert_eq!(res[1].data["$subchunks"], json!({"a": 1.0}));
     }
 }
@@ -4,9 +4,27 @@
 use aw_models::Event;
 use regex::{Regex, RegexBuilder};
 
+use crate::stream::Transform;
+
+/// This enum defines the rules for classification.
+/// It's puropse is to make the API easy to extend in the future without having to break backwards
+/// compatibility (or have to maintain "old" query2 functions).
 pub enum Rule {
     None,
     Regex(RegexRule),
+    Glob(GlobRule),
+    /// Matches if all of the rules match, matches if there are no rules
+    And(Vec<Rule>),
+    /// Matches if any of the rules match
+    Or(Vec<Rule>),
+    Not(Box<Rule>),
+}
+
+impl Rule {
+    /// Rule which matches if this rule matches but the exclude rule does not
+    pub fn exclude(self, exclude: Rule) -> Rule {
+        Rule::And(vec![self, Rule::Not(Box::new(exclude))])
+    }
 }
 
 impl RuleTrait for Rule {
@@ -14,6 +32,10 @@ impl RuleTrait for Rule {
         match self {
             Rule::None => false,
             Rule::Regex(rule) => rule.matches(event),
+            Rule::Glob(rule) => rule.matches(event),
+            Rule::And(rules) => rules.iter().all(|rule| rule.matches(event)),
+            Rule::Or(rules) => rules.iter().any(|rule| rule.matches(event)),
+            Rule::Not(rule) => !rule.matches(event),
         }
     }
 }
@@ -22,8 +44,24 @@ trait RuleTrait {
     fn matches(&self, event: &Event) -> bool;
 }
 
+/// String values of the event data, limited to the selected keys if there are any
+fn string_values<'a>(
+    event: &'a Event,
+    select_keys: &'a Option<Vec<String>>,
+) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+    let values = event
+        .data
+        .iter()
+        .filter_map(move |(key, val)| match select_keys {
+            Some(keys) if !keys.contains(key) => None,
+            _ => val.as_str(),
+        });
+    Box::new(values)
+}
+
 pub struct RegexRule {
     regex: Regex,
+    select_keys: Option<Vec<String>>,
 }
 
 impl RegexRule {
@@ -31,28 +69,72 @@ impl RegexRule {
         let mut regex_builder = RegexBuilder::new(regex_str);
         regex_builder.case_insensitive(ignore_case);
         let regex = regex_builder.build()?;
-        Ok(RegexRule { regex })
+        Ok(RegexRule {
+            regex,
+            select_keys: None,
+        })
+    }
+
+    /// Only match against the values of these keys instead of all values of the event data
+    pub fn select_keys(mut self, keys: Vec<String>) -> RegexRule {
+        self.select_keys = Some(keys);
+        self
     }
 }
 
-/// This struct defines the rules for classification.
-/// For now it just needs to contain the regex to match with, but in the future it might contain a
-/// glob-pattern, or other options for classifying.
-/// It's puropse is to make the API easy to extend in the future without having to break backwards
-/// compatibility (or have to maintain "old" query2 functions).
 impl RuleTrait for RegexRule {
     fn matches(&self, event: &Event) -> bool {
-        event
-            .data
-            .values()
-            .filter(|val| val.is_string())
-            .any(|val| self.regex.is_match(val.as_str().unwrap()))
+        string_values(event, &self.select_keys).any(|val| self.regex.is_match(val))
     }
 }
 
 impl From<Regex> for Rule {
     fn from(re: Regex) -> Self {
-        Rule::Regex(RegexRule { regex: re })
+        Rule::Regex(RegexRule {
+            regex: re,
+            select_keys: None,
+        })
+    }
+}
+
+/// Matches if a whole value matches a glob pattern, where * matches any amount of characters
+/// and ? matches a single character
+pub struct GlobRule {
+    regex: Regex,
+    select_keys: Option<Vec<String>>,
+}
+
+impl GlobRule {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<GlobRule, regex::Error> {
+        let mut regex_str = String::from("^");
+        for c in pattern.chars() {
+            match c {
+                '*' => regex_str.push_str(".*"),
+                '?' => regex_str.push('.'),
+                c => regex_str.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex_str.push('$');
+        let regex = RegexBuilder::new(&regex_str)
+            .case_insensitive(ignore_case)
+            .dot_matches_new_line(true)
+            .build()?;
+        Ok(GlobRule {
+            regex,
+            select_keys: None,
+        })
+    }
+
+    /// Only match against the values of these keys instead of all values of the event data
+    pub fn select_keys(mut self, keys: Vec<String>) -> GlobRule {
+        self.select_keys = Some(keys);
+        self
+    }
+}
+
+impl RuleTrait for GlobRule {
+    fn matches(&self, event: &Event) -> bool {
+        string_values(event, &self.select_keys).any(|val| self.regex.is_match(val))
     }
 }
 
@@ -70,11 +152,33 @@ pub fn categorize(mut events: Vec<Event>, rules: &[(Vec<String>, Rule)]) -> Vec<
 }
 
 fn categorize_one(mut event: Event, rules: &[(Vec<String>, Rule)]) -> Event {
-    let mut category: Vec<String> = vec!["Uncategorized".into()];
-    for (cat, rule) in rules {
-        if rule.matches(&event) {
-            category = _pick_highest_ranking_category(category, &cat);
-        }
+    let category = pick_category(&event, rules.iter().map(|(cat, rule)| (cat, rule)));
+    event
+        .data
+        .insert("$category".into(), serde_json::json!(category));
+    event
+}
+
+/// Same as categorize, but rules can also have a score
+///
+/// The score, for instance how productive the category is, is put into the `$score` key of the
+/// event data object. Categories without a score inherit the score of their closest parent
+/// category which has one, events without a score get no `$score` key.
+pub fn categorize_scored(
+    mut events: Vec<Event>,
+    rules: &[(Vec<String>, Rule, Option<f64>)],
+) -> Vec<Event> {
+    let mut classified_events = Vec::new();
+    for event in events.drain(..) {
+        classified_events.push(categorize_scored_one(event, rules));
+    }
+    classified_events
+}
+
+fn categorize_scored_one(mut event: Event, rules: &[(Vec<String>, Rule, Option<f64>)]) -> Event {
+    let category = pick_category(&event, rules.iter().map(|(cat, rule, _score)| (cat, rule)));
+    if let Some(score) = category_score(&category, rules) {
+        event.data.insert("$score".into(), serde_json::json!(score));
     }
     event
         .data
@@ -82,6 +186,33 @@ fn categorize_one(mut event: Event, rules: &[(Vec<String>, Rule)]) -> Event {
     event
 }
 
+/// The deepest category of the rules which match the event
+fn pick_category<'a>(
+    event: &Event,
+    rules: impl Iterator<Item = (&'a Vec<String>, &'a Rule)>,
+) -> Vec<String> {
+    let mut category: Vec<String> = vec!["Uncategorized".into()];
+    for (cat, rule) in rules {
+        if rule.matches(event) {
+            category = _pick_highest_ranking_category(category, &cat);
+        }
+    }
+    category
+}
+
+/// Score of the deepest rule with a score which is the category or one of its parents
+fn category_score(category: &[String], rules: &[(Vec<String>, Rule, Option<f64>)]) -> Option<f64> {
+    let mut best: Option<(usize, f64)> = None;
+    for (cat, _rule, score) in rules {
+        if let Some(score) = score {
+            if category.starts_with(cat) && best.map_or(true, |(depth, _)| cat.len() >= depth) {
+                best = Some((cat.len(), *score));
+            }
+        }
+    }
+    best.map(|(_, score)| score)
+}
+
 /// Tags a list of events
 ///
 /// An event can have many tags (as opposed to only one category) which will be put into the `$tags` key of
@@ -107,6 +238,48 @@ fn tag_one(mut event: Event, rules: &[(String, Rule)]) -> Event {
     event
 }
 
+/// Incremental version of categorize_scored
+pub struct Categorize<'a> {
+    rules: &'a [(Vec<String>, Rule, Option<f64>)],
+}
+
+impl<'a> Categorize<'a> {
+    pub fn new(rules: &'a [(Vec<String>, Rule, Option<f64>)]) -> Categorize<'a> {
+        Categorize { rules }
+    }
+}
+
+impl<'a> Transform for Categorize<'a> {
+    fn push(&mut self, event: Event) -> Vec<Event> {
+        vec![categorize_scored_one(event, self.rules)]
+    }
+
+    fn finish(&mut self) -> Vec<Event> {
+        vec![]
+    }
+}
+
+/// Incremental version of tag
+pub struct Tag<'a> {
+    rules: &'a [(String, Rule)],
+}
+
+impl<'a> Tag<'a> {
+    pub fn new(rules: &'a [(String, Rule)]) -> Tag<'a> {
+        Tag { rules }
+    }
+}
+
+impl<'a> Transform for Tag<'a> {
+    fn push(&mut self, event: Event) -> Vec<Event> {
+        vec![tag_one(event, self.rules)]
+    }
+
+    fn finish(&mut self) -> Vec<Event> {
+        vec![]
+    }
+}
+
 fn _pick_highest_ranking_category(acc: Vec<String>, item: &[String]) -> Vec<String> {
     if item.len() >= acc.len() {
         // If tag is category with greater or equal depth than current, then choose the new one instead.
@@ -139,6 +312,70 @@ fn test_rule() {
     assert_eq!(rule_none.matches(&e_match), false);
 }
 
+#[test]
+fn test_rule_select_keys() {
+    let mut e = Event::default();
+    e.data.insert("app".into(), serde_json::json!("code"));
+    e.data
+        .insert("title".into(), serde_json::json!("main.rs - vim"));
+
+    let rule = Rule::Regex(RegexRule::new("code", false).unwrap());
+    assert_eq!(rule.matches(&e), true);
+    let rule = Rule::Regex(
+        RegexRule::new("code", false)
+            .unwrap()
+            .select_keys(vec!["title".into()]),
+    );
+    assert_eq!(rule.matches(&e), false);
+    let rule = Rule::Regex(
+        RegexRule::new("vim", false)
+            .unwrap()
+            .select_keys(vec!["title".into()]),
+    );
+    assert_eq!(rule.matches(&e), true);
+}
+
+#[test]
+fn test_rule_glob() {
+    let mut e = Event::default();
+    e.data
+        .insert("title".into(), serde_json::json!("main.rs - vim"));
+
+    let glob = |pattern, ignore_case| Rule::Glob(GlobRule::new(pattern, ignore_case).unwrap());
+    assert_eq!(glob("*.rs - vim", false).matches(&e), true);
+    assert_eq!(glob("main.r? - *", false).matches(&e), true);
+    // Globs have to match the whole value
+    assert_eq!(glob("*.rs", false).matches(&e), false);
+    // Regex syntax is matched literally
+    assert_eq!(glob("main.rs - vi[m]", false).matches(&e), false);
+    assert_eq!(glob("MAIN.RS*", false).matches(&e), false);
+    assert_eq!(glob("MAIN.RS*", true).matches(&e), true);
+}
+
+#[test]
+fn test_rule_logic() {
+    let mut e = Event::default();
+    e.data.insert("app".into(), serde_json::json!("firefox"));
+    e.data
+        .insert("title".into(), serde_json::json!("GitHub - reddit"));
+
+    let regex = |s| Rule::from(Regex::new(s).unwrap());
+    let rule = Rule::And(vec![regex("firefox"), regex("GitHub")]);
+    assert_eq!(rule.matches(&e), true);
+    let rule = Rule::And(vec![regex("firefox"), regex("chrome")]);
+    assert_eq!(rule.matches(&e), false);
+    let rule = Rule::Or(vec![regex("chrome"), regex("GitHub")]);
+    assert_eq!(rule.matches(&e), true);
+    let rule = Rule::Or(vec![]);
+    assert_eq!(rule.matches(&e), false);
+    let rule = Rule::Not(Box::new(regex("chrome")));
+    assert_eq!(rule.matches(&e), true);
+    let rule = regex("GitHub").exclude(regex("reddit"));
+    assert_eq!(rule.matches(&e), false);
+    let rule = regex("GitHub").exclude(regex("twitter"));
+    assert_eq!(rule.matches(&e), true);
+}
+
 #[test]
 fn test_categorize() {
     let mut e = Event::default();
@@ -190,6 +427,47 @@ fn test_categorize_uncategorized() {
     );
 }
 
+#[test]
+fn test_categorize_score() {
+    let mut e1 = Event::default();
+    e1.data.insert("app".into(), serde_json::json!("vim"));
+    let mut e2 = Event::default();
+    e2.data.insert("app".into(), serde_json::json!("reddit"));
+    let mut e3 = Event::default();
+    e3.data.insert("app".into(), serde_json::json!("code"));
+    let mut e4 = Event::default();
+    e4.data
+        .insert("app".into(), serde_json::json!("calculator"));
+
+    let rules: Vec<(Vec<String>, Rule, Option<f64>)> = vec![
+        (
+            vec!["Work".into()],
+            Rule::from(Regex::new(r"vim|code").unwrap()),
+            Some(2.0),
+        ),
+        // Inherits the score of Work
+        (
+            vec!["Work".into(), "Editing".into()],
+            Rule::from(Regex::new(r"n?vim").unwrap()),
+            None,
+        ),
+        (
+            vec!["Media".into(), "Social".into()],
+            Rule::from(Regex::new(r"reddit|twitter").unwrap()),
+            Some(-1.0),
+        ),
+    ];
+    let events = categorize_scored(vec![e1, e2, e3, e4], &rules);
+    assert_eq!(
+        events[0].data["$category"],
+        serde_json::json!(vec!["Work", "Editing"])
+    );
+    assert_eq!(events[0].data["$score"], serde_json::json!(2.0));
+    assert_eq!(events[1].data["$score"], serde_json::json!(-1.0));
+    assert_eq!(events[2].data["$score"], serde_json::json!(2.0));
+    assert_eq!(events[3].data.get("$score"), None);
+}
+
 #[test]
 fn test_tag() {
     let mut e = Event::default();
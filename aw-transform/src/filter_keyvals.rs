@@ -2,6 +2,8 @@ use serde_json::value::Value;
 
 use aw_models::Event;
 
+use crate::stream::Transform;
+
 pub fn filter_keyvals(events: Vec<Event>, key: &str, vals: &[Value]) -> Vec<Event> {
     let mut filtered_events = Vec::new();
     for event in events {
@@ -20,6 +22,36 @@ pub fn filter_keyvals(events: Vec<Event>, key: &str, vals: &[Value]) -> Vec<Even
     filtered_events
 }
 
+/// Incremental version of filter_keyvals
+///
+/// Events without the key are dropped.
+pub struct FilterKeyvals {
+    key: String,
+    vals: Vec<Value>,
+}
+
+impl FilterKeyvals {
+    pub fn new(key: &str, vals: Vec<Value>) -> FilterKeyvals {
+        FilterKeyvals {
+            key: key.to_string(),
+            vals,
+        }
+    }
+}
+
+impl Transform for FilterKeyvals {
+    fn push(&mut self, event: Event) -> Vec<Event> {
+        match event.data.get(&self.key) {
+            Some(v) if self.vals.contains(v) => vec![event],
+            _ => vec![],
+        }
+    }
+
+    fn finish(&mut self) -> Vec<Event> {
+        vec![]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -1,5 +1,12 @@
+use std::cmp::max;
+
+use chrono::{DateTime, Utc};
+
 use aw_models::Event;
 
+use crate::sort_by_timestamp;
+use crate::stream::Transform;
+
 pub fn filter_period_intersect(events: &[Event], filter_events: &[Event]) -> Vec<Event> {
     let mut filtered_events = Vec::new();
     for filter in filter_events {
@@ -22,6 +29,132 @@ pub fn filter_period_intersect(events: &[Event], filter_events: &[Event]) -> Vec
     filtered_events
 }
 
+/// Incremental version of filter_period_intersect, events have to be pushed sorted by timestamp
+///
+/// The parts of an event are returned as soon as it is pushed, ordered by the filter events.
+/// Filter events which end before the last pushed event are dropped as they can not overlap any
+/// later events.
+pub struct FilterPeriodIntersect {
+    filter_events: Vec<Event>,
+    /// Index of the first filter event which can overlap the next event
+    first: usize,
+}
+
+impl FilterPeriodIntersect {
+    pub fn new(filter_events: Vec<Event>) -> FilterPeriodIntersect {
+        FilterPeriodIntersect {
+            filter_events: sort_by_timestamp(filter_events),
+            first: 0,
+        }
+    }
+}
+
+impl Transform for FilterPeriodIntersect {
+    fn push(&mut self, event: Event) -> Vec<Event> {
+        // Filter events are sorted by timestamp but not by endtime, so only the leading ones
+        // which have ended can be dropped
+        while self.first < self.filter_events.len()
+            && self.filter_events[self.first].calculate_endtime() < event.timestamp
+        {
+            self.first += 1;
+        }
+        let event_endtime = event.calculate_endtime();
+        let mut filtered_events = Vec::new();
+        for filter in &self.filter_events[self.first..] {
+            if filter.timestamp > event_endtime {
+                break;
+            }
+            let filter_endtime = filter.calculate_endtime();
+            if event.timestamp > filter_endtime {
+                continue;
+            }
+            let mut e = event.clone();
+            e.timestamp = std::cmp::max(e.timestamp, filter.timestamp);
+            let endtime = std::cmp::min(event_endtime, filter_endtime);
+            e.duration = endtime - e.timestamp;
+            filtered_events.push(e);
+        }
+        filtered_events
+    }
+
+    fn finish(&mut self) -> Vec<Event> {
+        vec![]
+    }
+}
+
+/// Removes the time covered by filter_events from events
+///
+/// Events which partially overlap a filter event are cut and events which span over a filter
+/// event are split in two, the parts keep the data of the original event.
+pub fn filter_period_exclude(events: &[Event], filter_events: &[Event]) -> Vec<Event> {
+    let periods = union_periods(filter_events);
+    let mut filtered_events = Vec::new();
+    for event in events {
+        let mut start = event.timestamp;
+        let end = event.calculate_endtime();
+        let mut excluded = false;
+        for (period_start, period_end) in &periods {
+            if *period_end <= start {
+                continue;
+            }
+            if *period_start >= end {
+                break;
+            }
+            if *period_start > start {
+                filtered_events.push(event_part(event, start, *period_start));
+            }
+            start = max(start, *period_end);
+            excluded = true;
+        }
+        if start < end || !excluded {
+            filtered_events.push(event_part(event, start, end));
+        }
+    }
+    filtered_events
+}
+
+/// Merges the overlapping periods of both lists of events
+///
+/// The returned events are sorted by timestamp and have no data, as overlapping events can have
+/// different data.
+pub fn period_union(events1: &[Event], events2: &[Event]) -> Vec<Event> {
+    let events: Vec<Event> = events1.iter().chain(events2.iter()).cloned().collect();
+    union_periods(&events)
+        .into_iter()
+        .map(|(start, end)| Event {
+            id: None,
+            timestamp: start,
+            duration: end - start,
+            data: json_map! {},
+        })
+        .collect()
+}
+
+/// Sorted start and end times of the periods covered by events, periods which overlap or
+/// touch each other are merged
+fn union_periods(events: &[Event]) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut periods: Vec<(DateTime<Utc>, DateTime<Utc>)> = events
+        .iter()
+        .map(|e| (e.timestamp, e.calculate_endtime()))
+        .collect();
+    periods.sort_by_key(|period| period.0);
+    let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for (start, end) in periods {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = max(last.1, end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn event_part(event: &Event, start: DateTime<Utc>, end: DateTime<Utc>) -> Event {
+    let mut e = event.clone();
+    e.timestamp = start;
+    e.duration = end - start;
+    e
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -33,7 +166,7 @@ mod tests {
 
     use aw_models::Event;
 
-    use super::filter_period_intersect;
+    use super::{filter_period_exclude, filter_period_intersect, period_union};
 
     #[test]
     fn test_filter_period_intersect() {
@@ -73,4 +206,70 @@ mod tests {
         let dt: DateTime<Utc> = DateTime::from_str("2000-01-01T00:00:04.000Z").unwrap();
         assert_eq!(filtered_events[2].timestamp, dt);
     }
+
+    #[test]
+    fn test_filter_period_exclude() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"test": json!(1)},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = DateTime::from_str("2000-01-01T00:00:20Z").unwrap();
+        e2.duration = Duration::seconds(2);
+
+        let mut f1 = e1.clone();
+        f1.timestamp = DateTime::from_str("2000-01-01T00:00:02Z").unwrap();
+        f1.duration = Duration::seconds(2);
+        f1.data = json_map! {};
+        // Overlaps the first filter event and cuts the end of e1
+        let mut f2 = f1.clone();
+        f2.timestamp = DateTime::from_str("2000-01-01T00:00:03Z").unwrap();
+        f2.duration = Duration::seconds(1);
+        let mut f3 = f1.clone();
+        f3.timestamp = DateTime::from_str("2000-01-01T00:00:08Z").unwrap();
+        f3.duration = Duration::seconds(5);
+
+        let filtered_events = filter_period_exclude(&[e1, e2.clone()], &[f3, f2, f1]);
+        assert_eq!(filtered_events.len(), 3);
+        let dt: DateTime<Utc> = DateTime::from_str("2000-01-01T00:00:00Z").unwrap();
+        assert_eq!(filtered_events[0].timestamp, dt);
+        assert_eq!(filtered_events[0].duration, Duration::seconds(2));
+        assert_eq!(filtered_events[0].data, json_map! {"test": json!(1)});
+        let dt: DateTime<Utc> = DateTime::from_str("2000-01-01T00:00:04Z").unwrap();
+        assert_eq!(filtered_events[1].timestamp, dt);
+        assert_eq!(filtered_events[1].duration, Duration::seconds(4));
+        // Events which do not overlap any filter event are kept as is
+        assert_eq!(filtered_events[2], e2);
+    }
+
+    #[test]
+    fn test_period_union() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(2),
+            data: json_map! {"status": json!("not-afk")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = DateTime::from_str("2000-01-01T00:00:05Z").unwrap();
+        let mut e3 = e1.clone();
+        e3.timestamp = DateTime::from_str("2000-01-01T00:00:01Z").unwrap();
+        e3.duration = Duration::seconds(3);
+        // Touches the end of e3
+        let mut e4 = e1.clone();
+        e4.timestamp = DateTime::from_str("2000-01-01T00:00:04Z").unwrap();
+        e4.duration = Duration::seconds(0);
+
+        let events = period_union(&[e2, e1], &[e3, e4]);
+        assert_eq!(events.len(), 2);
+        let dt: DateTime<Utc> = DateTime::from_str("2000-01-01T00:00:00Z").unwrap();
+        assert_eq!(events[0].timestamp, dt);
+        assert_eq!(events[0].duration, Duration::seconds(4));
+        assert!(events[0].data.is_empty());
+        let dt: DateTime<Utc> = DateTime::from_str("2000-01-01T00:00:05Z").unwrap();
+        assert_eq!(events[1].timestamp, dt);
+        assert_eq!(events[1].duration, Duration::seconds(2));
+    }
 }
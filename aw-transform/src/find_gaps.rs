@@ -0,0 +1,153 @@
+use std::cmp::{max, min};
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+use crate::sort_by_timestamp;
+
+/// Creates an event for every gap of at least min_gap between events within start and end
+///
+/// The gap events have `{"$gap": true}` as data, which makes untracked time explicit. If AFK
+/// events are given the "status" of the AFK events which cover the most of a gap is added to its
+/// data, gaps which are not covered by any AFK event get no status. Overlapping events are
+/// handled, so the events do not need to be sorted.
+pub fn find_gaps(
+    events: &[Event],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    min_gap: Duration,
+    afk_events: &[Event],
+) -> Vec<Event> {
+    let mut gaps = Vec::new();
+    let mut covered_until = start;
+    for event in sort_by_timestamp(events.to_vec()) {
+        let event_start = min(event.timestamp, end);
+        if event_start - covered_until >= min_gap && event_start > covered_until {
+            gaps.push(gap_event(covered_until, event_start, afk_events));
+        }
+        covered_until = max(covered_until, min(event.calculate_endtime(), end));
+    }
+    if end - covered_until >= min_gap && end > covered_until {
+        gaps.push(gap_event(covered_until, end, afk_events));
+    }
+    gaps
+}
+
+fn gap_event(start: DateTime<Utc>, end: DateTime<Utc>, afk_events: &[Event]) -> Event {
+    let mut event = Event {
+        id: None,
+        timestamp: start,
+        duration: end - start,
+        data: json_map! {"$gap": true},
+    };
+    // Summed overlap of each AFK status with the gap
+    let mut statuses: Vec<(&Value, Duration)> = Vec::new();
+    for afk_event in afk_events {
+        let status = match afk_event.data.get("status") {
+            Some(status) => status,
+            None => continue,
+        };
+        let overlap = min(afk_event.calculate_endtime(), end) - max(afk_event.timestamp, start);
+        if overlap <= Duration::zero() {
+            continue;
+        }
+        match statuses.iter_mut().find(|(s, _)| *s == status) {
+            Some((_, duration)) => *duration = *duration + overlap,
+            None => statuses.push((status, overlap)),
+        }
+    }
+    // The first status wins ties
+    let mut best: Option<(&Value, Duration)> = None;
+    for (status, duration) in statuses {
+        if best.map_or(true, |(_, best_duration)| duration > best_duration) {
+            best = Some((status, duration));
+        }
+    }
+    if let Some((status, _)) = best {
+        event.data.insert("status".to_string(), status.clone());
+    }
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{DateTime, Duration, Utc};
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::find_gaps;
+
+    fn dt(s: &str) -> DateTime<Utc> {
+        DateTime::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let e1 = Event {
+            id: None,
+            timestamp: dt("2000-01-01T00:01:00Z"),
+            duration: Duration::minutes(10),
+            data: json_map! {"app": json!("vim")},
+        };
+        // Overlaps e1
+        let mut e2 = e1.clone();
+        e2.timestamp = dt("2000-01-01T00:05:00Z");
+        e2.duration = Duration::minutes(2);
+        let mut e3 = e1.clone();
+        e3.timestamp = dt("2000-01-01T00:11:30Z");
+        e3.duration = Duration::minutes(10);
+        let mut e4 = e1.clone();
+        e4.timestamp = dt("2000-01-01T00:40:00Z");
+        e4.duration = Duration::minutes(30);
+        let events = vec![e4, e2, e1, e3];
+        let start = dt("2000-01-01T00:00:00Z");
+        let end = dt("2000-01-01T01:00:00Z");
+
+        let gaps = find_gaps(&events, start, end, Duration::minutes(1), &[]);
+        assert_eq!(gaps.len(), 2);
+        assert_eq!(gaps[0].timestamp, start);
+        assert_eq!(gaps[0].duration, Duration::minutes(1));
+        assert_eq!(gaps[0].data, json_map! {"$gap": true});
+        // The gap of 30 seconds between e1 and e3 is too short
+        assert_eq!(gaps[1].timestamp, dt("2000-01-01T00:21:30Z"));
+        assert_eq!(gaps[1].duration, Duration::seconds(1110));
+
+        let mut afk = Event {
+            id: None,
+            timestamp: dt("2000-01-01T00:20:00Z"),
+            duration: Duration::minutes(5),
+            data: json_map! {"status": json!("not-afk")},
+        };
+        let mut afk2 = afk.clone();
+        afk2.timestamp = dt("2000-01-01T00:25:00Z");
+        afk2.duration = Duration::minutes(20);
+        afk2.data = json_map! {"status": json!("afk")};
+        let gaps = find_gaps(
+            &events,
+            start,
+            end,
+            Duration::minutes(1),
+            &[afk.clone(), afk2],
+        );
+        assert_eq!(gaps[0].data, json_map! {"$gap": true});
+        assert_eq!(
+            gaps[1].data,
+            json_map! {"$gap": true, "status": json!("afk")}
+        );
+
+        // No events at all is a single gap
+        afk.timestamp = start;
+        afk.duration = Duration::hours(1);
+        let gaps = find_gaps(&[], start, end, Duration::hours(2), &[afk.clone()]);
+        assert!(gaps.is_empty());
+        let gaps = find_gaps(&[], start, end, Duration::hours(1), &[afk]);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].data["status"], json!("not-afk"));
+    }
+}
@@ -1,62 +1,97 @@
 use aw_models::Event;
 
 use crate::sort_by_timestamp;
+use crate::stream::Transform;
 
 pub fn flood(events: Vec<Event>, pulsetime: chrono::Duration) -> Vec<Event> {
-    let mut warned_negative_gap_safe = false;
-    let mut warned_negative_gap_unsafe = false;
-    let mut events_sorted = sort_by_timestamp(events);
-    let mut e1_iter = events_sorted.drain(..).peekable();
+    let mut flood = Flood::new(pulsetime);
     let mut new_events = Vec::new();
-    let mut drop_next = false;
-    let mut gap_prev: Option<chrono::Duration> = None;
-    while let Some(mut e1) = e1_iter.next() {
-        if drop_next {
-            drop_next = false;
-            continue;
+    for event in sort_by_timestamp(events) {
+        new_events.extend(flood.push(event));
+    }
+    new_events.extend(flood.finish());
+    new_events
+}
+
+/// Incremental version of flood, events have to be pushed sorted by timestamp
+///
+/// Only the last event is kept until the event after it has been pushed.
+pub struct Flood {
+    pulsetime: chrono::Duration,
+    /// Last event, which can still be extended towards the next event
+    last: Option<Event>,
+    /// Gap between the last event which was returned and the next event
+    gap_prev: Option<chrono::Duration>,
+    warned_negative_gap_safe: bool,
+    warned_negative_gap_unsafe: bool,
+}
+
+impl Flood {
+    pub fn new(pulsetime: chrono::Duration) -> Flood {
+        Flood {
+            pulsetime,
+            last: None,
+            gap_prev: None,
+            warned_negative_gap_safe: false,
+            warned_negative_gap_unsafe: false,
         }
-        if let Some(gap) = gap_prev {
-            e1.timestamp = e1.timestamp - (gap / 2);
-            e1.duration = e1.duration + (gap / 2);
-            gap_prev = None;
+    }
+
+    /// Extends the start of an event to the middle of the gap before it
+    fn pre_extend(&mut self, mut event: Event) -> Event {
+        if let Some(gap) = self.gap_prev.take() {
+            event.timestamp = event.timestamp - (gap / 2);
+            event.duration = event.duration + (gap / 2);
         }
-        let e2 = match e1_iter.peek() {
-            Some(e) => e,
+        event
+    }
+}
+
+impl Transform for Flood {
+    fn push(&mut self, e2: Event) -> Vec<Event> {
+        let mut e1 = match self.last.take() {
+            Some(e1) => e1,
             None => {
-                new_events.push(e1);
-                break;
+                self.last = Some(self.pre_extend(e2));
+                return vec![];
             }
         };
 
         let gap = e2.timestamp - e1.calculate_endtime();
 
-        if gap < pulsetime {
+        if gap < self.pulsetime {
             if e1.data == e2.data {
-                if chrono::Duration::seconds(0) > gap && !warned_negative_gap_safe {
+                if chrono::Duration::seconds(0) > gap && !self.warned_negative_gap_safe {
                     warn!("Gap was of negative duration ({}s), but could be safely merged. This error will only show once per batch.", gap);
-                    warned_negative_gap_safe = true;
+                    self.warned_negative_gap_safe = true;
                 }
                 // Extend e1 to the middle between e1 and e2
                 e1.duration = e2.calculate_endtime() - e1.timestamp;
-                // Drop next event since they are merged and flooded into e1
-                drop_next = true;
+                // Drop e2 since they are merged and flooded into e1
+                return vec![e1];
             } else {
                 if chrono::Duration::seconds(0) > gap {
-                    if !warned_negative_gap_unsafe {
+                    if !self.warned_negative_gap_unsafe {
                         warn!("Gap was of negative duration ({}s) and could NOT be safely merged. This error will only show once per batch.", gap);
-                        warned_negative_gap_unsafe = true;
+                        self.warned_negative_gap_unsafe = true;
                     }
-                    continue;
+                    self.last = Some(e2);
+                    return vec![];
                 }
                 // Extend e1 to the middle between e1 and e2
                 e1.duration = e1.duration + (gap / 2);
                 // Make sure next event is pre-extended
-                gap_prev = Some(gap);
+                self.gap_prev = Some(gap);
             }
         }
-        new_events.push(e1.clone());
+        self.last = Some(self.pre_extend(e2));
+        vec![e1]
+    }
+
+    fn finish(&mut self) -> Vec<Event> {
+        self.gap_prev = None;
+        self.last.take().into_iter().collect()
     }
-    new_events
 }
 
 #[cfg(test)]
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::map::Map;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+/// A statistic which can be calculated for each group by group_by
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregation {
+    /// Sum of the durations of the events
+    Duration,
+    /// Amount of events
+    Count,
+    /// Earliest timestamp of the events
+    First,
+    /// Latest timestamp of the events
+    Last,
+    /// Duration of the longest single event
+    Longest,
+    /// The N values of a key with the longest summed durations, parsed from "top:<key>:<n>"
+    Top(String, usize),
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "duration" => Ok(Aggregation::Duration),
+            "count" => Ok(Aggregation::Count),
+            "first" => Ok(Aggregation::First),
+            "last" => Ok(Aggregation::Last),
+            "longest" => Ok(Aggregation::Longest),
+            _ if s.starts_with("top:") => {
+                // Keys can contain ':' themselves, so the amount is after the last one
+                let parts: Vec<&str> = s["top:".len()..].rsplitn(2, ':').collect();
+                match parts.as_slice() {
+                    [n, key] => match n.parse() {
+                        Ok(n) => Ok(Aggregation::Top(key.to_string(), n)),
+                        Err(_) => Err(format!("Invalid amount of top values in '{}'", s)),
+                    },
+                    _ => Err(format!("Invalid top aggregation '{}'", s)),
+                }
+            }
+            _ => Err(format!("Unknown aggregation '{}'", s)),
+        }
+    }
+}
+
+/// Statistics of a group of events which share the same values for the grouped keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    /// Values of the grouped keys
+    pub values: Map<String, Value>,
+    pub duration: Duration,
+    pub count: usize,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    pub longest: Duration,
+    /// Top values for each Aggregation::Top, sorted by longest duration first
+    pub top: HashMap<String, Vec<(Value, Duration)>>,
+}
+
+/// Groups events by the values of keys and calculates statistics for each group
+///
+/// Events which lack any of the keys are ignored. Groups are identified by the value of the key
+/// as JSON when grouping by a single key and a JSON array of the values when grouping by multiple
+/// keys, so values of different types such as "1" and 1 are separate groups.
+/// Only Aggregation::Top needs to be listed in aggregations, the other statistics are cheap
+/// enough to always be calculated.
+pub fn group_by(
+    events: &[Event],
+    keys: &[String],
+    aggregations: &[Aggregation],
+) -> HashMap<String, Group> {
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    let mut top_durations: HashMap<String, TopDurations> = HashMap::new();
+    'event: for event in events {
+        let mut values = Map::new();
+        for key in keys {
+            match event.data.get(key) {
+                Some(v) => values.insert(key.to_string(), v.clone()),
+                None => continue 'event,
+            };
+        }
+        let group_id = group_id(keys, &values);
+        let group = groups.entry(group_id.clone()).or_insert_with(|| Group {
+            values,
+            duration: Duration::zero(),
+            count: 0,
+            first: event.timestamp,
+            last: event.timestamp,
+            longest: Duration::zero(),
+            top: HashMap::new(),
+        });
+        group.duration = group.duration + event.duration;
+        group.count += 1;
+        group.first = group.first.min(event.timestamp);
+        group.last = group.last.max(event.timestamp);
+        group.longest = group.longest.max(event.duration);
+
+        for aggregation in aggregations {
+            if let Aggregation::Top(key, _n) = aggregation {
+                let value = match event.data.get(key) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let top = top_durations
+                    .entry(group_id.clone())
+                    .or_insert_with(HashMap::new)
+                    .entry(key.to_string())
+                    .or_insert_with(HashMap::new)
+                    .entry(value.to_string())
+                    .or_insert_with(|| (value.clone(), Duration::zero()));
+                top.1 = top.1 + event.duration;
+            }
+        }
+    }
+
+    for (group_id, group) in groups.iter_mut() {
+        for aggregation in aggregations {
+            if let Aggregation::Top(key, n) = aggregation {
+                let mut top: Vec<(Value, Duration)> = top_durations
+                    .get_mut(group_id)
+                    .and_then(|tops| tops.remove(key))
+                    .map(|durations| durations.into_iter().map(|(_, v)| v).collect())
+                    .unwrap_or_else(Vec::new);
+                // Sort by value as well to make the order of equal durations stable
+                top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.to_string().cmp(&b.0.to_string())));
+                top.truncate(*n);
+                group.top.insert(key.to_string(), top);
+            }
+        }
+    }
+    groups
+}
+
+/// Summed durations per key and value of that key
+type TopDurations = HashMap<String, HashMap<String, (Value, Duration)>>;
+
+fn group_id(keys: &[String], values: &Map<String, Value>) -> String {
+    if keys.len() == 1 {
+        values[&keys[0]].to_string()
+    } else {
+        let values: Vec<&Value> = keys.iter().map(|key| &values[key]).collect();
+        serde_json::to_string(&values).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use chrono::Duration;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::{group_by, Aggregation};
+
+    #[test]
+    fn test_group_by() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(1),
+            data: json_map! {"app": json!("firefox"), "title": json!("a")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = DateTime::from_str("2000-01-01T00:00:03Z").unwrap();
+        e2.duration = Duration::seconds(3);
+        e2.data = json_map! {"app": json!("firefox"), "title": json!("b")};
+        let mut e3 = e1.clone();
+        e3.timestamp = DateTime::from_str("2000-01-01T00:00:01Z").unwrap();
+        e3.data = json_map! {"app": json!("firefox"), "title": json!("a")};
+        let mut e4 = e1.clone();
+        e4.data = json_map! {"app": json!("vim")};
+        let mut e5 = e1.clone();
+        e5.data = json_map! {"title": json!("no app")};
+
+        let aggregations = vec![Aggregation::Top("title".to_string(), 1)];
+        let groups = group_by(
+            &[e1.clone(), e2.clone(), e3, e4, e5],
+            &["app".to_string()],
+            &aggregations,
+        );
+        assert_eq!(groups.len(), 2);
+
+        let firefox = &groups[r#""firefox""#];
+        assert_eq!(firefox.values, json_map! {"app": json!("firefox")});
+        assert_eq!(firefox.duration, Duration::seconds(5));
+        assert_eq!(firefox.count, 3);
+        assert_eq!(firefox.first, e1.timestamp);
+        assert_eq!(firefox.last, e2.timestamp);
+        assert_eq!(firefox.longest, Duration::seconds(3));
+        assert_eq!(
+            firefox.top["title"],
+            vec![(json!("b"), Duration::seconds(3))]
+        );
+
+        let vim = &groups[r#""vim""#];
+        assert_eq!(vim.count, 1);
+        assert!(vim.top["title"].is_empty());
+
+        // Multiple keys
+        let groups = group_by(
+            &[e1.clone(), e2],
+            &["app".to_string(), "title".to_string()],
+            &[],
+        );
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[r#"["firefox","a"]"#].count, 1);
+
+        // Values of different types are different groups
+        let mut e6 = e1.clone();
+        e6.data = json_map! {"app": json!(1)};
+        let mut e7 = e1;
+        e7.data = json_map! {"app": json!("1")};
+        let groups = group_by(&[e6, e7], &["app".to_string()], &[]);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["1"].values, json_map! {"app": json!(1)});
+        assert_eq!(groups[r#""1""#].values, json_map! {"app": json!("1")});
+    }
+
+    #[test]
+    fn test_parse_aggregation() {
+        assert_eq!(Aggregation::from_str("count"), Ok(Aggregation::Count));
+        assert_eq!(
+            Aggregation::from_str("top:title:5"),
+            Ok(Aggregation::Top("title".to_string(), 5))
+        );
+        assert_eq!(
+            Aggregation::from_str("top:url:path:3"),
+            Ok(Aggregation::Top("url:path".to_string(), 3))
+        );
+        assert!(Aggregation::from_str("top:title").is_err());
+        assert!(Aggregation::from_str("top:title:x").is_err());
+        assert!(Aggregation::from_str("median").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+use crate::timeslots::{slot_parts, split_period, Resolution};
+
+/// Time spent within one time slot of a histogram
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeSlot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Sum of the durations of all events within the slot
+    pub duration: Duration,
+    /// Durations per value of the key the histogram was grouped by
+    pub totals: HashMap<String, Duration>,
+}
+
+/// Sums the durations of events within each time slot between start and end
+///
+/// Events are split at the slot boundaries so each part counts towards the slot it is within.
+/// All slots are returned, including the ones without any events. If a key is given the
+/// durations are also summed per value of that key, events without the key are then only
+/// counted in the total duration of the slot.
+pub fn histogram(
+    events: &[Event],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+    key: Option<&str>,
+) -> Result<Vec<TimeSlot>, String> {
+    let periods = split_period(start, end, resolution, tz, offset)?;
+    let mut slots: Vec<TimeSlot> = periods
+        .iter()
+        .map(|(slot_start, slot_end)| TimeSlot {
+            start: *slot_start,
+            end: *slot_end,
+            duration: Duration::zero(),
+            totals: HashMap::new(),
+        })
+        .collect();
+    for (i, event, duration) in slot_parts(events, &periods, resolution, tz, offset)? {
+        let slot = &mut slots[i];
+        slot.duration = slot.duration + duration;
+        let group = match key.and_then(|key| event.data.get(key)) {
+            Some(Value::String(s)) => s.to_string(),
+            Some(value) => value.to_string(),
+            None => continue,
+        };
+        let total = slot.totals.entry(group).or_insert_with(Duration::zero);
+        *total = *total + duration;
+    }
+    Ok(slots)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{DateTime, Duration, Utc};
+    use chrono_tz::Tz;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::histogram;
+    use crate::timeslots::Resolution;
+
+    fn dt(s: &str) -> DateTime<Utc> {
+        DateTime::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_histogram() {
+        let e1 = Event {
+            id: None,
+            timestamp: dt("2000-01-01T00:30:00Z"),
+            duration: Duration::minutes(60),
+            data: json_map! {"app": json!("firefox")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = dt("2000-01-01T01:40:00Z");
+        e2.duration = Duration::minutes(10);
+        e2.data = json_map! {"app": json!("vim")};
+        let mut e3 = e1.clone();
+        e3.timestamp = dt("2000-01-01T01:50:00Z");
+        e3.duration = Duration::minutes(5);
+        e3.data = json_map! {};
+
+        let slots = histogram(
+            &[e1, e2, e3],
+            dt("2000-01-01T00:00:00Z"),
+            dt("2000-01-01T03:00:00Z"),
+            Resolution::Hour,
+            &Tz::UTC,
+            Duration::zero(),
+            Some("app"),
+        )
+        .unwrap();
+        assert_eq!(slots.len(), 3);
+        assert_eq!(slots[0].start, dt("2000-01-01T00:00:00Z"));
+        assert_eq!(slots[0].end, dt("2000-01-01T01:00:00Z"));
+        assert_eq!(slots[0].duration, Duration::minutes(30));
+        assert_eq!(slots[0].totals["firefox"], Duration::minutes(30));
+
+        assert_eq!(slots[1].duration, Duration::minutes(45));
+        assert_eq!(slots[1].totals.len(), 2);
+        assert_eq!(slots[1].totals["firefox"], Duration::minutes(30));
+        assert_eq!(slots[1].totals["vim"], Duration::minutes(10));
+
+        // Empty slots are included as well
+        assert_eq!(slots[2].duration, Duration::zero());
+        assert!(slots[2].totals.is_empty());
+    }
+
+    #[test]
+    fn test_histogram_timezone() {
+        let e1 = Event {
+            id: None,
+            timestamp: dt("2000-01-01T22:00:00Z"),
+            duration: Duration::hours(4),
+            data: json_map! {},
+        };
+        // Days start at 00:00 in Stockholm, which is 23:00 UTC
+        let slots = histogram(
+            &[e1],
+            dt("2000-01-01T00:00:00Z"),
+            dt("2000-01-03T00:00:00Z"),
+            Resolution::Day,
+            &Tz::Europe__Stockholm,
+            Duration::zero(),
+            None,
+        )
+        .unwrap();
+        let durations: Vec<Duration> = slots.iter().map(|slot| slot.duration).collect();
+        assert_eq!(
+            durations,
+            vec![Duration::hours(1), Duration::hours(3), Duration::zero()]
+        );
+        assert!(slots[0].totals.is_empty());
+    }
+}
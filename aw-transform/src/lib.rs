@@ -1,3 +1,6 @@
+#[macro_use]
+extern crate lazy_static;
+
 #[macro_use]
 extern crate log;
 
@@ -17,6 +20,10 @@ macro_rules! json_map {
 }
 
 pub mod classify;
+pub mod stream;
+pub mod timeslots;
+
+pub use classify::{Categorize, Tag};
 
 mod heartbeat;
 pub use heartbeat::heartbeat;
@@ -25,10 +32,10 @@ mod find_bucket;
 pub use find_bucket::find_bucket;
 
 mod flood;
-pub use flood::flood;
+pub use flood::{flood, Flood};
 
 mod merge;
-pub use merge::merge_events_by_keys;
+pub use merge::{merge_events_by_keys, MergeEventsByKeys};
 
 mod chunk;
 pub use chunk::chunk_events_by_key;
@@ -37,10 +44,44 @@ mod sort;
 pub use sort::{sort_by_duration, sort_by_timestamp};
 
 mod filter_keyvals;
-pub use filter_keyvals::filter_keyvals;
+pub use filter_keyvals::{filter_keyvals, FilterKeyvals};
 
 mod filter_period;
-pub use filter_period::filter_period_intersect;
+pub use filter_period::{
+    filter_period_exclude, filter_period_intersect, period_union, FilterPeriodIntersect,
+};
+
+mod find_gaps;
+pub use find_gaps::find_gaps;
+
+mod resolve_overlaps;
+pub use resolve_overlaps::resolve_overlaps;
+
+mod public_suffix;
 
 mod split_url;
 pub use split_url::split_url_event;
+
+mod redact;
+pub use redact::{redact, Redaction};
+
+mod replace_keyvals;
+pub use replace_keyvals::replace_keyvals;
+
+mod sessionize;
+pub use sessionize::sessionize;
+
+mod resample;
+pub use resample::{resample, MAX_RESAMPLE_SLOTS};
+
+mod histogram;
+pub use histogram::{histogram, TimeSlot};
+
+mod productivity;
+pub use productivity::{productivity, Productivity};
+
+mod category_tree;
+pub use category_tree::{category_tree, CategoryNode};
+
+mod group_by;
+pub use group_by::{group_by, Aggregation, Group};
@@ -2,43 +2,69 @@ use std::collections::HashMap;
 
 use aw_models::Event;
 
-#[allow(clippy::map_entry)]
+use crate::stream::Transform;
+
 pub fn merge_events_by_keys(events: Vec<Event>, keys: Vec<String>) -> Vec<Event> {
-    if keys.is_empty() {
-        return vec![];
+    let mut merge = MergeEventsByKeys::new(keys);
+    for event in events {
+        merge.push(event);
+    }
+    merge.finish()
+}
+
+/// Incremental version of merge_events_by_keys
+///
+/// Only a merged event for each combination of values is kept, they are all returned by finish.
+pub struct MergeEventsByKeys {
+    keys: Vec<String>,
+    merged_events_map: HashMap<String, Event>,
+}
+
+impl MergeEventsByKeys {
+    pub fn new(keys: Vec<String>) -> MergeEventsByKeys {
+        MergeEventsByKeys {
+            keys,
+            merged_events_map: HashMap::new(),
+        }
     }
-    let mut merged_events_map: HashMap<String, Event> = HashMap::new();
-    'event: for event in events {
+}
+
+impl Transform for MergeEventsByKeys {
+    #[allow(clippy::map_entry)]
+    fn push(&mut self, event: Event) -> Vec<Event> {
+        if self.keys.is_empty() {
+            return vec![];
+        }
         let mut key_values = Vec::new();
-        for key in &keys {
+        for key in &self.keys {
             match event.data.get(key) {
                 Some(v) => key_values.push(v.to_string()),
-                None => continue 'event,
+                None => return vec![],
             }
         }
         let summed_key = key_values.join(".");
-        if merged_events_map.contains_key(&summed_key) {
-            let merged_event = merged_events_map.get_mut(&summed_key).unwrap();
+        if self.merged_events_map.contains_key(&summed_key) {
+            let merged_event = self.merged_events_map.get_mut(&summed_key).unwrap();
             merged_event.duration = merged_event.duration + event.duration;
         } else {
-            let mut data = HashMap::new();
-            for key in &keys {
-                data.insert(key.clone(), event.data.get(key).unwrap());
-            }
             let merged_event = Event {
                 id: None,
                 timestamp: event.timestamp,
                 duration: event.duration,
-                data: event.data.clone(),
+                data: event.data,
             };
-            merged_events_map.insert(summed_key, merged_event);
+            self.merged_events_map.insert(summed_key, merged_event);
         }
+        vec![]
     }
-    let mut merged_events_list = Vec::new();
-    for (_key, event) in merged_events_map.drain() {
-        merged_events_list.push(event);
+
+    fn finish(&mut self) -> Vec<Event> {
+        let mut merged_events_list = Vec::new();
+        for (_key, event) in self.merged_events_map.drain() {
+            merged_events_list.push(event);
+        }
+        merged_events_list
     }
-    merged_events_list
 }
 
 #[cfg(test)]
@@ -0,0 +1,137 @@
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+
+use aw_models::Event;
+
+use crate::timeslots::{slot_parts, split_period, Resolution};
+
+/// Productivity of the events within one time slot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Productivity {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Sum of the durations of all events within the slot
+    pub duration: Duration,
+    /// Time spent on events with a positive score
+    pub productive: Duration,
+    /// Time spent on events with a score of zero or without a score
+    pub neutral: Duration,
+    /// Time spent on events with a negative score
+    pub distracting: Duration,
+    /// Average score of the events weighted by their duration, zero if there are no events
+    pub score: f64,
+}
+
+/// Calculates the duration-weighted productivity within each time slot between start and end
+///
+/// The score of an event is read from `$score` in its data, as set by classify::categorize.
+/// Events without a score count as neutral time with a score of zero. Events are split at the
+/// slot boundaries the same way as in histogram.
+pub fn productivity(
+    events: &[Event],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<Vec<Productivity>, String> {
+    let periods = split_period(start, end, resolution, tz, offset)?;
+    let mut slots: Vec<Productivity> = periods
+        .iter()
+        .map(|(slot_start, slot_end)| Productivity {
+            start: *slot_start,
+            end: *slot_end,
+            duration: Duration::zero(),
+            productive: Duration::zero(),
+            neutral: Duration::zero(),
+            distracting: Duration::zero(),
+            score: 0.0,
+        })
+        .collect();
+    // Sum of score times seconds for each slot
+    let mut weighted_scores = vec![0.0; slots.len()];
+    for (i, event, duration) in slot_parts(events, &periods, resolution, tz, offset)? {
+        let score = event
+            .data
+            .get("$score")
+            .and_then(|score| score.as_f64())
+            .unwrap_or(0.0);
+        let slot = &mut slots[i];
+        slot.duration = slot.duration + duration;
+        if score > 0.0 {
+            slot.productive = slot.productive + duration;
+        } else if score < 0.0 {
+            slot.distracting = slot.distracting + duration;
+        } else {
+            slot.neutral = slot.neutral + duration;
+        }
+        weighted_scores[i] += score * duration.num_milliseconds() as f64 / 1000.0;
+    }
+    for (slot, weighted_score) in slots.iter_mut().zip(weighted_scores) {
+        let seconds = slot.duration.num_milliseconds() as f64 / 1000.0;
+        if seconds > 0.0 {
+            slot.score = weighted_score / seconds;
+        }
+    }
+    Ok(slots)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{DateTime, Duration, Utc};
+    use chrono_tz::Tz;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::productivity;
+    use crate::timeslots::Resolution;
+
+    fn dt(s: &str) -> DateTime<Utc> {
+        DateTime::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_productivity() {
+        let e1 = Event {
+            id: None,
+            timestamp: dt("2000-01-01T00:00:00Z"),
+            duration: Duration::minutes(30),
+            data: json_map! {"$score": json!(2.0)},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = dt("2000-01-01T00:30:00Z");
+        e2.duration = Duration::minutes(15);
+        e2.data = json_map! {"$score": json!(-1.0)};
+        let mut e3 = e1.clone();
+        e3.timestamp = dt("2000-01-01T00:45:00Z");
+        e3.duration = Duration::minutes(30);
+        e3.data = json_map! {};
+
+        let slots = productivity(
+            &[e1, e2, e3],
+            dt("2000-01-01T00:00:00Z"),
+            dt("2000-01-01T03:00:00Z"),
+            Resolution::Hour,
+            &Tz::UTC,
+            Duration::zero(),
+        )
+        .unwrap();
+        assert_eq!(slots.len(), 3);
+        assert_eq!(slots[0].duration, Duration::minutes(60));
+        assert_eq!(slots[0].productive, Duration::minutes(30));
+        assert_eq!(slots[0].distracting, Duration::minutes(15));
+        assert_eq!(slots[0].neutral, Duration::minutes(15));
+        // (30 * 2 - 15) / 60
+        assert!((slots[0].score - 0.75).abs() < f64::EPSILON);
+
+        assert_eq!(slots[1].duration, Duration::minutes(15));
+        assert_eq!(slots[1].neutral, Duration::minutes(15));
+        assert!(slots[1].score.abs() < f64::EPSILON);
+
+        assert_eq!(slots[2].duration, Duration::zero());
+        assert!(slots[2].score.abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+/// Rules of the public suffix list, see https://publicsuffix.org/list/ for the format
+struct PublicSuffixList {
+    rules: HashSet<&'static str>,
+    /// Rules starting with "*.", stored without it
+    wildcards: HashSet<&'static str>,
+    /// Rules starting with "!", stored without it
+    exceptions: HashSet<&'static str>,
+}
+
+impl PublicSuffixList {
+    fn parse(list: &'static str) -> PublicSuffixList {
+        let mut psl = PublicSuffixList {
+            rules: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+        for line in list.lines() {
+            // Rules end at the first whitespace
+            let rule = match line.split_whitespace().next() {
+                Some(rule) if !rule.starts_with("//") => rule,
+                _ => continue,
+            };
+            if rule.starts_with("*.") {
+                psl.wildcards.insert(&rule[2..]);
+            } else if rule.starts_with('!') {
+                psl.exceptions.insert(&rule[1..]);
+            } else {
+                psl.rules.insert(rule);
+            }
+        }
+        psl
+    }
+
+    /// Amount of labels of the public suffix of the labels of a domain
+    fn suffix_len(&self, labels: &[&str]) -> usize {
+        let n = labels.len();
+        // Longest match first, the default rule "*" makes the last label a suffix
+        for i in 0..n {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(candidate.as_str()) {
+                return n - i - 1;
+            }
+            if self.rules.contains(candidate.as_str()) {
+                return n - i;
+            }
+            if i + 1 < n && self.wildcards.contains(labels[i + 1..].join(".").as_str()) {
+                return n - i;
+            }
+        }
+        1
+    }
+}
+
+lazy_static! {
+    static ref PUBLIC_SUFFIX_LIST: PublicSuffixList =
+        PublicSuffixList::parse(include_str!("public_suffix_list.dat"));
+}
+
+/// Returns the registrable domain of a host, which is its public suffix and one more label
+///
+/// For instance "www.example.co.uk" gives "example.co.uk" and "user.github.io" gives
+/// "user.github.io". Returns None for IP addresses and hosts which are a public suffix.
+/// Internationalized domain names only match the rules of the list when given as unicode.
+pub fn registrable_domain(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.').to_lowercase();
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() || ip.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.iter().any(|label| label.is_empty()) {
+        return None;
+    }
+    let suffix_len = PUBLIC_SUFFIX_LIST.suffix_len(&labels);
+    if labels.len() <= suffix_len {
+        return None;
+    }
+    Some(labels[labels.len() - suffix_len - 1..].join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::registrable_domain;
+
+    #[test]
+    fn test_registrable_domain() {
+        let domain = |host| registrable_domain(host);
+        assert_eq!(domain("www.google.com"), Some("google.com".to_string()));
+        assert_eq!(domain("WWW.Google.COM."), Some("google.com".to_string()));
+        assert_eq!(domain("news.bbc.co.uk"), Some("bbc.co.uk".to_string()));
+        assert_eq!(
+            domain("activitywatch.github.io"),
+            Some("activitywatch.github.io".to_string())
+        );
+        // Wildcard and exception rules
+        assert_eq!(domain("a.b.ck"), Some("a.b.ck".to_string()));
+        assert_eq!(domain("www.ck"), Some("www.ck".to_string()));
+        // Not in the list, so the default rule applies
+        assert_eq!(domain("a.b.unknowntld"), Some("b.unknowntld".to_string()));
+
+        assert_eq!(domain("co.uk"), None);
+        assert_eq!(domain("github.io"), None);
+        assert_eq!(domain("localhost"), None);
+        assert_eq!(domain("127.0.0.1"), None);
+        assert_eq!(domain("[::1]"), None);
+        assert_eq!(domain(""), None);
+    }
+}
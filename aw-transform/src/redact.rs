@@ -0,0 +1,154 @@
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use serde_json::value::Value;
+use sha2::Sha256;
+
+use aw_models::Event;
+
+use crate::split_url_event;
+
+/// How the values of redacted keys are replaced
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redaction {
+    /// Replaces values with the hex encoded HMAC-SHA256 of the value keyed with the salt, so
+    /// equal values can still be grouped without revealing them
+    Hash(String),
+    /// Replaces URLs with their domain, values which are not URLs become empty strings
+    Domain,
+    /// Replaces values with the `$category` of the event as set by classify::categorize
+    Category,
+    /// Removes the keys from the event data
+    Remove,
+}
+
+impl Redaction {
+    /// Parses a redaction method, the salt is only used by "hash" which requires it
+    ///
+    /// Without a secret salt hashes of guessable values such as titles could be reversed by
+    /// hashing candidates.
+    pub fn from_method(method: &str, salt: Option<&str>) -> Result<Redaction, String> {
+        match method {
+            "hash" => match salt {
+                Some(salt) if !salt.is_empty() => Ok(Redaction::Hash(salt.to_string())),
+                _ => Err("The hash redaction method requires a salt".to_string()),
+            },
+            "domain" => Ok(Redaction::Domain),
+            "category" => Ok(Redaction::Category),
+            "remove" => Ok(Redaction::Remove),
+            _ => Err(format!(
+                "Unknown redaction method '{}', expected hash, domain, category or remove",
+                method
+            )),
+        }
+    }
+}
+
+/// Redacts the values of keys in the data of events
+pub fn redact(mut events: Vec<Event>, keys: &[String], redaction: &Redaction) -> Vec<Event> {
+    for event in events.iter_mut() {
+        for key in keys {
+            let value = match event.data.get(key) {
+                Some(value) => value,
+                None => continue,
+            };
+            let redacted = match redaction {
+                Redaction::Hash(salt) => {
+                    let value = match value {
+                        Value::String(s) => s.to_string(),
+                        value => value.to_string(),
+                    };
+                    Value::String(hash(salt, &value))
+                }
+                Redaction::Domain => {
+                    let mut url_event = Event::default();
+                    url_event.data.insert("url".to_string(), value.clone());
+                    split_url_event(&mut url_event);
+                    match url_event.data.remove("$domain") {
+                        Some(domain) => domain,
+                        None => json!(""),
+                    }
+                }
+                Redaction::Category => match event.data.get("$category") {
+                    Some(category) => category.clone(),
+                    None => json!(["Uncategorized"]),
+                },
+                Redaction::Remove => {
+                    event.data.remove(key);
+                    continue;
+                }
+            };
+            event.data.insert(key.to_string(), redacted);
+        }
+    }
+    events
+}
+
+fn hash(salt: &str, value: &str) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_varkey(salt.as_bytes()).unwrap();
+    mac.input(value.as_bytes());
+    mac.result()
+        .code()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use chrono::Duration;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::{redact, Redaction};
+
+    #[test]
+    fn test_redact() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(1),
+            data: json_map! {
+                "title": json!("Secret plans"),
+                "url": json!("https://www.example.com/secret?plans=1"),
+                "$category": json!(["Work"])
+            },
+        };
+        let mut e2 = e1.clone();
+        e2.data = json_map! {"title": json!("Other secret plans")};
+        let keys = vec!["title".to_string(), "url".to_string()];
+
+        let hash = Redaction::from_method("hash", Some("salt")).unwrap();
+        let res = redact(vec![e1.clone(), e1.clone(), e2.clone()], &keys, &hash);
+        assert_eq!(
+            res[0].data["title"],
+            json!("eefeb180fc3dd5848cfb98723d41b4a07f06e5982e3ff34b0265bd826d6d2c82")
+        );
+        assert_eq!(res[0].data, res[1].data);
+        assert_ne!(res[0].data["title"], res[2].data["title"]);
+        assert!(res[2].data.get("url").is_none());
+        // Another salt gives other hashes
+        let other_hash = Redaction::from_method("hash", Some("pepper")).unwrap();
+        let other_res = redact(vec![e1.clone()], &keys, &other_hash);
+        assert_ne!(res[0].data["title"], other_res[0].data["title"]);
+
+        let res = redact(vec![e1.clone()], &keys, &Redaction::Domain);
+        assert_eq!(res[0].data["url"], json!("example.com"));
+        assert_eq!(res[0].data["title"], json!(""));
+
+        let res = redact(vec![e1.clone(), e2], &keys, &Redaction::Category);
+        assert_eq!(res[0].data["title"], json!(["Work"]));
+        assert_eq!(res[1].data["title"], json!(["Uncategorized"]));
+
+        let res = redact(vec![e1], &keys, &Redaction::Remove);
+        assert_eq!(res[0].data, json_map! {"$category": json!(["Work"])});
+
+        assert!(Redaction::from_method("rot13", None).is_err());
+        assert!(Redaction::from_method("hash", None).is_err());
+        assert!(Redaction::from_method("hash", Some("")).is_err());
+    }
+}
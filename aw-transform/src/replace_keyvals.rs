@@ -0,0 +1,70 @@
+use regex::Regex;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+/// Replaces all matches of a regex in the value of key with replacement
+///
+/// The replacement can refer to capture groups of the regex with $1, $name etc.
+/// Events where the value of key is missing or is not a string are left unchanged.
+pub fn replace_keyvals(
+    mut events: Vec<Event>,
+    key: &str,
+    regex: &Regex,
+    replacement: &str,
+) -> Vec<Event> {
+    for event in events.iter_mut() {
+        let replaced = match event.data.get(key) {
+            Some(Value::String(val)) => regex.replace_all(val, replacement).into_owned(),
+            _ => continue,
+        };
+        event.data.insert(key.to_string(), Value::String(replaced));
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use chrono::Duration;
+    use regex::Regex;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::replace_keyvals;
+
+    #[test]
+    fn test_replace_keyvals() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(1),
+            data: json_map! {"title": json!("(3) Inbox - Gmail")},
+        };
+        let mut e2 = e1.clone();
+        e2.data = json_map! {"title": json!("Inbox - Gmail")};
+        let mut e3 = e1.clone();
+        e3.data = json_map! {"title": json!(1)};
+        let mut e4 = e1.clone();
+        e4.data = json_map! {"app": json!("(3) Inbox - Gmail")};
+
+        let regex = Regex::new(r"^\(\d+\) ").unwrap();
+        let res = replace_keyvals(
+            vec![e1, e2.clone(), e3.clone(), e4.clone()],
+            "title",
+            &regex,
+            "",
+        );
+        assert_eq!(res, vec![e2.clone(), e2, e3, e4.clone()]);
+
+        // Capture groups can be used in the replacement
+        let mut e5 = e4;
+        e5.data = json_map! {"title": json!("Inbox - Gmail")};
+        let regex = Regex::new(r"^(.*) - (.*)$").unwrap();
+        let res = replace_keyvals(vec![e5], "title", &regex, "$2: $1");
+        assert_eq!(res[0].data["title"], json!("Gmail: Inbox"));
+    }
+}
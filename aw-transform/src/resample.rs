@@ -0,0 +1,172 @@
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::map::Map;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+/// Max amount of slots between start and end, which bounds the work done for each event
+pub const MAX_RESAMPLE_SLOTS: i64 = 1_000_000;
+
+/// Resamples events into slots of a fixed length between start and end
+///
+/// Each slot with any events becomes a single event with the dominant data of the slot, which
+/// is the data with the longest summed duration within the slot. If a key is given events are
+/// compared by the value of that key and the new events only have that key in their data,
+/// events without the key are ignored. The new events start at the start of their slot and
+/// their duration is the time covered by events within the slot, so summed durations are kept.
+/// Slots are aligned to start and the last slot is cut at end.
+///
+/// Fails if the resolution is less than 1ms or if there would be more than MAX_RESAMPLE_SLOTS
+/// slots between start and end.
+pub fn resample(
+    events: &[Event],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    resolution: Duration,
+    key: Option<&str>,
+) -> Result<Vec<Event>, String> {
+    let resolution_ms = resolution.num_milliseconds();
+    if resolution_ms <= 0 {
+        return Err("Resolution of resample has to be at least 1ms".to_string());
+    }
+    let slot_count = (end - start).num_milliseconds() / resolution_ms;
+    if slot_count > MAX_RESAMPLE_SLOTS {
+        return Err(format!(
+            "Resampling would create {} slots, more than the max of {}",
+            slot_count, MAX_RESAMPLE_SLOTS
+        ));
+    }
+    // Summed durations of each data within each slot, keyed by slot index
+    let mut slots: BTreeMap<i64, DataDurations> = BTreeMap::new();
+    for event in events {
+        let data = match key {
+            Some(key) => match event.data.get(key) {
+                Some(value) => {
+                    let mut data = Map::new();
+                    data.insert(key.to_string(), value.clone());
+                    data
+                }
+                None => continue,
+            },
+            None => event.data.clone(),
+        };
+        let data_id = serde_json::to_string(&data).unwrap();
+        let mut part_start = max(event.timestamp, start);
+        let event_end = min(event.calculate_endtime(), end);
+        while part_start < event_end {
+            let i = (part_start - start).num_milliseconds() / resolution_ms;
+            let slot_end = min(start + Duration::milliseconds((i + 1) * resolution_ms), end);
+            let part_end = min(event_end, slot_end);
+            let entry = slots
+                .entry(i)
+                .or_insert_with(HashMap::new)
+                .entry(data_id.clone())
+                .or_insert_with(|| (data.clone(), Duration::zero()));
+            entry.1 = entry.1 + (part_end - part_start);
+            part_start = part_end;
+        }
+    }
+
+    let mut resampled_events = Vec::new();
+    for (i, mut durations) in slots {
+        let covered = durations
+            .values()
+            .fold(Duration::zero(), |acc, (_, duration)| acc + *duration);
+        // Ties are broken by the serialized data to keep the result deterministic
+        let dominant_id = durations
+            .iter()
+            .max_by(|(id_a, a), (id_b, b)| a.1.cmp(&b.1).then(id_b.cmp(id_a)))
+            .map(|(id, _)| id.to_string())
+            .unwrap();
+        let (data, _) = durations.remove(&dominant_id).unwrap();
+        resampled_events.push(Event {
+            id: None,
+            timestamp: start + Duration::milliseconds(i * resolution_ms),
+            duration: covered,
+            data,
+        });
+    }
+    Ok(resampled_events)
+}
+
+/// Data and its summed duration, keyed by the serialized data
+type DataDurations = HashMap<String, (Map<String, Value>, Duration)>;
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{DateTime, Duration, Utc};
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::resample;
+
+    fn dt(s: &str) -> DateTime<Utc> {
+        DateTime::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_resample() {
+        let e1 = Event {
+            id: None,
+            timestamp: dt("2000-01-01T00:00:00Z"),
+            duration: Duration::minutes(2),
+            data: json_map! {"app": json!("firefox"), "title": json!("a")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = dt("2000-01-01T00:02:00Z");
+        e2.duration = Duration::minutes(2);
+        e2.data = json_map! {"app": json!("firefox"), "title": json!("b")};
+        let mut e3 = e1.clone();
+        e3.timestamp = dt("2000-01-01T00:04:00Z");
+        e3.duration = Duration::minutes(3);
+        e3.data = json_map! {"app": json!("vim"), "title": json!("c")};
+        let mut e4 = e1.clone();
+        e4.timestamp = dt("2000-01-01T00:20:00Z");
+        e4.duration = Duration::minutes(1);
+        e4.data = json_map! {"title": json!("d")};
+
+        let events = vec![e1, e2, e3, e4];
+        let start = dt("2000-01-01T00:00:00Z");
+        let end = dt("2000-01-01T01:00:00Z");
+        let res = resample(&events, start, end, Duration::minutes(5), Some("app")).unwrap();
+        assert_eq!(res.len(), 2);
+        // firefox has 4 minutes of the first slot, vim 1 minute
+        assert_eq!(res[0].timestamp, start);
+        assert_eq!(res[0].duration, Duration::minutes(5));
+        assert_eq!(res[0].data, json_map! {"app": json!("firefox")});
+        assert_eq!(res[1].timestamp, dt("2000-01-01T00:05:00Z"));
+        assert_eq!(res[1].duration, Duration::minutes(2));
+        assert_eq!(res[1].data, json_map! {"app": json!("vim")});
+
+        // Without a key the whole data is compared, ties go to the smallest data
+        let res = resample(&events, start, end, Duration::minutes(5), None).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].data["title"], json!("a"));
+        assert_eq!(res[2].timestamp, dt("2000-01-01T00:20:00Z"));
+        assert_eq!(res[2].data, json_map! {"title": json!("d")});
+
+        // The last slot is cut at the end
+        let res = resample(
+            &events,
+            start,
+            dt("2000-01-01T00:03:00Z"),
+            Duration::minutes(5),
+            Some("title"),
+        )
+        .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].duration, Duration::minutes(3));
+        assert_eq!(res[0].data, json_map! {"title": json!("a")});
+
+        // Too small resolutions are rejected instead of looping over every slot
+        assert!(resample(&events, start, end, Duration::zero(), None).is_err());
+        let long_end = dt("2100-01-01T00:00:00Z");
+        assert!(resample(&events, start, long_end, Duration::milliseconds(1), None).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use aw_models::Event;
+
+use crate::{filter_period_exclude, period_union, sort_by_timestamp};
+
+/// Combines lists of events into a single timeline without overlapping events
+///
+/// The lists are given in priority order, events are cut where they overlap events of lists
+/// with a higher priority. Overlapping events within the same list are cut where they overlap
+/// an earlier event of that list. The parts of events which remain keep their data.
+pub fn resolve_overlaps(event_lists: Vec<Vec<Event>>) -> Vec<Event> {
+    let mut resolved_events = Vec::new();
+    let mut covered: Vec<Event> = Vec::new();
+    for events in event_lists {
+        let mut events_no_overlap = Vec::new();
+        let mut last_end = None;
+        for mut event in sort_by_timestamp(events) {
+            let end = event.calculate_endtime();
+            if let Some(last_end) = last_end {
+                if end <= last_end && event.timestamp < last_end {
+                    continue;
+                }
+                if event.timestamp < last_end {
+                    event.timestamp = last_end;
+                    event.duration = end - last_end;
+                }
+            }
+            last_end = Some(end);
+            events_no_overlap.push(event);
+        }
+        let clipped_events = filter_period_exclude(&events_no_overlap, &covered);
+        covered = period_union(&covered, &clipped_events);
+        resolved_events.extend(clipped_events);
+    }
+    sort_by_timestamp(resolved_events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::resolve_overlaps;
+
+    #[test]
+    fn test_resolve_overlaps() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:10Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"source": json!("high")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = DateTime::from_str("2000-01-01T00:00:00Z").unwrap();
+        e2.duration = Duration::seconds(30);
+        e2.data = json_map! {"source": json!("low")};
+        // Overlaps e2 which has the same priority but is earlier
+        let mut e3 = e2.clone();
+        e3.timestamp = DateTime::from_str("2000-01-01T00:00:25Z").unwrap();
+        e3.duration = Duration::seconds(10);
+
+        let events = resolve_overlaps(vec![vec![e1.clone()], vec![e3, e2.clone()]]);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].timestamp, e2.timestamp);
+        assert_eq!(events[0].duration, Duration::seconds(10));
+        assert_eq!(events[0].data, e2.data);
+        assert_eq!(events[1], e1);
+        let dt: DateTime<Utc> = DateTime::from_str("2000-01-01T00:00:20Z").unwrap();
+        assert_eq!(events[2].timestamp, dt);
+        assert_eq!(events[2].duration, Duration::seconds(10));
+        let dt: DateTime<Utc> = DateTime::from_str("2000-01-01T00:00:30Z").unwrap();
+        assert_eq!(events[3].timestamp, dt);
+        assert_eq!(events[3].duration, Duration::seconds(5));
+
+        let total = events
+            .iter()
+            .fold(Duration::zero(), |acc, e| acc + e.duration);
+        assert_eq!(total, Duration::seconds(35));
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+
+use aw_models::Event;
+
+use crate::sort_by_timestamp;
+
+/// Groups events into sessions which are separated by gaps longer than max_gap
+///
+/// Each session is returned as an event spanning from the start of its first event to the end
+/// of its last event, with the following data:
+/// - "events": amount of events in the session
+/// - "active": sum of the durations of the events in seconds, overlaps are counted twice
+/// - "distinct": amount of distinct values of key
+/// - "switches": amount of times the value of key changed between consecutive events
+///
+/// Events without the key count towards the session but are ignored by distinct and switches.
+pub fn sessionize(events: Vec<Event>, max_gap: Duration, key: &str) -> Vec<Event> {
+    let mut sessions = Vec::new();
+    let mut session: Option<Session> = None;
+    for event in sort_by_timestamp(events) {
+        if let Some(s) = session.take() {
+            if event.timestamp - s.end > max_gap {
+                sessions.push(s.into_event());
+            } else {
+                session = Some(s);
+            }
+        }
+        let s = session.get_or_insert_with(|| Session::new(&event));
+        s.add(&event, key);
+    }
+    if let Some(s) = session {
+        sessions.push(s.into_event());
+    }
+    sessions
+}
+
+struct Session {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    events: usize,
+    active: Duration,
+    values: HashSet<String>,
+    last_value: Option<String>,
+    switches: usize,
+}
+
+impl Session {
+    fn new(event: &Event) -> Session {
+        Session {
+            start: event.timestamp,
+            end: event.timestamp,
+            events: 0,
+            active: Duration::zero(),
+            values: HashSet::new(),
+            last_value: None,
+            switches: 0,
+        }
+    }
+
+    fn add(&mut self, event: &Event, key: &str) {
+        self.end = self.end.max(event.calculate_endtime());
+        self.events += 1;
+        self.active = self.active + event.duration;
+        if let Some(value) = event.data.get(key) {
+            let value = value.to_string();
+            if let Some(ref last_value) = self.last_value {
+                if *last_value != value {
+                    self.switches += 1;
+                }
+            }
+            self.values.insert(value.clone());
+            self.last_value = Some(value);
+        }
+    }
+
+    fn into_event(self) -> Event {
+        Event {
+            id: None,
+            timestamp: self.start,
+            duration: self.end - self.start,
+            data: json_map! {
+                "events": self.events,
+                "active": self.active.num_milliseconds() as f64 / 1000.0,
+                "distinct": self.values.len(),
+                "switches": self.switches
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use chrono::Duration;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::sessionize;
+
+    #[test]
+    fn test_sessionize() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"app": json!("firefox")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = DateTime::from_str("2000-01-01T00:00:15Z").unwrap();
+        e2.data = json_map! {"app": json!("vim")};
+        let mut e3 = e1.clone();
+        e3.timestamp = DateTime::from_str("2000-01-01T00:00:30Z").unwrap();
+        let mut e4 = e1.clone();
+        e4.timestamp = DateTime::from_str("2000-01-01T00:00:40Z").unwrap();
+        e4.data = json_map! {};
+        // Starts a new session as the gap is longer than 60s
+        let mut e5 = e1.clone();
+        e5.timestamp = DateTime::from_str("2000-01-01T00:02:00Z").unwrap();
+
+        let sessions = sessionize(
+            vec![e5, e4, e3, e2, e1.clone()],
+            Duration::seconds(60),
+            "app",
+        );
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].timestamp, e1.timestamp);
+        assert_eq!(sessions[0].duration, Duration::seconds(50));
+        assert_eq!(
+            sessions[0].data,
+            json_map! {"events": 4, "active": 40.0, "distinct": 2, "switches": 2}
+        );
+        assert_eq!(sessions[1].duration, Duration::seconds(10));
+        assert_eq!(
+            sessions[1].data,
+            json_map! {"events": 1, "active": 10.0, "distinct": 1, "switches": 0}
+        );
+
+        assert!(sessionize(vec![], Duration::seconds(60), "app").is_empty());
+    }
+}
@@ -1,6 +1,20 @@
 use aw_models::Event;
+use rocket::http::RawStr;
+use rocket::request::FormItems;
+use serde_json::map::Map;
 use serde_json::value::Value;
 
+use crate::public_suffix::registrable_domain;
+
+/// Splits the "url" of an event into its parts
+///
+/// Adds the following keys to the event data:
+/// - "$protocol", "$domain" (without "www."), "$path" and "$params" (the raw query string)
+/// - "$query": object of the decoded query parameters, the last value wins for repeated keys
+/// - "$path_segments": list of the decoded non-empty segments of the path
+/// - "$registrable_domain": domain which can be registered according to the public suffix list,
+///   for instance "example.co.uk" for "www.example.co.uk", empty if there is none
+/// - "$repository": "owner/name" for repository URLs of GitHub, GitLab and Bitbucket
 pub fn split_url_event(event: &mut Event) {
     use rocket::http::uri::Absolute;
     let uri_str = match event.data.get("url") {
@@ -34,7 +48,7 @@ pub fn split_url_event(event: &mut Event) {
     };
     event.data.insert("$path".to_string(), Value::String(path));
     // Params
-    // TODO: What's the difference between params and query?
+    // The raw query string, it's called params for compatibility with earlier versions
     let params = match uri.origin() {
         Some(origin) => match origin.query() {
             Some(query) => query.to_string(),
@@ -42,13 +56,146 @@ pub fn split_url_event(event: &mut Event) {
         },
         None => "".to_string(),
     };
+    // Query
+    let mut query = Map::new();
+    for item in FormItems::from(params.as_str()) {
+        let (key, value) = item.key_value_decoded();
+        query.insert(key, Value::String(value));
+    }
     event
         .data
         .insert("$params".to_string(), Value::String(params));
+    event
+        .data
+        .insert("$query".to_string(), Value::Object(query));
+    // Path segments
+    let segments: Vec<String> = match uri.origin() {
+        Some(origin) => origin
+            .segments()
+            .map(|segment| RawStr::from_str(segment).percent_decode_lossy().to_string())
+            .collect(),
+        None => Vec::new(),
+    };
+    // Registrable domain
+    let host = match uri.authority() {
+        Some(authority) => authority.host(),
+        None => "",
+    };
+    let registrable = registrable_domain(host).unwrap_or_else(|| "".to_string());
+    // Repository of well-known code hosting sites
+    if let Some(repository) = repository(&registrable, &segments) {
+        event
+            .data
+            .insert("$repository".to_string(), Value::String(repository));
+    }
+    event.data.insert(
+        "$registrable_domain".to_string(),
+        Value::String(registrable),
+    );
+    event.data.insert(
+        "$path_segments".to_string(),
+        Value::Array(segments.into_iter().map(Value::String).collect()),
+    );
 
     // TODO: aw-server-python also has options and identifier
 }
 
+/// First path segments on GitHub which are not users or organizations
+const GITHUB_RESERVED: &[&str] = &[
+    "about",
+    "apps",
+    "codespaces",
+    "collections",
+    "dashboard",
+    "enterprise",
+    "explore",
+    "features",
+    "issues",
+    "login",
+    "marketplace",
+    "new",
+    "notifications",
+    "organizations",
+    "orgs",
+    "pricing",
+    "pulls",
+    "search",
+    "settings",
+    "sponsors",
+    "topics",
+    "trending",
+];
+
+/// First path segments on GitLab which are not groups or users
+const GITLAB_RESERVED: &[&str] = &[
+    "-",
+    "admin",
+    "dashboard",
+    "explore",
+    "groups",
+    "help",
+    "projects",
+    "search",
+    "snippets",
+    "users",
+];
+
+/// First path segments on Bitbucket which are not workspaces
+const BITBUCKET_RESERVED: &[&str] = &[
+    "account",
+    "blog",
+    "dashboard",
+    "product",
+    "repo",
+    "site",
+    "snippets",
+    "socialauth",
+    "workspace",
+];
+
+/// Second path segments on Bitbucket which are pages of a workspace rather than repositories
+const BITBUCKET_WORKSPACE_PAGES: &[&str] = &["profile", "projects", "workspace"];
+
+/// Repository as "owner/name" if the URL is within a repository of a code hosting site
+fn repository(registrable_domain: &str, segments: &[String]) -> Option<String> {
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let parts: &[&str] = match registrable_domain {
+        "github.com" => {
+            if segments.len() < 2 || GITHUB_RESERVED.contains(&segments[0]) {
+                return None;
+            }
+            &segments[..2]
+        }
+        "bitbucket.org" => {
+            if segments.len() < 2
+                || BITBUCKET_RESERVED.contains(&segments[0])
+                || BITBUCKET_WORKSPACE_PAGES.contains(&segments[1])
+            {
+                return None;
+            }
+            &segments[..2]
+        }
+        // Projects on GitLab can be in nested groups, so the path of a project page can't be
+        // told apart from a subgroup. Only pages within a project, which come after a "-", and
+        // clone URLs are known to be within a repository.
+        "gitlab.com" => {
+            if segments.is_empty() || GITLAB_RESERVED.contains(&segments[0]) {
+                return None;
+            }
+            match segments.iter().position(|segment| *segment == "-") {
+                Some(separator) => &segments[..separator],
+                None if segments[segments.len() - 1].ends_with(".git") => &segments,
+                None => return None,
+            }
+        }
+        _ => return None,
+    };
+    if parts.len() < 2 {
+        return None;
+    }
+    Some(parts.join("/").trim_end_matches(".git").to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -77,8 +224,90 @@ mod tests {
                 "$protocol": json!("http"),
                 "$domain": json!("google.com"),
                 "$path": json!("/path"),
-                "$params": json!("query=1")
+                "$params": json!("query=1"),
+                "$query": json!({"query": "1"}),
+                "$path_segments": json!(["path"]),
+                "$registrable_domain": json!("google.com")
             }
         );
     }
+
+    #[test]
+    fn test_split_url_query_and_segments() {
+        let mut e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:01Z").unwrap(),
+            duration: Duration::seconds(1),
+            data: json_map! {"url": "https://docs.example.co.uk/a%20b/c/?q=hello+world&x=%2F&x=2&flag"},
+        };
+        split_url_event(&mut e1);
+        assert_eq!(e1.data["$domain"], json!("docs.example.co.uk"));
+        assert_eq!(e1.data["$registrable_domain"], json!("example.co.uk"));
+        assert_eq!(e1.data["$path_segments"], json!(["a b", "c"]));
+        assert_eq!(
+            e1.data["$query"],
+            json!({"q": "hello world", "x": "2", "flag": ""})
+        );
+        assert!(e1.data.get("$repository").is_none());
+
+        let mut e2 = e1;
+        e2.data = json_map! {"url": "http://192.168.0.1:8080/"};
+        split_url_event(&mut e2);
+        assert_eq!(e2.data["$registrable_domain"], json!(""));
+        assert_eq!(e2.data["$path_segments"], json!([]));
+        assert_eq!(e2.data["$query"], json!({}));
+    }
+
+    #[test]
+    fn test_split_url_repository() {
+        let repository = |url: &str| {
+            let mut e = Event::default();
+            e.data.insert("url".to_string(), json!(url));
+            split_url_event(&mut e);
+            e.data.get("$repository").cloned()
+        };
+        assert_eq!(
+            repository("https://github.com/ActivityWatch/aw-server-rust/pull/1"),
+            Some(json!("ActivityWatch/aw-server-rust"))
+        );
+        assert_eq!(
+            repository("https://github.com/ActivityWatch/aw-server-rust.git"),
+            Some(json!("ActivityWatch/aw-server-rust"))
+        );
+        assert_eq!(
+            repository("https://gitlab.com/group/subgroup/project/-/issues/1"),
+            Some(json!("group/subgroup/project"))
+        );
+        assert_eq!(repository("https://github.com/ActivityWatch"), None);
+        assert_eq!(repository("https://github.com/settings/profile"), None);
+        assert_eq!(
+            repository("https://gitlab.com/group/project.git"),
+            Some(json!("group/project"))
+        );
+        assert_eq!(
+            repository("https://bitbucket.org/workspace-name/repo-name/src/master/"),
+            Some(json!("workspace-name/repo-name"))
+        );
+
+        // Pages of the sites which aren't repositories
+        assert_eq!(repository("https://gitlab.com/explore/projects"), None);
+        assert_eq!(repository("https://gitlab.com/users/sign_in"), None);
+        assert_eq!(repository("https://gitlab.com/group/subgroup"), None);
+        assert_eq!(repository("https://gitlab.com/groups/group/-/issues"), None);
+        assert_eq!(
+            repository("https://gitlab.com/dashboard/merge_requests"),
+            None
+        );
+        assert_eq!(repository("https://bitbucket.org/account/signin"), None);
+        assert_eq!(repository("https://bitbucket.org/dashboard/overview"), None);
+        assert_eq!(
+            repository("https://bitbucket.org/workspace-name/workspace/projects"),
+            None
+        );
+        assert_eq!(repository("https://bitbucket.org/product/features"), None);
+        assert_eq!(
+            repository("https://activitywatch.github.io/aw-server-rust/docs"),
+            None
+        );
+    }
 }
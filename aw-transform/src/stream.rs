@@ -0,0 +1,172 @@
+//! Incremental versions of the transforms which process events one at a time
+//!
+//! The transforms of this crate take and return whole lists of events, so a chain of them keeps
+//! several copies of all events in memory. A Transform instead takes the events one by one and
+//! only keeps the state it needs, such as the last event or the merged events so far. Transforms
+//! are chained with `then` and run over any iterator of events with `process`, which is lazy.
+//!
+//! ```
+//! use aw_transform::stream::Transform;
+//! use aw_transform::{Flood, MergeEventsByKeys};
+//!
+//! # let events: Vec<aw_models::Event> = Vec::new();
+//! let pipeline = Flood::new(chrono::Duration::seconds(5))
+//!     .then(MergeEventsByKeys::new(vec!["app".to_string()]));
+//! let merged: Vec<aw_models::Event> = pipeline.process(events).collect();
+//! ```
+
+use std::collections::VecDeque;
+
+use aw_models::Event;
+
+/// A transform which takes events one at a time
+///
+/// Most transforms expect the events to be pushed sorted by timestamp, like the list based
+/// transforms expect sorted lists.
+pub trait Transform {
+    /// Takes the next event and returns the events which are done so far
+    fn push(&mut self, event: Event) -> Vec<Event>;
+
+    /// Returns the remaining events once all events have been pushed
+    fn finish(&mut self) -> Vec<Event>;
+
+    /// Chains another transform which takes the events returned by this one
+    fn then<T: Transform>(self, next: T) -> Chain<Self, T>
+    where
+        Self: Sized,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+
+    /// Lazily runs the transform over events
+    fn process<I: IntoIterator<Item = Event>>(self, events: I) -> Process<Self, I::IntoIter>
+    where
+        Self: Sized,
+    {
+        Process {
+            transform: self,
+            events: events.into_iter(),
+            done: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+/// Two transforms run after each other, see Transform::then
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Transform, B: Transform> Transform for Chain<A, B> {
+    fn push(&mut self, event: Event) -> Vec<Event> {
+        let mut done = Vec::new();
+        for event in self.first.push(event) {
+            done.extend(self.second.push(event));
+        }
+        done
+    }
+
+    fn finish(&mut self) -> Vec<Event> {
+        let mut done = Vec::new();
+        for event in self.first.finish() {
+            done.extend(self.second.push(event));
+        }
+        done.extend(self.second.finish());
+        done
+    }
+}
+
+/// Iterator over the events returned by a transform, see Transform::process
+pub struct Process<T, I> {
+    transform: T,
+    events: I,
+    done: VecDeque<Event>,
+    finished: bool,
+}
+
+impl<T: Transform, I: Iterator<Item = Event>> Iterator for Process<T, I> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.done.pop_front() {
+                return Some(event);
+            }
+            if self.finished {
+                return None;
+            }
+            match self.events.next() {
+                Some(event) => self.done.extend(self.transform.push(event)),
+                None => {
+                    self.done.extend(self.transform.finish());
+                    self.finished = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use chrono::Duration;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::Transform;
+    use crate::classify::{categorize_scored, RegexRule, Rule};
+    use crate::{
+        filter_period_intersect, flood, merge_events_by_keys, sort_by_duration, Categorize,
+        FilterPeriodIntersect, Flood, MergeEventsByKeys,
+    };
+
+    #[test]
+    fn test_pipeline() {
+        let e1 = Event {
+            id: None,
+            timestamp: DateTime::from_str("2000-01-01T00:00:00Z").unwrap(),
+            duration: Duration::seconds(10),
+            data: json_map! {"app": json!("vim")},
+        };
+        let mut e2 = e1.clone();
+        e2.timestamp = DateTime::from_str("2000-01-01T00:00:12Z").unwrap();
+        e2.data = json_map! {"app": json!("firefox")};
+        let mut e3 = e1.clone();
+        e3.timestamp = DateTime::from_str("2000-01-01T00:00:25Z").unwrap();
+        let mut e4 = e1.clone();
+        e4.timestamp = DateTime::from_str("2000-01-01T00:01:00Z").unwrap();
+        let mut filter = e1.clone();
+        filter.timestamp = DateTime::from_str("2000-01-01T00:00:05Z").unwrap();
+        filter.duration = Duration::seconds(50);
+        let events = vec![e1, e2, e3, e4];
+        let rules = vec![(
+            vec!["Work".to_string()],
+            Rule::Regex(RegexRule::new("vim", false).unwrap()),
+            None,
+        )];
+        let keys = vec!["$category".to_string()];
+
+        let pipeline = Flood::new(Duration::seconds(5))
+            .then(FilterPeriodIntersect::new(vec![filter.clone()]))
+            .then(Categorize::new(&rules))
+            .then(MergeEventsByKeys::new(keys.clone()));
+        let streamed = sort_by_duration(pipeline.process(events.clone()).collect());
+
+        let flooded = flood(events, Duration::seconds(5));
+        let filtered = filter_period_intersect(&flooded, &[filter]);
+        let categorized = categorize_scored(filtered, &rules);
+        let merged = sort_by_duration(merge_events_by_keys(categorized, keys));
+
+        assert_eq!(streamed.len(), 2);
+        assert_eq!(streamed, merged);
+        // vim from 00:05 to 00:11 and 00:23.5 to 00:35 after flooding
+        assert_eq!(streamed[0].duration, Duration::milliseconds(17500));
+    }
+}
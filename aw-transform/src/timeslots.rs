@@ -0,0 +1,489 @@
+use std::cmp::{max, min};
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone,
+    Timelike, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use serde_json::json;
+
+use aw_models::Event;
+
+/// Length of the time slots which periods are split into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Hour,
+    Day,
+    Week,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(Resolution::Hour),
+            "day" => Ok(Resolution::Day),
+            "week" => Ok(Resolution::Week),
+            _ => Err(format!(
+                "Unknown resolution '{}', expected hour, day or week",
+                s
+            )),
+        }
+    }
+}
+
+/// Start and end of a time slot
+pub type Slot = (DateTime<Utc>, DateTime<Utc>);
+
+/// Max absolute offset in hours of the start of days from midnight
+pub const MAX_DAY_OFFSET_HOURS: f64 = 24.0;
+
+/// Error of the functions in this module for times too close to the limits of chrono
+fn out_of_range() -> String {
+    "Time is out of the supported range".to_string()
+}
+
+/// Offset of the start of days from midnight from a number of hours
+///
+/// Fails unless the number of hours is strictly between -MAX_DAY_OFFSET_HOURS and
+/// MAX_DAY_OFFSET_HOURS.
+pub fn day_offset(hours: f64) -> Result<Duration, String> {
+    if hours.is_nan() || hours.abs() >= MAX_DAY_OFFSET_HOURS {
+        return Err(format!(
+            "Day offset has to be between -{0} and {0} hours, got {1}",
+            MAX_DAY_OFFSET_HOURS, hours
+        ));
+    }
+    Ok(Duration::milliseconds((hours * 3_600_000.0) as i64))
+}
+
+/// Converts a local time to UTC
+///
+/// Ambiguous times use the earliest alternative, times which are skipped by a DST transition are
+/// moved forward to the first time which exists.
+fn local_to_utc(tz: &Tz, local: NaiveDateTime) -> Result<DateTime<Utc>, String> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(dt) | LocalResult::Ambiguous(dt, _) => Ok(dt.with_timezone(&Utc)),
+        LocalResult::None => {
+            let later = local
+                .checked_add_signed(Duration::minutes(15))
+                .ok_or_else(out_of_range)?;
+            local_to_utc(tz, later)
+        }
+    }
+}
+
+/// Local date of the day or week slot which contains time, days are shifted by offset
+fn slot_date(
+    time: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<NaiveDate, String> {
+    let date = time
+        .with_timezone(tz)
+        .naive_local()
+        .checked_sub_signed(offset)
+        .ok_or_else(out_of_range)?
+        .date();
+    match resolution {
+        Resolution::Week => date
+            .checked_sub_signed(Duration::days(date.weekday().num_days_from_monday() as i64))
+            .ok_or_else(out_of_range),
+        _ => Ok(date),
+    }
+}
+
+/// Local midnight of date plus offset in UTC
+fn date_start(date: NaiveDate, tz: &Tz, offset: Duration) -> Result<DateTime<Utc>, String> {
+    let local = date
+        .and_hms(0, 0, 0)
+        .checked_add_signed(offset)
+        .ok_or_else(out_of_range)?;
+    local_to_utc(tz, local)
+}
+
+/// Start of the time slot which contains time
+///
+/// Days and weeks start at local midnight plus offset, weeks start on mondays. The offset is not
+/// used for hours.
+pub fn slot_start(
+    time: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<DateTime<Utc>, String> {
+    match resolution {
+        Resolution::Hour => {
+            // Truncate in the local time so timezones with offsets which are not whole hours
+            // get the correct boundaries, this also works for hours which occur twice
+            let local = time.with_timezone(tz);
+            let into_hour = Duration::minutes(local.minute() as i64)
+                + Duration::seconds(local.second() as i64)
+                + Duration::nanoseconds(local.nanosecond() as i64);
+            time.checked_sub_signed(into_hour).ok_or_else(out_of_range)
+        }
+        Resolution::Day | Resolution::Week => {
+            let date = slot_date(time, resolution, tz, offset)?;
+            date_start(date, tz, offset)
+        }
+    }
+}
+
+/// Start of the time slot which comes after the one containing time
+pub fn next_slot_start(
+    time: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<DateTime<Utc>, String> {
+    match resolution {
+        Resolution::Hour => slot_start(time, resolution, tz, offset)?
+            .checked_add_signed(Duration::hours(1))
+            .ok_or_else(out_of_range),
+        Resolution::Day | Resolution::Week => {
+            let days = if resolution == Resolution::Day { 1 } else { 7 };
+            let date = slot_date(time, resolution, tz, offset)?
+                .checked_add_signed(Duration::days(days))
+                .ok_or_else(out_of_range)?;
+            date_start(date, tz, offset)
+        }
+    }
+}
+
+/// Splits the period between start and end at the boundaries of the time slots it overlaps
+pub fn split_period(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<Vec<Slot>, String> {
+    let mut periods = Vec::new();
+    let mut period_start = start;
+    while period_start < end {
+        let period_end = min(next_slot_start(period_start, resolution, tz, offset)?, end);
+        periods.push((period_start, period_end));
+        period_start = period_end;
+    }
+    Ok(periods)
+}
+
+/// Splits events at the boundaries of slots as returned by split_period
+///
+/// Returns the index of the slot each part of an event is within, together with the event and
+/// the duration of the part. Parts of events outside of the slots are skipped.
+pub fn slot_parts<'a>(
+    events: &'a [Event],
+    slots: &[Slot],
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<Vec<(usize, &'a Event, Duration)>, String> {
+    let mut slot_parts = Vec::new();
+    let (start, end) = match (slots.first(), slots.last()) {
+        (Some((start, _)), Some((_, end))) => (*start, *end),
+        _ => return Ok(slot_parts),
+    };
+    for event in events {
+        let parts = split_period(
+            max(event.timestamp, start),
+            min(event.calculate_endtime(), end),
+            resolution,
+            tz,
+            offset,
+        )?;
+        for (part_start, part_end) in parts {
+            let i = match slots.binary_search_by_key(&part_start, |slot| slot.0) {
+                Ok(i) => i,
+                Err(i) => i - 1,
+            };
+            slot_parts.push((i, event, part_end - part_start));
+        }
+    }
+    Ok(slot_parts)
+}
+
+/// Same as split_period, but returns an event for each part
+///
+/// The events have the local date of the slot as "date" and the ISO weekday number as "weekday"
+/// in their data, hour slots also have the local "hour".
+pub fn split_period_events(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<Vec<Event>, String> {
+    split_period(start, end, resolution, tz, offset)?
+        .into_iter()
+        .map(|(period_start, period_end)| {
+            let date = slot_date(period_start, resolution, tz, offset)?;
+            let mut event = Event {
+                id: None,
+                timestamp: period_start,
+                duration: period_end - period_start,
+                data: json_map! {
+                    "date": date.to_string(),
+                    "weekday": date.weekday().number_from_monday()
+                },
+            };
+            if resolution == Resolution::Hour {
+                let hour = period_start.with_timezone(tz).hour();
+                event.data.insert("hour".to_string(), json!(hour));
+            }
+            Ok(event)
+        })
+        .collect()
+}
+
+/// Splits events at the local day boundaries of a timezone, days start at midnight plus offset
+///
+/// Events which span several days are replaced by a part for each day with the same id and data,
+/// so summed durations per day are correct. The order of the events is kept and the parts of an
+/// event are in chronological order.
+pub fn split_events_by_day(
+    events: Vec<Event>,
+    tz: &Tz,
+    offset: Duration,
+) -> Result<Vec<Event>, String> {
+    let mut split_events = Vec::new();
+    for event in events {
+        let periods = split_period(
+            event.timestamp,
+            event.calculate_endtime(),
+            Resolution::Day,
+            tz,
+            offset,
+        )?;
+        // Events without a duration have no periods but are kept as they are
+        if periods.len() <= 1 {
+            split_events.push(event);
+            continue;
+        }
+        for (period_start, period_end) in periods {
+            let mut part = event.clone();
+            part.timestamp = period_start;
+            part.duration = period_end - period_start;
+            split_events.push(part);
+        }
+    }
+    Ok(split_events)
+}
+
+/// Creates an event for the time between from and to in the local time of every day within the
+/// period which is one of the weekdays
+///
+/// If to is before from the event ends on the following day. The events are cut to the period
+/// and have the local date of the day they start on as "date" in their data.
+pub fn daily_periods(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    from: NaiveTime,
+    to: NaiveTime,
+    weekdays: &[Weekday],
+    tz: &Tz,
+) -> Result<Vec<Event>, String> {
+    let mut events = Vec::new();
+    // Start a day early as periods on the previous day can end on the following day
+    let mut date = start
+        .with_timezone(tz)
+        .naive_local()
+        .date()
+        .pred_opt()
+        .ok_or_else(out_of_range)?;
+    let last_date = end.with_timezone(tz).naive_local().date();
+    while date <= last_date {
+        let next_date = date.succ_opt().ok_or_else(out_of_range)?;
+        if weekdays.contains(&date.weekday()) {
+            let end_date = if to > from { date } else { next_date };
+            let period_start = local_to_utc(tz, date.and_time(from))?.max(start);
+            let period_end = local_to_utc(tz, end_date.and_time(to))?.min(end);
+            if period_start < period_end {
+                events.push(Event {
+                    id: None,
+                    timestamp: period_start,
+                    duration: period_end - period_start,
+                    data: json_map! {
+                        "date": date.to_string(),
+                        "weekday": date.weekday().number_from_monday()
+                    },
+                });
+            }
+        }
+        date = next_date;
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{DateTime, Duration, NaiveTime, Utc, Weekday};
+    use chrono_tz::Tz;
+    use serde_json::json;
+
+    use aw_models::Event;
+
+    use super::{
+        daily_periods, day_offset, next_slot_start, slot_start, split_events_by_day, split_period,
+        split_period_events, Resolution,
+    };
+
+    fn dt(s: &str) -> DateTime<Utc> {
+        DateTime::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_slot_start() {
+        let tz = Tz::Europe__Stockholm;
+        let time = dt("2020-03-04T23:30:00Z");
+        let res = |r| slot_start(time, r, &tz, Duration::zero()).unwrap();
+        assert_eq!(res(Resolution::Hour), dt("2020-03-04T23:00:00Z"));
+        // Already the next day in Stockholm
+        assert_eq!(res(Resolution::Day), dt("2020-03-04T23:00:00Z"));
+        assert_eq!(res(Resolution::Week), dt("2020-03-01T23:00:00Z"));
+
+        // Days which start at 04:00
+        let start = slot_start(time, Resolution::Day, &tz, Duration::hours(4)).unwrap();
+        assert_eq!(start, dt("2020-03-04T03:00:00Z"));
+
+        // India is 5:30 ahead of UTC
+        let tz = Tz::Asia__Kolkata;
+        let start = slot_start(time, Resolution::Hour, &tz, Duration::zero()).unwrap();
+        assert_eq!(start, dt("2020-03-04T23:30:00Z"));
+    }
+
+    #[test]
+    fn test_day_offset() {
+        assert_eq!(day_offset(4.5).unwrap(), Duration::minutes(270));
+        assert_eq!(day_offset(-23.0).unwrap(), Duration::hours(-23));
+        assert!(day_offset(24.0).is_err());
+        assert!(day_offset(-1e12).is_err());
+        assert!(day_offset(std::f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_slot_start_out_of_range() {
+        // Errors instead of overflowing at the limits of chrono
+        let max = chrono::MAX_DATE.and_hms(12, 0, 0);
+        assert!(next_slot_start(max, Resolution::Day, &Tz::UTC, Duration::zero()).is_err());
+        assert!(next_slot_start(max, Resolution::Hour, &Tz::UTC, Duration::zero()).is_ok());
+        let min = chrono::MIN_DATE.and_hms(0, 0, 0);
+        assert!(slot_start(min, Resolution::Day, &Tz::UTC, Duration::hours(4)).is_err());
+        assert!(slot_start(min, Resolution::Week, &Tz::UTC, Duration::zero()).is_err());
+    }
+
+    #[test]
+    fn test_split_period() {
+        let tz = Tz::UTC;
+        let periods = split_period(
+            dt("2020-01-01T22:30:00Z"),
+            dt("2020-01-02T01:00:00Z"),
+            Resolution::Hour,
+            &tz,
+            Duration::zero(),
+        )
+        .unwrap();
+        assert_eq!(
+            periods,
+            vec![
+                (dt("2020-01-01T22:30:00Z"), dt("2020-01-01T23:00:00Z")),
+                (dt("2020-01-01T23:00:00Z"), dt("2020-01-02T00:00:00Z")),
+                (dt("2020-01-02T00:00:00Z"), dt("2020-01-02T01:00:00Z")),
+            ]
+        );
+
+        // The day DST starts in Stockholm is only 23 hours long
+        let tz = Tz::Europe__Stockholm;
+        let events = split_period_events(
+            dt("2020-03-28T23:00:00Z"),
+            dt("2020-03-30T22:00:00Z"),
+            Resolution::Day,
+            &tz,
+            Duration::zero(),
+        )
+        .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].duration, Duration::hours(23));
+        assert_eq!(events[0].data["date"], json!("2020-03-29"));
+        assert_eq!(events[0].data["weekday"], json!(7));
+        assert_eq!(events[1].timestamp, dt("2020-03-29T22:00:00Z"));
+        assert_eq!(events[1].duration, Duration::hours(24));
+    }
+
+    #[test]
+    fn test_daily_periods() {
+        let tz = Tz::Europe__Stockholm;
+        // Friday to Monday
+        let events = daily_periods(
+            dt("2020-01-03T00:00:00Z"),
+            dt("2020-01-06T12:00:00Z"),
+            NaiveTime::from_hms(9, 0, 0),
+            NaiveTime::from_hms(17, 0, 0),
+            &[Weekday::Mon, Weekday::Fri],
+            &tz,
+        )
+        .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].timestamp, dt("2020-01-03T08:00:00Z"));
+        assert_eq!(events[0].duration, Duration::hours(8));
+        assert_eq!(events[0].data["date"], json!("2020-01-03"));
+        // Cut at the end of the period
+        assert_eq!(events[1].timestamp, dt("2020-01-06T08:00:00Z"));
+        assert_eq!(events[1].duration, Duration::hours(4));
+
+        // Periods over midnight continue on the next day
+        let events = daily_periods(
+            dt("2020-01-03T00:00:00Z"),
+            dt("2020-01-04T00:00:00Z"),
+            NaiveTime::from_hms(22, 0, 0),
+            NaiveTime::from_hms(2, 0, 0),
+            &[Weekday::Thu],
+            &Tz::UTC,
+        )
+        .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].timestamp, dt("2020-01-03T00:00:00Z"));
+        assert_eq!(events[0].duration, Duration::hours(2));
+        assert_eq!(events[0].data["date"], json!("2020-01-02"));
+    }
+
+    #[test]
+    fn test_split_events_by_day() {
+        let tz = Tz::America__New_York;
+        // An AFK event from 22:00 to 09:00 local time in New York
+        let e1 = Event {
+            id: Some(1),
+            timestamp: dt("2020-01-02T03:00:00Z"),
+            duration: Duration::hours(11),
+            data: json_map! {"status": json!("afk")},
+        };
+        let mut e2 = e1.clone();
+        e2.id = Some(2);
+        e2.timestamp = dt("2020-01-02T15:00:00Z");
+        e2.duration = Duration::hours(1);
+        e2.data = json_map! {"status": json!("not-afk")};
+
+        let events =
+            split_events_by_day(vec![e2.clone(), e1.clone()], &tz, Duration::zero()).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], e2);
+        assert_eq!(events[1].timestamp, e1.timestamp);
+        assert_eq!(events[1].duration, Duration::hours(2));
+        assert_eq!(events[2].timestamp, dt("2020-01-02T05:00:00Z"));
+        assert_eq!(events[2].duration, Duration::hours(9));
+        assert_eq!(events[2].id, e1.id);
+        assert_eq!(events[2].data, e1.data);
+
+        // Days which start at 04:00 split the event there instead
+        let events = split_events_by_day(vec![e1], &tz, Duration::hours(4)).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].duration, Duration::hours(6));
+        assert_eq!(events[1].timestamp, dt("2020-01-02T09:00:00Z"));
+        assert_eq!(events[1].duration, Duration::hours(5));
+    }
+}